@@ -0,0 +1,128 @@
+//! Description of the functions of a contract that can be called from
+//! outside, so that off-chain callers can marshal their arguments and results
+//! without parsing the contract.
+
+use clarity::vm::analysis::ContractAnalysis;
+use clarity::vm::types::{FunctionType, TypeSignature};
+use clarity::vm::ClarityName;
+
+/// The functions that can be called from outside a contract.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AbiDescriptor {
+    /// The public and read-only functions, sorted by name.
+    pub functions: Vec<FunctionAbi>,
+}
+
+/// Signature of a function that can be called from outside a contract.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionAbi {
+    pub name: String,
+    /// Names and types of the arguments, in order.
+    pub args: Vec<(String, TypeSignature)>,
+    pub returns: TypeSignature,
+    /// `true` for a `define-read-only` function, `false` for a
+    /// `define-public` one.
+    pub read_only: bool,
+}
+
+/// Build the ABI of the public and read-only functions of a type-checked
+/// contract.
+pub fn abi_descriptor(analysis: &ContractAnalysis) -> AbiDescriptor {
+    let public = analysis
+        .public_function_types
+        .iter()
+        .map(|function| (function, false));
+    let read_only = analysis
+        .read_only_function_types
+        .iter()
+        .map(|function| (function, true));
+
+    let mut functions: Vec<_> = public
+        .chain(read_only)
+        .filter_map(|((name, function_type), read_only)| {
+            function_abi(name, function_type, read_only)
+        })
+        .collect();
+    functions.sort_by(|a, b| a.name.cmp(&b.name));
+
+    AbiDescriptor { functions }
+}
+
+/// User-defined functions always have a fixed type, other types are skipped.
+fn function_abi(
+    name: &ClarityName,
+    function_type: &FunctionType,
+    read_only: bool,
+) -> Option<FunctionAbi> {
+    let FunctionType::Fixed(fixed) = function_type else {
+        return None;
+    };
+    Some(FunctionAbi {
+        name: name.to_string(),
+        args: fixed
+            .args
+            .iter()
+            .map(|arg| (arg.name.to_string(), arg.signature.clone()))
+            .collect(),
+        returns: fixed.returns.clone(),
+        read_only,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use clarity::vm::types::signatures::BufferLength;
+    use clarity::vm::types::{SequenceSubtype, TypeSignature};
+
+    use super::{abi_descriptor, FunctionAbi};
+    use crate::tools::compile_snippet;
+
+    #[test]
+    fn two_function_contract() {
+        let analysis = compile_snippet(
+            "
+(define-private (helper (x int)) x)
+(define-public (transfer (amount uint) (memo (buff 4)))
+  (if (> amount u0) (ok amount) (err u1)))
+(define-read-only (get-flag (owner principal))
+  (is-eq owner tx-sender))
+",
+        )
+        .contract_analysis;
+
+        let buff_4 = TypeSignature::SequenceType(SequenceSubtype::BufferType(
+            BufferLength::try_from(4u32).unwrap(),
+        ));
+        assert_eq!(
+            abi_descriptor(&analysis).functions,
+            vec![
+                FunctionAbi {
+                    name: "get-flag".to_owned(),
+                    args: vec![("owner".to_owned(), TypeSignature::PrincipalType)],
+                    returns: TypeSignature::BoolType,
+                    read_only: true,
+                },
+                FunctionAbi {
+                    name: "transfer".to_owned(),
+                    args: vec![
+                        ("amount".to_owned(), TypeSignature::UIntType),
+                        ("memo".to_owned(), buff_4),
+                    ],
+                    returns: TypeSignature::new_response(
+                        TypeSignature::UIntType,
+                        TypeSignature::UIntType
+                    )
+                    .unwrap(),
+                    read_only: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn compile_result_includes_abi() {
+        let result = compile_snippet("(define-read-only (one) 1)");
+        assert_eq!(result.abi, abi_descriptor(&result.contract_analysis));
+        assert_eq!(result.abi.functions.len(), 1);
+    }
+}
@@ -9,7 +9,7 @@ use walrus::ir::{
 use walrus::{InstrSeqBuilder, LocalId, MemoryId, ValType};
 
 use crate::wasm_generator::{
-    add_placeholder_for_clarity_type, clar2wasm_ty, GeneratorError, WasmGenerator,
+    add_placeholder_for_clarity_type, clar2wasm_ty, CostOp, GeneratorError, WasmGenerator,
 };
 
 impl WasmGenerator {
@@ -201,6 +201,7 @@ impl WasmGenerator {
                         .global_set(self.stack_pointer);
 
                     // Copy the principal to the destination
+                    self.charge_cost(then, CostOp::MemoryCopy, PRINCIPAL_BYTES as i64);
                     then.local_get(result_offset)
                         .local_get(offset_local)
                         .i32_const(1)
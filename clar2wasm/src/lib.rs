@@ -1,5 +1,6 @@
 extern crate lazy_static;
 
+pub use abi::{abi_descriptor, AbiDescriptor, FunctionAbi};
 use clarity::types::StacksEpochId;
 use clarity::vm::analysis::{run_analysis, AnalysisDatabase, ContractAnalysis};
 use clarity::vm::ast::{build_ast_with_diagnostics, ContractAST};
@@ -7,11 +8,17 @@ use clarity::vm::costs::{ExecutionCost, LimitedCostTracker};
 use clarity::vm::diagnostic::Diagnostic;
 use clarity::vm::types::QualifiedContractIdentifier;
 use clarity::vm::ClarityVersion;
+pub use unsupported::{
+    scan_unsupported, supported_natives, supported_types, TypeSupport, Unsupported,
+};
 pub use walrus::Module;
+pub use wasm_generator::WasmGeneratorConfig;
 use wasm_generator::{GeneratorError, WasmGenerator};
 
+mod abi;
 mod deserialize;
 mod serialize;
+mod unsupported;
 pub mod wasm_generator;
 mod words;
 
@@ -20,6 +27,9 @@ pub mod datastore;
 #[cfg(feature = "developer-mode")]
 pub mod tools;
 
+/// Version of this build of clar2wasm.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
 // FIXME: This is copied from stacks-blockchain
 // Block limit in Stacks 2.1
 pub const BLOCK_LIMIT_MAINNET_21: ExecutionCost = ExecutionCost {
@@ -36,6 +46,18 @@ pub struct CompileResult {
     pub diagnostics: Vec<Diagnostic>,
     pub module: Module,
     pub contract_analysis: ContractAnalysis,
+    /// The functions of the contract callable from outside.
+    pub abi: AbiDescriptor,
+    /// The serialized `module`, emitted once by [`compile`]. It must be
+    /// emitted again by whoever changes `module` afterwards.
+    pub wasm_bytes: Vec<u8>,
+}
+
+impl CompileResult {
+    /// Size in bytes of the serialized Wasm module.
+    pub fn wasm_size(&self) -> usize {
+        self.wasm_bytes.len()
+    }
 }
 
 #[derive(Debug)]
@@ -48,12 +70,34 @@ pub enum CompileError {
 }
 
 pub fn compile(
+    source: &str,
+    contract_id: &QualifiedContractIdentifier,
+    cost_tracker: LimitedCostTracker,
+    clarity_version: ClarityVersion,
+    epoch: StacksEpochId,
+    analysis_db: &mut AnalysisDatabase,
+) -> Result<CompileResult, CompileError> {
+    compile_with_config(
+        source,
+        contract_id,
+        cost_tracker,
+        clarity_version,
+        epoch,
+        analysis_db,
+        WasmGeneratorConfig::default(),
+    )
+}
+
+/// Same as [`compile`], with a [`WasmGeneratorConfig`] controlling the code
+/// emitted for the contract.
+pub fn compile_with_config(
     source: &str,
     contract_id: &QualifiedContractIdentifier,
     mut cost_tracker: LimitedCostTracker,
     clarity_version: ClarityVersion,
     epoch: StacksEpochId,
     analysis_db: &mut AnalysisDatabase,
+    config: WasmGeneratorConfig,
 ) -> Result<CompileResult, CompileError> {
     // Parse the contract
     let (mut ast, mut diagnostics, success) = build_ast_with_diagnostics(
@@ -114,11 +158,13 @@ pub fn compile(
     }
 
     #[allow(clippy::expect_used)]
-    match WasmGenerator::new(contract_analysis.clone()).and_then(WasmGenerator::generate) {
-        Ok(module) => Ok(CompileResult {
+    match compile_contract_with_config(contract_analysis.clone(), config) {
+        Ok(mut module) => Ok(CompileResult {
             ast,
             diagnostics,
+            wasm_bytes: module.emit_wasm(),
             module,
+            abi: abi_descriptor(&contract_analysis),
             contract_analysis,
         }),
         Err(e) => {
@@ -138,6 +184,15 @@ pub fn compile(
 }
 
 pub fn compile_contract(contract_analysis: ContractAnalysis) -> Result<Module, GeneratorError> {
-    let generator = WasmGenerator::new(contract_analysis)?;
+    compile_contract_with_config(contract_analysis, WasmGeneratorConfig::default())
+}
+
+/// Same as [`compile_contract`], with a [`WasmGeneratorConfig`] controlling
+/// the code emitted for the contract.
+pub fn compile_contract_with_config(
+    contract_analysis: ContractAnalysis,
+    config: WasmGeneratorConfig,
+) -> Result<Module, GeneratorError> {
+    let generator = WasmGenerator::new(contract_analysis)?.with_config(config);
     generator.generate()
 }
@@ -6,7 +6,7 @@ use clarity::vm::types::{
 use walrus::ir::{BinaryOp, IfElse, InstrSeqType, Loop, MemArg, StoreKind};
 use walrus::{InstrSeqBuilder, LocalId, MemoryId, ValType};
 
-use crate::wasm_generator::{clar2wasm_ty, GeneratorError, WasmGenerator};
+use crate::wasm_generator::{clar2wasm_ty, CostOp, GeneratorError, WasmGenerator};
 
 impl WasmGenerator {
     /// Serialize an integer (`int` or `uint`) to memory using consensus
@@ -133,6 +133,7 @@ impl WasmGenerator {
         // Copy the standard principal part to the buffer, offset by 1
         // byte for the type prefix, which we will write next, so that
         // we don't need two branches.
+        self.charge_cost(builder, CostOp::MemoryCopy, PRINCIPAL_BYTES as i64);
         builder
             .i32_const(1)
             .binop(BinaryOp::I32Add)
@@ -170,7 +171,15 @@ impl WasmGenerator {
                             },
                         );
 
+                    // Compute the length of the contract name
+                    let name_length = self.module.locals.add(ValType::I32);
+                    then.local_get(plength)
+                        .i32_const(STANDARD_PRINCIPAL_BYTES as i32)
+                        .binop(BinaryOp::I32Sub)
+                        .local_set(name_length);
+
                     // Copy the contract name to the buffer
+                    self.charge_cost_bytes(then, CostOp::MemoryCopy, name_length);
                     then
                         // Compute the destination offset
                         .local_get(write_ptr)
@@ -180,10 +189,7 @@ impl WasmGenerator {
                         .local_get(poffset)
                         .i32_const(STANDARD_PRINCIPAL_BYTES as i32)
                         .binop(BinaryOp::I32Add)
-                        // Compute the length
-                        .local_get(plength)
-                        .i32_const(STANDARD_PRINCIPAL_BYTES as i32)
-                        .binop(BinaryOp::I32Sub)
+                        .local_get(name_length)
                         // Copy the data
                         .memory_copy(memory, memory);
 
@@ -620,6 +626,7 @@ impl WasmGenerator {
             .local_tee(write_ptr);
 
         // Copy the buffer
+        self.charge_cost_bytes(builder, CostOp::MemoryCopy, length);
         builder
             .local_get(read_ptr)
             .local_get(length)
@@ -687,6 +694,7 @@ impl WasmGenerator {
             .local_tee(write_ptr);
 
         // Copy the string
+        self.charge_cost_bytes(builder, CostOp::MemoryCopy, length);
         builder
             .local_get(read_ptr)
             .local_get(length)
@@ -847,6 +855,7 @@ impl WasmGenerator {
 
             // Serialize the key name
             let (offset, length) = self.add_string_literal(key)?;
+            self.charge_cost(builder, CostOp::MemoryCopy, length.into());
             builder
                 .i32_const(offset as i32)
                 .i32_const(length as i32)
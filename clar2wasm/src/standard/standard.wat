@@ -270,7 +270,7 @@
     (table 5 funcref) ;; table for hash160 compress function
     (elem (i32.const 0) $hash160-f1 $hash160-f2 $hash160-f3 $hash160-f4 $hash160-f5)
 
-    ;; The error code is one of:
+    ;; The error code is one of (see `RuntimeError` in the generator):
         ;; 0: overflow
         ;; 1: underflow
         ;; 2: divide by zero
@@ -855,7 +855,10 @@
         )
     )
 
-    (func $stdlib.lt-buff (param $offset_a i32) (param $length_a i32) (param $offset_b i32) (param $length_b i32) (result i32)
+    ;; Three-way comparison of two buffers, byte by byte up to the length of the
+    ;; shorter one, then by length. Returns -1 if a < b, 0 if a == b and 1 if a > b.
+    ;; `string-ascii` and `string-utf8` values are compared with the same function.
+    (func $stdlib.cmp-buff (param $offset_a i32) (param $length_a i32) (param $offset_b i32) (param $length_b i32) (result i32)
         (local $i i32) (local $sub i32)
         ;; pseudo-code:
         ;; let i = min(length_a, length_b)
@@ -864,7 +867,7 @@
         ;;     offset_a += 1; offset_b += 1; i -= 1;
         ;;   } else { break }
         ;; }
-        ;; return (sub != 0) ? (sub < 0) : (length_a < length_b)
+        ;; return (sub != 0) ? sign(sub) : sign(length_a - length_b)
         (block $done
             ;; we can skip the comparison loop if $i (min length) is 0
             (br_if $done
@@ -897,122 +900,45 @@
         )
         ;; if sub is 0, it means that for the min length of both buffers, both are equals
         ;;   - in this case, the result is the comparison of the lengths
-        ;;   - otherwise $sub < 0
-        (select
-            (i32.lt_s (local.get $sub) (i32.const 0))
-            (i32.lt_u (local.get $length_a) (local.get $length_b))
-            (local.get $sub)
-        )
-    )
-
-    (func $stdlib.gt-buff (param $offset_a i32) (param $length_a i32) (param $offset_b i32) (param $length_b i32) (result i32)
-        (local $i i32) (local $sub i32)
-        ;; same algorithm as $stdlib.lt-buff
-        (block $done
-            (br_if $done
-                (i32.eqz
-                    (local.tee $i
-                        (select
-                            (local.get $length_a)
-                            (local.get $length_b)
-                            (i32.lt_u (local.get $length_a) (local.get $length_b))
-                        )
-                    )
-                )
+        ;;   - otherwise it is the sign of $sub
+        (if (result i32) (local.get $sub)
+            (then
+                (select (i32.const -1) (i32.const 1) (i32.lt_s (local.get $sub) (i32.const 0)))
             )
-            (loop $loop
-                (if
-                    (i32.eqz
-                        (local.tee $sub
-                            (i32.sub (i32.load8_u (local.get $offset_a)) (i32.load8_u (local.get $offset_b)))
-                        )
-                    )
-                    (then
-                        (local.set $offset_a (i32.add (local.get $offset_a) (i32.const 1)))
-                        (local.set $offset_b (i32.add (local.get $offset_b) (i32.const 1)))
-                        (br_if $loop (local.tee $i (i32.sub (local.get $i) (i32.const 1))))
-                    )
+            (else
+                (i32.sub
+                    (i32.gt_u (local.get $length_a) (local.get $length_b))
+                    (i32.lt_u (local.get $length_a) (local.get $length_b))
                 )
             )
         )
-        (select
-            (i32.gt_s (local.get $sub) (i32.const 0))
-            (i32.gt_u (local.get $length_a) (local.get $length_b))
-            (local.get $sub)
+    )
+
+    (func $stdlib.lt-buff (param $offset_a i32) (param $length_a i32) (param $offset_b i32) (param $length_b i32) (result i32)
+        (i32.lt_s
+            (call $stdlib.cmp-buff (local.get $offset_a) (local.get $length_a) (local.get $offset_b) (local.get $length_b))
+            (i32.const 0)
         )
     )
 
-    (func $stdlib.le-buff (param $offset_a i32) (param $length_a i32) (param $offset_b i32) (param $length_b i32) (result i32)
-        (local $i i32) (local $sub i32)
-        ;; same algorithm as $stdlib.lt-buff
-        (block $done
-            (br_if $done
-                (i32.eqz
-                    (local.tee $i
-                        (select
-                            (local.get $length_a)
-                            (local.get $length_b)
-                            (i32.lt_u (local.get $length_a) (local.get $length_b))
-                        )
-                    )
-                )
-            )
-            (loop $loop
-                (if
-                    (i32.eqz
-                        (local.tee $sub
-                            (i32.sub (i32.load8_u (local.get $offset_a)) (i32.load8_u (local.get $offset_b)))
-                        )
-                    )
-                    (then
-                        (local.set $offset_a (i32.add (local.get $offset_a) (i32.const 1)))
-                        (local.set $offset_b (i32.add (local.get $offset_b) (i32.const 1)))
-                        (br_if $loop (local.tee $i (i32.sub (local.get $i) (i32.const 1))))
-                    )
-                )
-            )
+    (func $stdlib.gt-buff (param $offset_a i32) (param $length_a i32) (param $offset_b i32) (param $length_b i32) (result i32)
+        (i32.gt_s
+            (call $stdlib.cmp-buff (local.get $offset_a) (local.get $length_a) (local.get $offset_b) (local.get $length_b))
+            (i32.const 0)
         )
-        (select
-            (i32.le_s (local.get $sub) (i32.const 0))
-            (i32.le_u (local.get $length_a) (local.get $length_b))
-            (local.get $sub)
+    )
+
+    (func $stdlib.le-buff (param $offset_a i32) (param $length_a i32) (param $offset_b i32) (param $length_b i32) (result i32)
+        (i32.le_s
+            (call $stdlib.cmp-buff (local.get $offset_a) (local.get $length_a) (local.get $offset_b) (local.get $length_b))
+            (i32.const 0)
         )
     )
 
     (func $stdlib.ge-buff (param $offset_a i32) (param $length_a i32) (param $offset_b i32) (param $length_b i32) (result i32)
-        (local $i i32) (local $sub i32)
-        ;; same algorithm as $stdlib.lt-buff
-        (block $done
-            (br_if $done
-                (i32.eqz
-                    (local.tee $i
-                        (select
-                            (local.get $length_a)
-                            (local.get $length_b)
-                            (i32.lt_u (local.get $length_a) (local.get $length_b))
-                        )
-                    )
-                )
-            )
-            (loop $loop
-                (if
-                    (i32.eqz
-                        (local.tee $sub
-                            (i32.sub (i32.load8_u (local.get $offset_a)) (i32.load8_u (local.get $offset_b)))
-                        )
-                    )
-                    (then
-                        (local.set $offset_a (i32.add (local.get $offset_a) (i32.const 1)))
-                        (local.set $offset_b (i32.add (local.get $offset_b) (i32.const 1)))
-                        (br_if $loop (local.tee $i (i32.sub (local.get $i) (i32.const 1))))
-                    )
-                )
-            )
-        )
-        (select
-            (i32.ge_s (local.get $sub) (i32.const 0))
-            (i32.ge_u (local.get $length_a) (local.get $length_b))
-            (local.get $sub)
+        (i32.ge_s
+            (call $stdlib.cmp-buff (local.get $offset_a) (local.get $length_a) (local.get $offset_b) (local.get $length_b))
+            (i32.const 0)
         )
     )
 
@@ -3492,6 +3418,95 @@
         (i32.const 1)
     )
 
+    ;;
+    ;; Checks the helpers the generator relies on against known results.
+    ;; Returns 0 if they all pass, or the number of the first failing check.
+    ;; The scratch memory is above the stack pointer, which is not moved.
+    ;; The generator removes it from the contracts.
+    ;;
+    (func $stdlib.self-test (result i32)
+        (local $scratch i32)
+        (local.set $scratch (global.get $stack-pointer))
+
+        ;; 1: u1 + u2 = u3
+        (if (i32.eqz (call $stdlib.is-eq-int
+                (call $stdlib.add-uint (i64.const 1) (i64.const 0) (i64.const 2) (i64.const 0))
+                (i64.const 3) (i64.const 0)))
+            (then (return (i32.const 1))))
+        ;; 2: -1 + 1 = 0
+        (if (i32.eqz (call $stdlib.is-eq-int
+                (call $stdlib.add-int (i64.const -1) (i64.const -1) (i64.const 1) (i64.const 0))
+                (i64.const 0) (i64.const 0)))
+            (then (return (i32.const 2))))
+        ;; 3: u5 - u3 = u2
+        (if (i32.eqz (call $stdlib.is-eq-int
+                (call $stdlib.sub-uint (i64.const 5) (i64.const 0) (i64.const 3) (i64.const 0))
+                (i64.const 2) (i64.const 0)))
+            (then (return (i32.const 3))))
+        ;; 4: 0 - 1 = -1
+        (if (i32.eqz (call $stdlib.is-eq-int
+                (call $stdlib.sub-int (i64.const 0) (i64.const 0) (i64.const 1) (i64.const 0))
+                (i64.const -1) (i64.const -1)))
+            (then (return (i32.const 4))))
+        ;; 5: 2^32 * 2^32 = 2^64, carried to the high word
+        (if (i32.eqz (call $stdlib.is-eq-int
+                (call $stdlib.mul-uint (i64.const 4294967296) (i64.const 0) (i64.const 4294967296) (i64.const 0))
+                (i64.const 0) (i64.const 1)))
+            (then (return (i32.const 5))))
+        ;; 6: -3 * 4 = -12
+        (if (i32.eqz (call $stdlib.is-eq-int
+                (call $stdlib.mul-int (i64.const -3) (i64.const -1) (i64.const 4) (i64.const 0))
+                (i64.const -12) (i64.const -1)))
+            (then (return (i32.const 6))))
+        ;; 7: u7 / u2 = u3
+        (if (i32.eqz (call $stdlib.is-eq-int
+                (call $stdlib.div-uint (i64.const 7) (i64.const 0) (i64.const 2) (i64.const 0))
+                (i64.const 3) (i64.const 0)))
+            (then (return (i32.const 7))))
+        ;; 8: -7 / 2 = -3, rounded toward zero
+        (if (i32.eqz (call $stdlib.is-eq-int
+                (call $stdlib.div-int (i64.const -7) (i64.const -1) (i64.const 2) (i64.const 0))
+                (i64.const -3) (i64.const -1)))
+            (then (return (i32.const 8))))
+        ;; 9: u7 mod u2 = u1
+        (if (i32.eqz (call $stdlib.is-eq-int
+                (call $stdlib.mod-uint (i64.const 7) (i64.const 0) (i64.const 2) (i64.const 0))
+                (i64.const 1) (i64.const 0)))
+            (then (return (i32.const 9))))
+        ;; 10: -7 mod 2 = -1, with the sign of the dividend
+        (if (i32.eqz (call $stdlib.is-eq-int
+                (call $stdlib.mod-int (i64.const -7) (i64.const -1) (i64.const 2) (i64.const 0))
+                (i64.const -1) (i64.const -1)))
+            (then (return (i32.const 10))))
+
+        ;; 11: equal buffers are equal
+        (i64.store (local.get $scratch) (i64.const 0x0807060504030201))
+        (i64.store offset=8 (local.get $scratch) (i64.const 0x0807060504030201))
+        (if (i32.eqz (call $stdlib.is-eq-bytes
+                (local.get $scratch) (i32.const 8)
+                (i32.add (local.get $scratch) (i32.const 8)) (i32.const 8)))
+            (then (return (i32.const 11))))
+        ;; 12: buffers of different lengths are not equal
+        (if (call $stdlib.is-eq-bytes
+                (local.get $scratch) (i32.const 8)
+                (i32.add (local.get $scratch) (i32.const 8)) (i32.const 7))
+            (then (return (i32.const 12))))
+        ;; 13-14: buffers differing only in their last byte are not equal, and
+        ;; are ordered by that byte
+        (i32.store8 (i32.add (local.get $scratch) (i32.const 15)) (i32.const 0xff))
+        (if (call $stdlib.is-eq-bytes
+                (local.get $scratch) (i32.const 8)
+                (i32.add (local.get $scratch) (i32.const 8)) (i32.const 8))
+            (then (return (i32.const 13))))
+        (if (i32.eqz (call $stdlib.lt-buff
+                (local.get $scratch) (i32.const 8)
+                (i32.add (local.get $scratch) (i32.const 8)) (i32.const 8)))
+            (then (return (i32.const 14))))
+
+        (i32.const 0)
+    )
+
+    (export ".self-test" (func $stdlib.self-test))
     (export "stdlib.add-uint" (func $stdlib.add-uint))
     (export "stdlib.add-int" (func $stdlib.add-int))
     (export "stdlib.sub-uint" (func $stdlib.sub-uint))
@@ -3510,6 +3525,7 @@
     (export "stdlib.gt-int" (func $stdlib.gt-int))
     (export "stdlib.le-int" (func $stdlib.le-int))
     (export "stdlib.ge-int" (func $stdlib.ge-int))
+    (export "stdlib.cmp-buff" (func $stdlib.cmp-buff))
     (export "stdlib.lt-buff" (func $stdlib.lt-buff))
     (export "stdlib.gt-buff" (func $stdlib.gt-buff))
     (export "stdlib.le-buff" (func $stdlib.le-buff))
@@ -8,19 +8,24 @@ use std::collections::HashMap;
 
 use clarity::consts::CHAIN_ID_TESTNET;
 use clarity::types::StacksEpochId;
-use clarity::vm::analysis::run_analysis;
+use clarity::vm::analysis::{run_analysis, ContractAnalysis};
 use clarity::vm::ast::build_ast;
 use clarity::vm::clarity_wasm::initialize_contract;
 use clarity::vm::contexts::GlobalContext;
 use clarity::vm::contracts::Contract;
 use clarity::vm::costs::LimitedCostTracker;
-use clarity::vm::database::ClarityDatabase;
+use clarity::vm::database::{ClarityDatabase, MemoryBackingStore};
 use clarity::vm::errors::{Error, WasmError};
+use clarity::vm::events::StacksTransactionEvent;
 use clarity::vm::types::{PrincipalData, QualifiedContractIdentifier, StandardPrincipalData};
-use clarity::vm::{eval_all, ClarityVersion, ContractContext, Value};
+use clarity::vm::{
+    eval_all, ClarityVersion, ContractContext, SymbolicExpression, SymbolicExpressionType, Value,
+};
+use walrus::Module;
 
-use crate::compile;
 use crate::datastore::{BurnDatastore, Datastore, StacksConstants};
+use crate::wasm_generator::GeneratorError;
+use crate::{compile, compile_contract, compile_with_config, CompileResult, WasmGeneratorConfig};
 
 #[derive(Clone)]
 pub struct TestEnvironment {
@@ -30,6 +35,7 @@ pub struct TestEnvironment {
     datastore: Datastore,
     burn_datastore: BurnDatastore,
     cost_tracker: LimitedCostTracker,
+    events: Vec<StacksTransactionEvent>,
 }
 
 impl TestEnvironment {
@@ -63,6 +69,7 @@ impl TestEnvironment {
             datastore,
             burn_datastore,
             cost_tracker,
+            events: Vec::new(),
         }
     }
 
@@ -70,6 +77,36 @@ impl TestEnvironment {
         &mut self,
         contract_name: &str,
         snippet: &str,
+    ) -> Result<Option<Value>, Error> {
+        self.init_contract(contract_name, snippet, |_| Ok(()))
+    }
+
+    /// Same as [`Self::init_contract_with_snippet`], but `mutate` can change
+    /// the analysis of the contract, like its expressions, before the module
+    /// is generated. This allows running constructs that the Clarity
+    /// front-end does not produce.
+    pub fn init_contract_with_mutated_snippet(
+        &mut self,
+        contract_name: &str,
+        snippet: &str,
+        mutate: impl FnOnce(&mut ContractAnalysis),
+    ) -> Result<Option<Value>, Error> {
+        self.init_contract(contract_name, snippet, |compile_result| {
+            mutate(&mut compile_result.contract_analysis);
+            compile_result.module = compile_contract(compile_result.contract_analysis.clone())
+                .map_err(|e| Error::Wasm(WasmError::WasmGeneratorError(format!("{:?}", e))))?;
+            compile_result.wasm_bytes = compile_result.module.emit_wasm();
+            Ok(())
+        })
+    }
+
+    /// Compile `snippet`, let `prepare` update the result, and initialize the
+    /// contract.
+    fn init_contract(
+        &mut self,
+        contract_name: &str,
+        snippet: &str,
+        prepare: impl FnOnce(&mut CompileResult) -> Result<(), Error>,
     ) -> Result<Option<Value>, Error> {
         let contract_id = QualifiedContractIdentifier::new(
             StandardPrincipalData::transient(),
@@ -90,6 +127,7 @@ impl TestEnvironment {
                 )
             })
             .map_err(|e| Error::Wasm(WasmError::WasmGeneratorError(format!("{:?}", e))))?;
+        prepare(&mut compile_result)?;
 
         self.datastore
             .as_analysis_db()
@@ -100,7 +138,7 @@ impl TestEnvironment {
 
         let mut contract_context = ContractContext::new(contract_id.clone(), self.version);
         // compile_result.module.emit_wasm_file("test.wasm").unwrap();
-        contract_context.set_wasm_module(compile_result.module.emit_wasm());
+        contract_context.set_wasm_module(compile_result.wasm_bytes);
 
         let mut cost_tracker = LimitedCostTracker::new_free();
         std::mem::swap(&mut self.cost_tracker, &mut cost_tracker);
@@ -136,7 +174,9 @@ impl TestEnvironment {
             .set_contract_data_size(&contract_id, data_size)
             .expect("Failed to set contract data size.");
 
-        global_context.commit().unwrap();
+        let (_, event_batch) = global_context.commit().unwrap();
+        self.events
+            .extend(event_batch.into_iter().flat_map(|batch| batch.events));
         self.cost_tracker = global_context.cost_track;
 
         self.contract_contexts
@@ -149,6 +189,12 @@ impl TestEnvironment {
         self.init_contract_with_snippet("snippet", snippet)
     }
 
+    /// Events emitted by the contracts initialized in this environment, in
+    /// order.
+    pub fn get_events(&self) -> &[StacksTransactionEvent] {
+        &self.events
+    }
+
     pub fn get_contract_context(&self, contract_name: &str) -> Option<&ContractContext> {
         self.contract_contexts.get(contract_name)
     }
@@ -264,6 +310,16 @@ pub fn evaluate(snippet: &str) -> Result<Option<Value>, ()> {
     evaluate_at(snippet, StacksEpochId::latest(), ClarityVersion::latest()).map_err(|_| ())
 }
 
+/// Evaluate a Clarity snippet at the latest epoch and clarity version, after
+/// `mutate` changed its analysis. See
+/// [`TestEnvironment::init_contract_with_mutated_snippet`].
+pub fn evaluate_mutated(
+    snippet: &str,
+    mutate: impl FnOnce(&mut ContractAnalysis),
+) -> Result<Option<Value>, Error> {
+    TestEnvironment::default().init_contract_with_mutated_snippet("snippet", snippet, mutate)
+}
+
 /// Interpret a Clarity snippet at a specific epoch and version.
 /// Returns an optional value -- the result of the evaluation.
 pub fn interpret_at(
@@ -282,6 +338,174 @@ pub fn interpret(snippet: &str) -> Result<Option<Value>, ()> {
     interpret_at(snippet, StacksEpochId::latest(), ClarityVersion::latest()).map_err(|_| ())
 }
 
+/// Compile a Clarity snippet at the latest epoch and clarity version, without
+/// running it. Panics if the compilation fails.
+pub fn compile_snippet(snippet: &str) -> CompileResult {
+    compile_snippet_with_config(snippet, WasmGeneratorConfig::default())
+}
+
+/// Same as [`compile_snippet`], with a [`WasmGeneratorConfig`] controlling the
+/// generated code.
+pub fn compile_snippet_with_config(snippet: &str, config: WasmGeneratorConfig) -> CompileResult {
+    let mut datastore = MemoryBackingStore::new();
+    compile_with_config(
+        snippet,
+        &QualifiedContractIdentifier::transient(),
+        LimitedCostTracker::new_free(),
+        ClarityVersion::latest(),
+        StacksEpochId::latest(),
+        &mut datastore.as_analysis_db(),
+        config,
+    )
+    .expect("Failed to compile snippet")
+}
+
+/// Name of the read-only function wrapping the expression compiled by
+/// [`compile_expr`].
+pub const EXPR_FUNCTION: &str = "expr";
+
+/// Compile a single Clarity expression, wrapped in a read-only function named
+/// [`EXPR_FUNCTION`], so that the code generated for it can be inspected
+/// without writing a contract. Panics if the compilation fails.
+pub fn compile_expr(expr: &str) -> Module {
+    compile_snippet(&format!("(define-read-only ({EXPR_FUNCTION}) {expr})")).module
+}
+
+/// Compile a Clarity snippet at the latest epoch and clarity version, let
+/// `mutate` change its analysis, and generate a module from the mutated
+/// analysis. This allows checking how the generator handles constructs that
+/// the Clarity front-end rejects or never produces. Panics if the snippet
+/// does not compile.
+pub fn generate_mutated(
+    snippet: &str,
+    mutate: impl FnOnce(&mut ContractAnalysis),
+) -> Result<Module, GeneratorError> {
+    let mut analysis = compile_snippet(snippet).contract_analysis;
+    mutate(&mut analysis);
+    compile_contract(analysis)
+}
+
+/// Same as [`generate_mutated`], for a mutation that the generator must
+/// reject. Returns the error, and panics if the generation succeeds.
+pub fn generation_error(
+    snippet: &str,
+    mutate: impl FnOnce(&mut ContractAnalysis),
+) -> GeneratorError {
+    match generate_mutated(snippet, mutate) {
+        Ok(_) => panic!("the generation of the mutated snippet should fail"),
+        Err(err) => err,
+    }
+}
+
+/// Call `f` on each expression of `exprs` and on its sub-expressions, in
+/// pre-order. The sub-expressions of an expression are those it has after
+/// `f` changed it.
+pub fn for_each_expr_mut(
+    exprs: &mut [SymbolicExpression],
+    f: &mut impl FnMut(&mut SymbolicExpression),
+) {
+    for expr in exprs {
+        f(expr);
+        if let SymbolicExpressionType::List(list) = &mut expr.expr {
+            for_each_expr_mut(list, f);
+        }
+    }
+}
+
+/// Instantiate `module` with wasmtime. `link` can define host functions on
+/// the linker, the other imports of the module trap when called.
+#[cfg(test)]
+pub fn instantiate<T>(
+    module: &mut Module,
+    data: T,
+    link: impl FnOnce(&mut wasmtime::Linker<T>),
+) -> (wasmtime::Store<T>, wasmtime::Instance) {
+    let engine = wasmtime::Engine::default();
+    let module = wasmtime::Module::new(&engine, module.emit_wasm()).unwrap();
+    let mut store = wasmtime::Store::new(&engine, data);
+    let mut linker = wasmtime::Linker::new(&engine);
+    link(&mut linker);
+    linker.define_unknown_imports_as_traps(&module).unwrap();
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    (store, instance)
+}
+
+/// Instantiate `module` as [`instantiate`] does and call its `.top-level`
+/// function. Returns the results of the call and the data of the store.
+#[cfg(test)]
+pub fn run_top_level<T>(
+    module: &mut Module,
+    data: T,
+    link: impl FnOnce(&mut wasmtime::Linker<T>),
+) -> (Vec<wasmtime::Val>, T) {
+    let (mut store, instance) = instantiate(module, data, link);
+    let results = call_top_level(&mut store, instance).unwrap();
+    (results, store.into_data())
+}
+
+/// Instantiate `module` as [`instantiate`] does and call its `.top-level`
+/// function. Returns the runtime error raised by the call, or `None` if it
+/// returns. The calls to `stdlib.runtime-error`, which only traps, are
+/// redirected to the host to record the error code before trapping.
+#[cfg(test)]
+pub fn top_level_runtime_error(
+    module: &mut Module,
+    link: impl FnOnce(&mut wasmtime::Linker<Option<i32>>),
+) -> Option<crate::wasm_generator::RuntimeError> {
+    let runtime_error = module.funcs.by_name("stdlib.runtime-error").unwrap();
+    let ty = module.types.add(&[walrus::ValType::I32], &[]);
+    let (record, _) = module.add_import_func("test", "runtime_error", ty);
+    let walrus::FunctionKind::Local(func) = &mut module.funcs.get_mut(runtime_error).kind else {
+        panic!("stdlib.runtime-error should be a local function");
+    };
+    let code = func.args[0];
+    let mut body = func.builder_mut().func_body();
+    body.instrs_mut().clear();
+    body.local_get(code).call(record).unreachable();
+
+    let (mut store, instance) = instantiate(module, None, |linker| {
+        linker
+            .func_wrap(
+                "test",
+                "runtime_error",
+                |mut caller: wasmtime::Caller<'_, Option<i32>>, code: i32| {
+                    *caller.data_mut() = Some(code);
+                },
+            )
+            .unwrap();
+        link(linker);
+    });
+    match call_top_level(&mut store, instance) {
+        Ok(_) => None,
+        Err(trap) => {
+            let code = store
+                .data()
+                .unwrap_or_else(|| panic!("trapped without a runtime error: {trap}"));
+            let error = crate::wasm_generator::RuntimeError::from_code(code);
+            Some(error.expect("unknown runtime error code"))
+        }
+    }
+}
+
+/// Call the `.top-level` function of `instance`, returning its results.
+#[cfg(test)]
+fn call_top_level<T>(
+    store: &mut wasmtime::Store<T>,
+    instance: wasmtime::Instance,
+) -> wasmtime::Result<Vec<wasmtime::Val>> {
+    let top_level = instance.get_func(&mut *store, ".top-level").unwrap();
+    let mut results: Vec<_> = top_level
+        .ty(&*store)
+        .results()
+        .map(|ty| match ty {
+            wasmtime::ValType::I64 => wasmtime::Val::I64(0),
+            _ => wasmtime::Val::I32(0),
+        })
+        .collect();
+    top_level.call(&mut *store, &[], &mut results)?;
+    Ok(results)
+}
+
 pub fn crosscheck(snippet: &str, expected: Result<Option<Value>, ()>) {
     let compiled = evaluate_at(snippet, StacksEpochId::latest(), ClarityVersion::latest());
     let interpreted = interpret(snippet);
@@ -358,3 +582,44 @@ pub fn crosscheck_validate<V: Fn(Value)>(snippet: &str, validator: V) {
 fn test_evaluate_snippet() {
     assert_eq!(evaluate("(+ 1 2)"), Ok(Some(Value::Int(3))));
 }
+
+#[test]
+fn test_compile_expr() {
+    use walrus::ir::{dfs_in_order, Call, Const, Value as WasmValue, Visitor};
+    use walrus::{ExportItem, FunctionId, FunctionKind};
+
+    #[derive(Default)]
+    struct Collector {
+        calls: Vec<FunctionId>,
+        consts: Vec<i64>,
+    }
+    impl<'instr> Visitor<'instr> for Collector {
+        fn visit_call(&mut self, instr: &Call) {
+            self.calls.push(instr.func);
+        }
+        fn visit_const(&mut self, instr: &Const) {
+            if let WasmValue::I64(value) = instr.value {
+                self.consts.push(value);
+            }
+        }
+    }
+
+    let module = compile_expr("(+ u1 u2)");
+    let func = module
+        .exports
+        .iter()
+        .find_map(|export| match export.item {
+            ExportItem::Function(func) if export.name == EXPR_FUNCTION => Some(func),
+            _ => None,
+        })
+        .expect("expression function not exported");
+    let FunctionKind::Local(func) = &module.funcs.get(func).kind else {
+        panic!("expression function should be a local function");
+    };
+
+    let mut collector = Collector::default();
+    dfs_in_order(&mut collector, func, func.entry_block());
+    let add_uint = module.funcs.by_name("stdlib.add-uint").unwrap();
+    assert_eq!(collector.calls, vec![add_uint]);
+    assert_eq!(collector.consts, vec![1, 0, 2, 0]);
+}
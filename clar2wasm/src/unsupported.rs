@@ -0,0 +1,304 @@
+//! Detection of the Clarity constructs that the Wasm generator cannot compile
+//! yet. This allows tooling to report every unsupported construct of a
+//! contract in one pass, without generating a module.
+
+use clarity::vm::analysis::ContractAnalysis;
+use clarity::vm::clarity_wasm::get_type_size;
+use clarity::vm::functions::NativeFunctions;
+use clarity::vm::representations::Span;
+use clarity::vm::types::signatures::{BufferLength, BUFF_1};
+use clarity::vm::types::{SequenceSubtype, StringSubtype, TupleTypeSignature, TypeSignature};
+use clarity::vm::{ClarityName, SymbolicExpression, SymbolicExpressionType, Value};
+use lazy_static::lazy_static;
+
+use crate::wasm_generator::{clar2wasm_ty, unsupported_literal_kind};
+use crate::words;
+
+/// A construct which cannot be compiled by the Wasm generator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unsupported {
+    /// Name of the unsupported function, or kind of the unsupported literal.
+    pub name: String,
+    /// Location of the construct in the source.
+    pub span: Span,
+}
+
+/// Traverse the expressions of a type-checked contract and report, in source
+/// order, every construct that the Wasm generator does not support.
+pub fn scan_unsupported(analysis: &ContractAnalysis) -> Vec<Unsupported> {
+    let mut scanner = UnsupportedScanner {
+        analysis,
+        unsupported: Vec::new(),
+    };
+    for expr in &analysis.expressions {
+        traverse_expr(analysis, &mut scanner, expr);
+    }
+    scanner.unsupported
+}
+
+/// Visitor of the evaluated constructs of a contract, called by
+/// [`traverse_expr`] in source order.
+trait ExprVisitor {
+    /// Visit a call to the function `name`, before its arguments.
+    fn visit_call(&mut self, expr: &SymbolicExpression, name: &ClarityName);
+
+    /// Visit a literal value.
+    fn visit_literal_value(&mut self, expr: &SymbolicExpression, value: &Value);
+}
+
+/// Traverse `expr` and its sub-expressions with `visitor`.
+fn traverse_expr(
+    analysis: &ContractAnalysis,
+    visitor: &mut impl ExprVisitor,
+    expr: &SymbolicExpression,
+) {
+    match &expr.expr {
+        SymbolicExpressionType::List(exprs) => {
+            // Only typed lists are evaluated. Untyped lists are structural,
+            // like `let` bindings or function signatures, and are not calls.
+            let is_evaluated = analysis
+                .type_map
+                .as_ref()
+                .and_then(|ty| ty.get_type(expr))
+                .is_some();
+            if is_evaluated {
+                if let Some(name) = exprs.first().and_then(|e| e.match_atom()) {
+                    visitor.visit_call(expr, name);
+                }
+            }
+            for expr in exprs.iter() {
+                traverse_expr(analysis, visitor, expr);
+            }
+        }
+        SymbolicExpressionType::LiteralValue(value) => visitor.visit_literal_value(expr, value),
+        _ => {}
+    }
+}
+
+/// Collects the constructs which the generator would reject.
+struct UnsupportedScanner<'a> {
+    analysis: &'a ContractAnalysis,
+    unsupported: Vec<Unsupported>,
+}
+
+impl ExprVisitor for UnsupportedScanner<'_> {
+    fn visit_call(&mut self, expr: &SymbolicExpression, name: &ClarityName) {
+        if !is_supported_function(self.analysis, name) {
+            self.unsupported.push(Unsupported {
+                name: name.to_string(),
+                span: expr.span.clone(),
+            });
+        }
+    }
+
+    fn visit_literal_value(&mut self, expr: &SymbolicExpression, value: &Value) {
+        // Same check as the generator, which rejects these literals.
+        if let Some(kind) = unsupported_literal_kind(value) {
+            self.unsupported.push(Unsupported {
+                name: kind.to_owned(),
+                span: expr.span.clone(),
+            });
+        }
+    }
+}
+
+/// A function is supported if it is a word known by the generator, or if it
+/// is defined in the contract itself.
+fn is_supported_function(analysis: &ContractAnalysis, name: &ClarityName) -> bool {
+    has_word(name)
+        || analysis.get_public_function_type(name).is_some()
+        || analysis.get_read_only_function_type(name).is_some()
+        || analysis.get_private_function(name).is_some()
+}
+
+/// Returns true if the generator has a word for the function `name`.
+fn has_word(name: &str) -> bool {
+    words::lookup_complex(name).is_some()
+        || words::lookup_simple(name).is_some()
+        || words::lookup_variadic_simple(name).is_some()
+}
+
+/// Support of each kind of Clarity type by the Wasm generator. A supported
+/// type can be used anywhere the language allows: in bindings, arguments,
+/// return values, data vars, maps and comparisons.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypeSupport {
+    pub int: bool,
+    pub uint: bool,
+    pub bool: bool,
+    pub principal: bool,
+    pub buffer: bool,
+    pub string: bool,
+    pub list: bool,
+    pub tuple: bool,
+    pub optional: bool,
+    pub response: bool,
+}
+
+lazy_static! {
+    static ref SUPPORTED_NATIVES: Vec<NativeFunctions> = NativeFunctions::ALL
+        .iter()
+        .filter(|native| has_word(native.get_name_str()))
+        .cloned()
+        .collect();
+}
+
+/// Returns the native functions which the Wasm generator can compile.
+pub fn supported_natives() -> &'static [NativeFunctions] {
+    &SUPPORTED_NATIVES
+}
+
+/// Returns the kinds of Clarity types which the Wasm generator can compile,
+/// checked on a representative type of each kind.
+pub fn supported_types() -> TypeSupport {
+    let string = BufferLength::try_from(1u32).map(|len| {
+        TypeSignature::SequenceType(SequenceSubtype::StringType(StringSubtype::ASCII(len)))
+    });
+    let list = TypeSignature::list_of(TypeSignature::IntType, 1);
+    let tuple =
+        TupleTypeSignature::try_from(vec![(ClarityName::from("a"), TypeSignature::IntType)])
+            .map(TypeSignature::TupleType);
+    let optional = TypeSignature::new_option(TypeSignature::IntType);
+    let response = TypeSignature::new_response(TypeSignature::IntType, TypeSignature::IntType);
+    TypeSupport {
+        int: is_supported_type(&TypeSignature::IntType),
+        uint: is_supported_type(&TypeSignature::UIntType),
+        bool: is_supported_type(&TypeSignature::BoolType),
+        principal: is_supported_type(&TypeSignature::PrincipalType),
+        buffer: is_supported_type(&BUFF_1),
+        string: string.as_ref().map_or(false, is_supported_type),
+        list: list.as_ref().map_or(false, is_supported_type),
+        tuple: tuple.as_ref().map_or(false, is_supported_type),
+        optional: optional.as_ref().map_or(false, is_supported_type),
+        response: response.as_ref().map_or(false, is_supported_type),
+    }
+}
+
+/// Returns true if the generator has a representation for the type `ty`,
+/// both as Wasm values and in memory.
+fn is_supported_type(ty: &TypeSignature) -> bool {
+    !clar2wasm_ty(ty).is_empty() && get_type_size(ty) > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use clarity::vm::functions::NativeFunctions;
+    use clarity::vm::{SymbolicExpression, SymbolicExpressionType, Value};
+
+    use super::{has_word, scan_unsupported, supported_natives, supported_types, TypeSupport};
+    use crate::tools::{compile_snippet, for_each_expr_mut};
+
+    const CONTRACT: &str = "
+(define-private (double (n int))
+  (* n 2))
+(define-read-only (compute (a int))
+  (+ (double a) (- a 1)))
+";
+
+    /// Rename the function called by every call to `from` in `exprs`.
+    fn rename_calls(exprs: &mut [SymbolicExpression], from: &str, to: &str) {
+        for_each_expr_mut(exprs, &mut |expr| {
+            if let SymbolicExpressionType::List(list) = &mut expr.expr {
+                if let Some(head) = list.first_mut() {
+                    if head
+                        .match_atom()
+                        .map_or(false, |name| name.as_str() == from)
+                    {
+                        head.expr = SymbolicExpressionType::Atom(to.into());
+                    }
+                }
+            }
+        })
+    }
+
+    /// Replace every literal `from` in `exprs` by `to`.
+    fn replace_literals(exprs: &mut [SymbolicExpression], from: &Value, to: Value) {
+        for_each_expr_mut(exprs, &mut |expr| {
+            if expr.match_literal_value() == Some(from) {
+                expr.expr = SymbolicExpressionType::LiteralValue(to.clone());
+            }
+        })
+    }
+
+    #[test]
+    fn supported_contract_reports_nothing() {
+        let analysis = compile_snippet(CONTRACT).contract_analysis;
+        assert!(scan_unsupported(&analysis).is_empty());
+    }
+
+    #[test]
+    fn reports_all_unsupported_functions() {
+        let mut analysis = compile_snippet(CONTRACT).contract_analysis;
+        rename_calls(&mut analysis.expressions, "*", "times-unsupported");
+        rename_calls(&mut analysis.expressions, "-", "minus-unsupported");
+
+        let names: Vec<_> = scan_unsupported(&analysis)
+            .into_iter()
+            .map(|unsupported| unsupported.name)
+            .collect();
+        assert_eq!(names, vec!["times-unsupported", "minus-unsupported"]);
+    }
+
+    #[test]
+    fn reports_unsupported_literals() {
+        let mut analysis = compile_snippet(CONTRACT).contract_analysis;
+        replace_literals(&mut analysis.expressions, &Value::Int(2), Value::Bool(true));
+
+        let names: Vec<_> = scan_unsupported(&analysis)
+            .into_iter()
+            .map(|unsupported| unsupported.name)
+            .collect();
+        assert_eq!(names, vec!["bool literal"]);
+    }
+
+    #[test]
+    fn supported_natives_are_listed() {
+        let natives = supported_natives();
+        for native in [
+            NativeFunctions::Add,
+            NativeFunctions::If,
+            NativeFunctions::Let,
+            NativeFunctions::Map,
+            NativeFunctions::Fold,
+            NativeFunctions::Filter,
+            NativeFunctions::FetchVar,
+            NativeFunctions::SetEntry,
+            NativeFunctions::ContractCall,
+            NativeFunctions::Print,
+        ] {
+            assert!(natives.contains(&native), "{}", native.get_name_str());
+        }
+
+        // A name without a word is not reported, even if the contract defines
+        // a function with that name.
+        assert!(!has_word("no-such-function"));
+        assert!(!has_word("double"));
+
+        // Every native of the pinned Clarity version is compiled.
+        let unsupported: Vec<_> = NativeFunctions::ALL
+            .iter()
+            .filter(|native| !natives.contains(native))
+            .map(|native| native.get_name_str())
+            .collect();
+        assert_eq!(unsupported, Vec::<&str>::new());
+    }
+
+    #[test]
+    fn all_types_are_supported() {
+        assert_eq!(
+            supported_types(),
+            TypeSupport {
+                int: true,
+                uint: true,
+                bool: true,
+                principal: true,
+                buffer: true,
+                string: true,
+                list: true,
+                tuple: true,
+                optional: true,
+                response: true,
+            }
+        );
+    }
+}
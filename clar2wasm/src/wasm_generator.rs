@@ -1,22 +1,25 @@
 use std::borrow::BorrowMut;
-use std::collections::HashMap;
+use std::collections::BTreeSet;
+use std::ops::{Deref, DerefMut};
 
 use clarity::vm::analysis::ContractAnalysis;
 use clarity::vm::clarity_wasm::{get_type_in_memory_size, get_type_size, is_in_memory_type};
 use clarity::vm::diagnostic::DiagnosableError;
-use clarity::vm::types::signatures::{StringUTF8Length, BUFF_1};
+use clarity::vm::types::signatures::{BufferLength, StringUTF8Length, BUFF_1};
 use clarity::vm::types::{
     CharType, FunctionType, PrincipalData, SequenceData, SequenceSubtype, StringSubtype,
     TypeSignature,
 };
 use clarity::vm::variables::NativeVariables;
 use clarity::vm::{ClarityName, SymbolicExpression, SymbolicExpressionType};
+use indexmap::IndexMap;
 use walrus::ir::{
-    BinaryOp, IfElse, InstrSeqId, InstrSeqType, LoadKind, MemArg, StoreKind, UnaryOp,
+    dfs_in_order, BinaryOp, Call, Const, IfElse, Instr, InstrSeq, InstrSeqId, InstrSeqType,
+    LoadKind, MemArg, StoreKind, UnaryOp, Value, Visitor,
 };
 use walrus::{
-    ActiveData, DataKind, FunctionBuilder, FunctionId, GlobalId, InstrSeqBuilder, LocalId,
-    MemoryId, Module, ValType,
+    ActiveData, DataKind, ExportItem, FunctionBuilder, FunctionId, GlobalId, InstrSeqBuilder,
+    LocalId, MemoryId, Module, ValType,
 };
 
 use crate::words;
@@ -24,6 +27,63 @@ use crate::words;
 // First free position after data directly defined in standard.wat
 pub const END_OF_STANDARD_DATA: u32 = 1352;
 
+/// Options controlling the code emitted by the [`WasmGenerator`].
+#[derive(Debug, Clone)]
+pub struct WasmGeneratorConfig {
+    /// A `fold` over a list literal with at most this many elements is
+    /// unrolled into straight-line code. `0` disables the unrolling.
+    pub fold_unroll_threshold: usize,
+    /// A chain of nested `concat`s, or of nested `append`s, is built in a
+    /// single buffer instead of allocating and copying a new sequence for
+    /// each `concat` or `append`.
+    pub flatten_sequence_chains: bool,
+    /// Call the `charge_cost` host import, with a [`CostOp`] and an amount,
+    /// before the expensive operations, so that the host can meter the
+    /// execution. The import is only added to the module when enabled, and
+    /// must then be provided by the host: the Clarity host does not define
+    /// it, so this is only for embedders with their own host.
+    pub emit_cost_hooks: bool,
+    /// Call the `print` host import for each `print` expression. When
+    /// disabled, `print` only returns its argument and no event is emitted,
+    /// which is only suitable for test harnesses that ignore the events.
+    pub emit_events: bool,
+    /// Name under which the function running the top-level expressions is
+    /// exported, or `None` to leave it unexported.
+    pub top_level_export: Option<String>,
+}
+
+impl Default for WasmGeneratorConfig {
+    fn default() -> Self {
+        Self {
+            fold_unroll_threshold: 4,
+            flatten_sequence_chains: true,
+            emit_cost_hooks: false,
+            emit_events: true,
+            top_level_export: Some(".top-level".to_owned()),
+        }
+    }
+}
+
+/// Operations charged through the `charge_cost(op_id: i32, n: i64)` host
+/// import. The discriminant is the `op_id` passed to the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum CostOp {
+    /// Integer arithmetic, `n` is the number of operands.
+    Arithmetic = 0,
+    /// Copy of data in memory, like the sequences built by `concat` or the
+    /// value returned by a function, `n` is the number of bytes copied at
+    /// runtime.
+    MemoryCopy = 1,
+    /// Hashing, `n` is the size in bytes of the input at runtime.
+    Hash = 2,
+    /// Call to any host function, including from the standard library, `n`
+    /// is always 1.
+    HostCall = 3,
+    /// Iteration of `fold`, `map` or `filter`, `n` is always 1.
+    LoopIteration = 4,
+}
+
 /// WasmGenerator is a Clarity AST visitor that generates a WebAssembly module
 /// as it traverses the AST.
 pub struct WasmGenerator {
@@ -36,27 +96,42 @@ pub struct WasmGenerator {
     pub(crate) literal_memory_end: u32,
     /// Global ID of the stack pointer.
     pub(crate) stack_pointer: GlobalId,
-    /// Map strings saved in the literal memory to their offset.
-    pub(crate) literal_memory_offset: HashMap<LiteralMemoryEntry, u32>,
-    /// Map constants to an offset in the literal memory.
-    pub(crate) constants: HashMap<String, u32>,
+    /// Map literals saved in the literal memory to their offset, in the order
+    /// they were saved.
+    pub(crate) literal_memory_offset: IndexMap<LiteralMemoryEntry, u32>,
+    /// Map constants to an offset in the literal memory, in definition order.
+    pub(crate) constants: IndexMap<String, u32>,
+    /// Constants initialized by an expression rather than a literal. Their
+    /// in-memory values are stored as an offset and a length, like any other
+    /// value written to memory, instead of being stored inline.
+    pub(crate) computed_constants: BTreeSet<String>,
     /// The current function body block, used for early exit
     early_return_block_id: Option<InstrSeqId>,
+    /// Host functions exiting the contexts (`as-contract`, `at-block`)
+    /// entered by the current expression, innermost last. An early return
+    /// calls them in reverse order before leaving the function.
+    context_exits: Vec<FunctionId>,
     /// The return type of the current function.
     pub(crate) return_type: Option<TypeSignature>,
-    /// The types of defined data-vars
-    pub(crate) datavars_types: HashMap<ClarityName, TypeSignature>,
+    /// The types of defined data-vars, in definition order.
+    pub(crate) datavars_types: IndexMap<ClarityName, TypeSignature>,
 
-    /// The locals for the current function.
-    pub(crate) bindings: HashMap<String, Vec<LocalId>>,
+    /// The locals for the current function, in binding order. Identifier
+    /// maps keep insertion order so that any iteration over them follows the
+    /// contract rather than a hash or the alphabet.
+    pub(crate) bindings: IndexMap<String, Vec<LocalId>>,
     /// Size of the current function's stack frame.
     frame_size: i32,
+    /// Options for the generated code.
+    pub(crate) config: WasmGeneratorConfig,
 }
 
 #[derive(Hash, Eq, PartialEq)]
 pub enum LiteralMemoryEntry {
     Ascii(String),
     Utf8(String),
+    /// The in-memory representation of any other literal, e.g. a buffer.
+    Bytes(Vec<u8>),
 }
 
 #[derive(Debug)]
@@ -66,12 +141,60 @@ pub enum GeneratorError {
     TypeError(String),
 }
 
+/// Reason for a runtime error raised by the compiled code. The codes are
+/// stable, and match the error codes passed to `stdlib.runtime-error` by the
+/// standard library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum RuntimeError {
+    Overflow = 0,
+    Underflow = 1,
+    DivideByZero = 2,
+    LogOfNumberLessThanOrEqualToZero = 3,
+    ExpectedANonNegativeNumber = 4,
+    BufferToIntegerLength = 5,
+    Panic = 6,
+    ShortReturn = 7,
+}
+
+impl RuntimeError {
+    /// The runtime error with the error code `code`, if there is one.
+    pub fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(RuntimeError::Overflow),
+            1 => Some(RuntimeError::Underflow),
+            2 => Some(RuntimeError::DivideByZero),
+            3 => Some(RuntimeError::LogOfNumberLessThanOrEqualToZero),
+            4 => Some(RuntimeError::ExpectedANonNegativeNumber),
+            5 => Some(RuntimeError::BufferToIntegerLength),
+            6 => Some(RuntimeError::Panic),
+            7 => Some(RuntimeError::ShortReturn),
+            _ => None,
+        }
+    }
+}
+
 pub enum FunctionKind {
     Public,
     Private,
     ReadOnly,
 }
 
+impl FunctionKind {
+    /// Name of a user-defined function in the module. It is prefixed with the
+    /// kind of the function, so that it cannot collide with a function of
+    /// another kind or with a function of the standard library. Exports keep
+    /// the bare Clarity name.
+    pub(crate) fn internal_name(&self, name: &str) -> String {
+        let prefix = match self {
+            FunctionKind::Public => "public",
+            FunctionKind::Private => "private",
+            FunctionKind::ReadOnly => "read-only",
+        };
+        format!("{prefix}.{name}")
+    }
+}
+
 impl DiagnosableError for GeneratorError {
     fn message(&self) -> String {
         match self {
@@ -186,6 +309,44 @@ pub enum SequenceElementType {
     Other(TypeSignature),
 }
 
+impl SequenceElementType {
+    /// The elements of a sequence of type `ty`, of the type returned by
+    /// [`sequence_element_type`]. Returns an error if `ty` is not a sequence
+    /// type.
+    pub fn from_sequence_type(ty: &TypeSignature) -> Result<Self, GeneratorError> {
+        let elem_ty = sequence_element_type(ty)
+            .ok_or_else(|| GeneratorError::TypeError("expected sequence type".to_owned()))?;
+        Ok(match ty {
+            TypeSignature::SequenceType(SequenceSubtype::ListType(_)) => {
+                SequenceElementType::Other(elem_ty)
+            }
+            TypeSignature::SequenceType(SequenceSubtype::StringType(StringSubtype::UTF8(_))) => {
+                SequenceElementType::UnicodeScalar
+            }
+            _ => SequenceElementType::Byte,
+        })
+    }
+
+    /// Size of an element in the memory of the sequence.
+    pub fn size(&self) -> i32 {
+        match self {
+            SequenceElementType::Byte => 1,
+            SequenceElementType::UnicodeScalar => 4,
+            SequenceElementType::Other(ty) => get_type_size(ty),
+        }
+    }
+}
+
+/// A sequence being iterated over by [`WasmGenerator::emit_sequence_loop`].
+pub struct SequenceIter {
+    /// Local holding the offset of the current element.
+    pub offset: LocalId,
+    /// Local holding the offset of the end of the sequence.
+    pub end: LocalId,
+    /// Type of the elements of the sequence.
+    pub elem_ty: SequenceElementType,
+}
+
 /// Drop a value of type `ty` from the data stack.
 pub(crate) fn drop_value(builder: &mut InstrSeqBuilder, ty: &TypeSignature) {
     let wasm_types = clar2wasm_ty(ty);
@@ -194,26 +355,256 @@ pub(crate) fn drop_value(builder: &mut InstrSeqBuilder, ty: &TypeSignature) {
     });
 }
 
-pub fn type_from_sequence_element(se: &SequenceElementType) -> TypeSignature {
-    match se {
-        SequenceElementType::Other(o) => o.clone(),
-        SequenceElementType::Byte => BUFF_1.clone(),
-        SequenceElementType::UnicodeScalar => {
-            TypeSignature::SequenceType(SequenceSubtype::StringType(StringSubtype::UTF8(
-                #[allow(clippy::unwrap_used)]
-                StringUTF8Length::try_from(1u32).unwrap(),
+/// Returns an error if `size`, the number of bytes that were `action` for a
+/// value of type `ty`, differs from the size of `ty` in memory known to the
+/// host.
+fn check_type_size(action: &str, size: i64, ty: &TypeSignature) -> Result<(), GeneratorError> {
+    let expected = get_type_size(ty) as i64;
+    if size == expected {
+        Ok(())
+    } else {
+        Err(GeneratorError::InternalError(format!(
+            "{action} {size} bytes for a value of type {ty}, expected {expected}"
+        )))
+    }
+}
+
+/// Returns true if a value of type `ty` references data in memory, either
+/// directly or through one of its components.
+pub(crate) fn contains_in_memory_type(ty: &TypeSignature) -> bool {
+    match ty {
+        TypeSignature::OptionalType(inner) => contains_in_memory_type(inner),
+        TypeSignature::ResponseType(inner) => {
+            contains_in_memory_type(&inner.0) || contains_in_memory_type(&inner.1)
+        }
+        TypeSignature::TupleType(tuple) => {
+            tuple.get_type_map().values().any(contains_in_memory_type)
+        }
+        _ => is_in_memory_type(ty),
+    }
+}
+
+/// Returns the type of the elements of a sequence type: the item type for a
+/// list, a single byte for a buffer and a single character for a string.
+/// Returns `None` if `ty` is not a sequence type.
+#[allow(clippy::unwrap_used)]
+pub fn sequence_element_type(ty: &TypeSignature) -> Option<TypeSignature> {
+    match ty {
+        TypeSignature::SequenceType(SequenceSubtype::ListType(list_type)) => {
+            Some(list_type.get_list_item_type().clone())
+        }
+        TypeSignature::SequenceType(SequenceSubtype::BufferType(_)) => Some(BUFF_1.clone()),
+        TypeSignature::SequenceType(SequenceSubtype::StringType(StringSubtype::ASCII(_))) => {
+            Some(TypeSignature::SequenceType(SequenceSubtype::StringType(
+                StringSubtype::ASCII(BufferLength::try_from(1u32).unwrap()),
+            )))
+        }
+        TypeSignature::SequenceType(SequenceSubtype::StringType(StringSubtype::UTF8(_))) => {
+            Some(TypeSignature::SequenceType(SequenceSubtype::StringType(
+                StringSubtype::UTF8(StringUTF8Length::try_from(1u32).unwrap()),
             )))
         }
+        _ => None,
+    }
+}
+
+/// Returns the kind of a literal value which [`WasmGenerator`] cannot emit
+/// directly, or `None` if it is supported. A list literal is supported if all
+/// of its elements are.
+pub(crate) fn unsupported_literal_kind(value: &clarity::vm::Value) -> Option<&'static str> {
+    match value {
+        clarity::vm::Value::Int(_)
+        | clarity::vm::Value::UInt(_)
+        | clarity::vm::Value::Principal(_)
+        | clarity::vm::Value::Sequence(SequenceData::Buffer(_))
+        | clarity::vm::Value::Sequence(SequenceData::String(_)) => None,
+        clarity::vm::Value::Sequence(SequenceData::List(list)) => {
+            list.data.iter().find_map(unsupported_literal_kind)
+        }
+        clarity::vm::Value::Bool(_) => Some("bool literal"),
+        clarity::vm::Value::Tuple(_) => Some("tuple literal"),
+        clarity::vm::Value::Optional(_) => Some("optional literal"),
+        clarity::vm::Value::Response(_) => Some("response literal"),
+        clarity::vm::Value::CallableContract(_) => Some("callable contract literal"),
+    }
+}
+
+/// Returns the number of 64KiB pages holding the literal memory, which ends
+/// at `end`. The stack starts at the end of the literal memory, and the
+/// stack pointer is an `i32`, so `end` cannot exceed `i32::MAX`.
+fn literal_memory_pages(end: u32) -> Result<u32, GeneratorError> {
+    if end > i32::MAX as u32 {
+        return Err(GeneratorError::InternalError(
+            "literal memory exceeds 2GiB".to_owned(),
+        ));
+    }
+    Ok(end / (64 * 1024) + (end % (64 * 1024) > 0) as u32)
+}
+
+/// Functions of the standard library called by the generator itself, for
+/// every function definition, call and runtime error, whatever the words
+/// used by the contract.
+const REQUIRED_STDLIB_FUNCTIONS: &[&str] = &[
+    "stdlib.runtime-error",
+    "stdlib.define_function",
+    "stdlib.begin_public_call",
+    "stdlib.begin_read_only_call",
+    "stdlib.commit_call",
+    "stdlib.roll_back_call",
+];
+
+/// Returns an error if the standard library `module` does not define one of
+/// the [`REQUIRED_STDLIB_FUNCTIONS`].
+fn check_required_functions(module: &Module) -> Result<(), GeneratorError> {
+    match REQUIRED_STDLIB_FUNCTIONS
+        .iter()
+        .find(|name| module.funcs.by_name(name).is_none())
+    {
+        Some(missing) => Err(GeneratorError::InternalError(format!(
+            "Expected the standard library to define a function named ${missing}"
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Charge a [`CostOp::HostCall`] through `charge_cost` before every call to
+/// a host import in the local functions of `module`.
+fn charge_host_calls(module: &mut Module, charge_cost: FunctionId) {
+    struct InstrSeqs(Vec<InstrSeqId>);
+    impl<'instr> Visitor<'instr> for InstrSeqs {
+        fn start_instr_seq(&mut self, instr_seq: &'instr InstrSeq) {
+            self.0.push(instr_seq.id());
+        }
+    }
+
+    let host_functions: BTreeSet<FunctionId> = module
+        .funcs
+        .iter()
+        .filter(|func| matches!(func.kind, walrus::FunctionKind::Import(_)))
+        .map(|func| func.id())
+        .filter(|func| *func != charge_cost)
+        .collect();
+
+    for (_, func) in module.funcs.iter_local_mut() {
+        let mut instr_seqs = InstrSeqs(Vec::new());
+        dfs_in_order(&mut instr_seqs, func, func.entry_block());
+        for seq_id in instr_seqs.0 {
+            let seq = func.block_mut(seq_id);
+            let instrs = std::mem::take(&mut seq.instrs);
+            for (instr, loc) in instrs {
+                if matches!(&instr, Instr::Call(call) if host_functions.contains(&call.func)) {
+                    seq.instrs.extend([
+                        (
+                            Instr::Const(Const {
+                                value: Value::I32(CostOp::HostCall as i32),
+                            }),
+                            loc,
+                        ),
+                        (
+                            Instr::Const(Const {
+                                value: Value::I64(1),
+                            }),
+                            loc,
+                        ),
+                        (Instr::Call(Call { func: charge_cost }), loc),
+                    ]);
+                }
+                seq.instrs.push((instr, loc));
+            }
+        }
+    }
+}
+
+/// Name of the export of the standard library checking its own helpers.
+const SELF_TEST_EXPORT: &str = ".self-test";
+
+/// Remove the self-test of the standard library from `module`, which is only
+/// meant to be called by the tests of the standard library itself.
+fn remove_self_test(module: &mut Module) {
+    let self_test = module
+        .exports
+        .iter()
+        .find(|export| export.name == SELF_TEST_EXPORT)
+        .map(|export| (export.id(), export.item));
+    if let Some((export, item)) = self_test {
+        module.exports.delete(export);
+        if let ExportItem::Function(func) = item {
+            module.funcs.delete(func);
+        }
+    }
+}
+
+/// The generator inside the definition of a function. The top-level state,
+/// saved on entry, is restored when the scope is dropped, whether the
+/// definition returns or unwinds from a panic, so that a caller recovering
+/// from either can keep using the generator.
+struct FunctionScope<'a> {
+    generator: &'a mut WasmGenerator,
+    bindings: IndexMap<String, Vec<LocalId>>,
+    frame_size: i32,
+    return_type: Option<TypeSignature>,
+    early_return_block_id: Option<InstrSeqId>,
+}
+
+impl<'a> FunctionScope<'a> {
+    fn enter(generator: &'a mut WasmGenerator) -> Self {
+        FunctionScope {
+            bindings: std::mem::take(&mut generator.bindings),
+            frame_size: std::mem::replace(&mut generator.frame_size, 0),
+            return_type: generator.return_type.take(),
+            early_return_block_id: generator.early_return_block_id.take(),
+            generator,
+        }
+    }
+}
+
+impl Deref for FunctionScope<'_> {
+    type Target = WasmGenerator;
+
+    fn deref(&self) -> &WasmGenerator {
+        self.generator
+    }
+}
+
+impl DerefMut for FunctionScope<'_> {
+    fn deref_mut(&mut self) -> &mut WasmGenerator {
+        self.generator
+    }
+}
+
+impl Drop for FunctionScope<'_> {
+    fn drop(&mut self) {
+        self.generator.bindings = std::mem::take(&mut self.bindings);
+        self.generator.frame_size = self.frame_size;
+        self.generator.return_type = self.return_type.take();
+        self.generator.early_return_block_id = self.early_return_block_id.take();
     }
 }
 
 impl WasmGenerator {
     pub fn new(contract_analysis: ContractAnalysis) -> Result<WasmGenerator, GeneratorError> {
         let standard_lib_wasm: &[u8] = include_bytes!("standard/standard.wasm");
+        Self::with_standard_lib(contract_analysis, standard_lib_wasm)
+    }
 
-        let module = Module::from_buffer(standard_lib_wasm).map_err(|_err| {
-            GeneratorError::InternalError("failed to load standard library".to_owned())
+    /// Create a generator using an externally built standard library instead
+    /// of the one embedded in the crate. This is useful when working on the
+    /// standard library itself. The library must define a memory, a global
+    /// named `stack-pointer` and the functions the generator always relies
+    /// on, like `stdlib.runtime-error`.
+    pub fn with_standard_lib(
+        contract_analysis: ContractAnalysis,
+        standard_lib_wasm: &[u8],
+    ) -> Result<WasmGenerator, GeneratorError> {
+        let mut module = Module::from_buffer(standard_lib_wasm).map_err(|err| {
+            GeneratorError::InternalError(format!("failed to load standard library: {err}"))
         })?;
+        remove_self_test(&mut module);
+        if module.memories.iter().next().is_none() {
+            return Err(GeneratorError::InternalError(
+                "Expected the standard library to define a memory".to_owned(),
+            ));
+        }
         // Get the stack-pointer global ID
         let stack_pointer_name = "stack-pointer";
         let global_id = module
@@ -231,23 +622,34 @@ impl WasmGenerator {
                     "Expected to find a global named $stack-pointer".to_owned(),
                 )
             })?;
+        check_required_functions(&module)?;
 
         Ok(WasmGenerator {
             contract_analysis,
             module,
             literal_memory_end: END_OF_STANDARD_DATA,
             stack_pointer: global_id,
-            literal_memory_offset: HashMap::new(),
-            constants: HashMap::new(),
-            bindings: HashMap::new(),
+            literal_memory_offset: IndexMap::new(),
+            constants: IndexMap::new(),
+            computed_constants: BTreeSet::new(),
+            bindings: IndexMap::new(),
             early_return_block_id: None,
+            context_exits: Vec::new(),
             return_type: None,
             frame_size: 0,
-            datavars_types: HashMap::new(),
+            config: WasmGeneratorConfig::default(),
+            datavars_types: IndexMap::new(),
         })
     }
 
+    /// Use `config` instead of the default options for the generated code.
+    pub fn with_config(mut self, config: WasmGeneratorConfig) -> Self {
+        self.config = config;
+        self
+    }
+
     pub fn set_memory_pages(&mut self) -> Result<(), GeneratorError> {
+        let pages = literal_memory_pages(self.literal_memory_end)?;
         let memory = self
             .module
             .memories
@@ -255,14 +657,31 @@ impl WasmGenerator {
             .next()
             .ok_or_else(|| GeneratorError::InternalError("No Memory found".to_owned()))?;
 
-        let pages_required = self.literal_memory_end / (64 * 1024);
-        let remainder = self.literal_memory_end % (64 * 1024);
-
-        memory.initial = pages_required + (remainder > 0) as u32;
+        memory.initial = pages;
 
         Ok(())
     }
 
+    /// Reserves `len` bytes at the end of the literal memory, growing the
+    /// memory first if the literal does not fit, and returns the offset of
+    /// the reserved bytes.
+    fn reserve_literal_memory(&mut self, len: u32) -> Result<u32, GeneratorError> {
+        let offset = self.literal_memory_end;
+        let end = offset.saturating_add(len);
+        let pages = literal_memory_pages(end)?;
+
+        let memory = self
+            .module
+            .memories
+            .iter_mut()
+            .next()
+            .ok_or_else(|| GeneratorError::InternalError("No Memory found".to_owned()))?;
+        memory.initial = memory.initial.max(pages);
+
+        self.literal_memory_end = end;
+        Ok(offset)
+    }
+
     pub fn generate(mut self) -> Result<Module, GeneratorError> {
         let expressions = std::mem::take(&mut self.contract_analysis.expressions);
         // println!("{:?}", expressions);
@@ -275,14 +694,25 @@ impl WasmGenerator {
 
         let mut current_function = FunctionBuilder::new(&mut self.module.types, &[], &return_ty);
 
+        self.register_top_level_definitions(&expressions)?;
+
         if !expressions.is_empty() {
             self.traverse_statement_list(&mut current_function.func_body(), &expressions)?;
         }
+        self.check_frame_size(".top-level")?;
 
         self.contract_analysis.expressions = expressions;
 
         let top_level = current_function.finish(vec![], &mut self.module.funcs);
-        self.module.exports.add(".top-level", top_level);
+        if let Some(name) = &self.config.top_level_export {
+            self.module.exports.add(name, top_level);
+        }
+
+        // The host calls are charged once all the code has been generated,
+        // wherever they are made from.
+        if let Some(charge_cost) = self.charge_cost_func() {
+            charge_host_calls(&mut self.module, charge_cost);
+        }
 
         self.set_memory_pages()?;
 
@@ -295,6 +725,49 @@ impl WasmGenerator {
         Ok(self.module)
     }
 
+    /// Register the identifiers of the data vars and maps defined at the
+    /// top-level before any code is generated, since Clarity allows functions
+    /// to reference them before their definition.
+    pub(crate) fn register_top_level_definitions(
+        &mut self,
+        expressions: &[SymbolicExpression],
+    ) -> Result<(), GeneratorError> {
+        for expr in expressions {
+            let Some(list) = expr.match_list() else {
+                continue;
+            };
+            let (Some(definition), Some(name)) = (
+                list.first().and_then(|e| e.match_atom()),
+                list.get(1).and_then(|e| e.match_atom()),
+            ) else {
+                continue;
+            };
+            match definition.as_str() {
+                "define-data-var" => {
+                    self.add_string_literal(name)?;
+                    let ty = self
+                        .contract_analysis
+                        .persisted_variable_types
+                        .get(name)
+                        .ok_or_else(|| {
+                            GeneratorError::TypeError(format!("data var must be typed: {name}"))
+                        })?
+                        .clone();
+                    if self.datavars_types.insert(name.clone(), ty).is_some() {
+                        return Err(GeneratorError::InternalError(format!(
+                            "Data var defined twice: {name}"
+                        )));
+                    }
+                }
+                "define-map" => {
+                    self.add_string_literal(name)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
     pub fn get_memory(&self) -> Result<MemoryId, GeneratorError> {
         Ok(self
             .module
@@ -408,6 +881,18 @@ impl WasmGenerator {
         name: &ClarityName,
         body: &SymbolicExpression,
         kind: FunctionKind,
+    ) -> Result<FunctionId, GeneratorError> {
+        FunctionScope::enter(self).define_function(builder, name, body, kind)
+    }
+
+    /// Define the function `name` in the module. Must be called in a
+    /// [`FunctionScope`], which restores the top-level state afterwards.
+    fn define_function(
+        &mut self,
+        builder: &mut InstrSeqBuilder,
+        name: &ClarityName,
+        body: &SymbolicExpression,
+        kind: FunctionKind,
     ) -> Result<FunctionId, GeneratorError> {
         let opt_function_type = match kind {
             FunctionKind::ReadOnly => {
@@ -446,7 +931,7 @@ impl WasmGenerator {
         // Call the host interface function, `define_function`
         builder.call(self.func_by_name("stdlib.define_function"));
 
-        let mut bindings = HashMap::new();
+        let mut bindings = IndexMap::new();
 
         // Setup the parameters
         let mut param_locals = Vec::new();
@@ -469,7 +954,7 @@ impl WasmGenerator {
             params_types.as_slice(),
             results_types.as_slice(),
         );
-        func_builder.name(name.as_str().to_string());
+        func_builder.name(kind.internal_name(name.as_str()));
         let mut func_body = func_builder.func_body();
 
         // Function prelude
@@ -479,9 +964,8 @@ impl WasmGenerator {
             .global_get(self.stack_pointer)
             .local_set(frame_pointer);
 
-        // Setup the locals map for this function, saving the top-level map to
-        // restore after.
-        let top_level_locals = std::mem::replace(&mut self.bindings, bindings);
+        // Setup the locals map for this function. Its frame starts empty.
+        self.bindings = bindings;
 
         let mut block = func_body.dangling_instr_seq(InstrSeqType::new(
             &mut self.module.types,
@@ -495,33 +979,310 @@ impl WasmGenerator {
         // Traverse the body of the function
         self.set_expr_type(body, function_type.returns.clone())?;
         self.traverse_expr(&mut block, body)?;
+        self.check_frame_size(name.as_str())?;
 
         // Insert the function body block into the function
         func_body.instr(walrus::ir::Block { seq: block_id });
 
         // Function postlude
-        // Restore the initial stack pointer.
-        func_body
+        // Restore the initial stack pointer. Early returns branch to the end
+        // of the body block, so they also go through here and restore the
+        // pointer saved on entry, whatever the scope they return from.
+        match &function_type.returns {
+            TypeSignature::SequenceType(SequenceSubtype::ListType(list_ty))
+                if contains_in_memory_type(list_ty.get_list_item_type()) =>
+            {
+                self.copy_returned_value(&mut func_body, frame_pointer, &function_type.returns)?;
+            }
+            TypeSignature::SequenceType(_) => {
+                self.copy_returned_sequence(&mut func_body, frame_pointer)?;
+            }
+            returns if contains_in_memory_type(returns) => {
+                self.copy_returned_value(&mut func_body, frame_pointer, returns)?;
+            }
+            _ => {
+                func_body
+                    .local_get(frame_pointer)
+                    .global_set(self.stack_pointer);
+            }
+        }
+
+        Ok(func_builder.finish(param_locals, &mut self.module.funcs))
+    }
+
+    /// Restores the stack pointer to `frame_pointer` at the end of a function
+    /// returning a sequence. A sequence in the frame of the function is first
+    /// moved to the start of the frame, and the stack pointer is set right
+    /// after it, so that it stays valid for the caller.
+    fn copy_returned_sequence(
+        &mut self,
+        builder: &mut InstrSeqBuilder,
+        frame_pointer: LocalId,
+    ) -> Result<(), GeneratorError> {
+        let memory = self.get_memory()?;
+        let stack_pointer = self.stack_pointer;
+
+        // STACK: [offset, length]
+        let offset = self.module.locals.add(ValType::I32);
+        let length = self.module.locals.add(ValType::I32);
+        builder.local_set(length).local_set(offset);
+
+        let mut in_frame = builder.dangling_instr_seq(None);
+        self.charge_cost_bytes(&mut in_frame, CostOp::MemoryCopy, length);
+        in_frame
+            .local_get(frame_pointer)
+            .local_get(offset)
+            .local_get(length)
+            .memory_copy(memory, memory)
+            .local_get(frame_pointer)
+            .local_tee(offset)
+            .local_get(length)
+            .binop(BinaryOp::I32Add)
+            .global_set(stack_pointer);
+        let in_frame_id = in_frame.id();
+
+        let mut outside_frame = builder.dangling_instr_seq(None);
+        outside_frame
+            .local_get(frame_pointer)
+            .global_set(stack_pointer);
+        let outside_frame_id = outside_frame.id();
+
+        builder
+            .local_get(offset)
+            .local_get(frame_pointer)
+            .binop(BinaryOp::I32GeU)
+            .instr(IfElse {
+                consequent: in_frame_id,
+                alternative: outside_frame_id,
+            });
+
+        builder.local_get(offset).local_get(length);
+        Ok(())
+    }
+
+    /// Restores the stack pointer to `frame_pointer` at the end of a function
+    /// returning a value of type `ty` which references data in memory, like
+    /// an optional string or a list of buffers. The data of the value that is
+    /// in the frame of the function, including the elements of its lists, is
+    /// first copied to the free memory above the frame, with the references
+    /// to it adjusted to where it is moved next. This copy is then moved to
+    /// the start of the frame, and the stack pointer is set right after it.
+    fn copy_returned_value(
+        &mut self,
+        builder: &mut InstrSeqBuilder,
+        frame_pointer: LocalId,
+        ty: &TypeSignature,
+    ) -> Result<(), GeneratorError> {
+        let memory = self.get_memory()?;
+        let locals = self.save_to_locals(builder, ty, true);
+
+        // All the data in the frame is below the stack pointer, so copying
+        // above it never overwrites data that remains to be copied.
+        let start = self.module.locals.add(ValType::I32);
+        let cursor = self.module.locals.add(ValType::I32);
+        let shift = self.module.locals.add(ValType::I32);
+        builder
+            .global_get(self.stack_pointer)
+            .local_tee(start)
+            .local_tee(cursor)
             .local_get(frame_pointer)
+            .binop(BinaryOp::I32Sub)
+            .local_set(shift);
+
+        self.relocate_returned_value(builder, frame_pointer, cursor, shift, ty, &locals)?;
+
+        let size = self.module.locals.add(ValType::I32);
+        builder
+            .local_get(cursor)
+            .local_get(start)
+            .binop(BinaryOp::I32Sub)
+            .local_set(size);
+        self.charge_cost_bytes(builder, CostOp::MemoryCopy, size);
+        builder
+            .local_get(frame_pointer)
+            .local_get(start)
+            .local_get(size)
+            .memory_copy(memory, memory)
+            .local_get(cursor)
+            .local_get(shift)
+            .binop(BinaryOp::I32Sub)
             .global_set(self.stack_pointer);
 
-        // Restore the top-level locals map.
-        self.bindings = top_level_locals;
+        for local in locals {
+            builder.local_get(local);
+        }
+        Ok(())
+    }
+
+    /// Copy the data referenced by the value of type `ty` in `locals` which
+    /// is in the frame starting at `frame_pointer` to `cursor`, advancing it,
+    /// and update the references in `locals` to point `shift` bytes before
+    /// the copy. The payload of a `none` or of the unset half of a response
+    /// is a placeholder, which is not followed.
+    fn relocate_returned_value(
+        &mut self,
+        builder: &mut InstrSeqBuilder,
+        frame_pointer: LocalId,
+        cursor: LocalId,
+        shift: LocalId,
+        ty: &TypeSignature,
+        locals: &[LocalId],
+    ) -> Result<(), GeneratorError> {
+        match ty {
+            TypeSignature::OptionalType(inner) if contains_in_memory_type(inner) => {
+                let mut some = builder.dangling_instr_seq(None);
+                self.relocate_returned_value(
+                    &mut some,
+                    frame_pointer,
+                    cursor,
+                    shift,
+                    inner,
+                    &locals[1..],
+                )?;
+                let some_id = some.id();
+                let none_id = builder.dangling_instr_seq(None).id();
+                builder.local_get(locals[0]).instr(IfElse {
+                    consequent: some_id,
+                    alternative: none_id,
+                });
+            }
+            TypeSignature::ResponseType(inner) if contains_in_memory_type(ty) => {
+                let ok_len = clar2wasm_ty(&inner.0).len();
+                let mut ok = builder.dangling_instr_seq(None);
+                self.relocate_returned_value(
+                    &mut ok,
+                    frame_pointer,
+                    cursor,
+                    shift,
+                    &inner.0,
+                    &locals[1..1 + ok_len],
+                )?;
+                let ok_id = ok.id();
+                let mut err = builder.dangling_instr_seq(None);
+                self.relocate_returned_value(
+                    &mut err,
+                    frame_pointer,
+                    cursor,
+                    shift,
+                    &inner.1,
+                    &locals[1 + ok_len..],
+                )?;
+                let err_id = err.id();
+                builder.local_get(locals[0]).instr(IfElse {
+                    consequent: ok_id,
+                    alternative: err_id,
+                });
+            }
+            TypeSignature::TupleType(tuple_ty) => {
+                let mut index = 0;
+                for field_ty in tuple_ty.get_type_map().values() {
+                    let len = clar2wasm_ty(field_ty).len();
+                    self.relocate_returned_value(
+                        builder,
+                        frame_pointer,
+                        cursor,
+                        shift,
+                        field_ty,
+                        &locals[index..index + len],
+                    )?;
+                    index += len;
+                }
+            }
+            _ if is_in_memory_type(ty) => {
+                let memory = self.get_memory()?;
+                let (offset, length) = (locals[0], locals[1]);
+                let copy = self.module.locals.add(ValType::I32);
+
+                // Data outside of the frame, like literals or data of the
+                // caller, stays valid and is not copied.
+                let mut in_frame = builder.dangling_instr_seq(None);
+                self.charge_cost_bytes(&mut in_frame, CostOp::MemoryCopy, length);
+                in_frame
+                    .local_get(cursor)
+                    .local_tee(copy)
+                    .local_get(offset)
+                    .local_get(length)
+                    .memory_copy(memory, memory)
+                    .local_get(copy)
+                    .local_get(length)
+                    .binop(BinaryOp::I32Add)
+                    .local_set(cursor);
+
+                // The elements of a copied list still reference the data of
+                // the frame, which is copied in turn.
+                if let TypeSignature::SequenceType(SequenceSubtype::ListType(list_ty)) = ty {
+                    let elem_ty = list_ty.get_list_item_type();
+                    if contains_in_memory_type(elem_ty) {
+                        let elem = self.module.locals.add(ValType::I32);
+                        let end = self.module.locals.add(ValType::I32);
+                        in_frame
+                            .local_get(copy)
+                            .local_tee(elem)
+                            .local_get(length)
+                            .binop(BinaryOp::I32Add)
+                            .local_set(end);
+                        let iter = SequenceIter {
+                            offset: elem,
+                            end,
+                            elem_ty: SequenceElementType::Other(elem_ty.clone()),
+                        };
+                        self.emit_sequence_loop(
+                            &mut in_frame,
+                            std::slice::from_ref(&iter),
+                            |generator, loop_| {
+                                generator.read_from_memory(loop_, elem, 0, elem_ty)?;
+                                let elem_locals = generator.save_to_locals(loop_, elem_ty, true);
+                                generator.relocate_returned_value(
+                                    loop_,
+                                    frame_pointer,
+                                    cursor,
+                                    shift,
+                                    elem_ty,
+                                    &elem_locals,
+                                )?;
+                                for local in elem_locals {
+                                    loop_.local_get(local);
+                                }
+                                generator.write_to_memory(loop_, elem, 0, elem_ty)?;
+                                Ok(())
+                            },
+                        )?;
+                    }
+                }
 
-        // Reset the return type and early block to None
-        self.return_type = None;
-        self.early_return_block_id = None;
+                in_frame
+                    .local_get(copy)
+                    .local_get(shift)
+                    .binop(BinaryOp::I32Sub)
+                    .local_set(offset);
+                let in_frame_id = in_frame.id();
+                let outside_frame_id = builder.dangling_instr_seq(None).id();
 
-        Ok(func_builder.finish(param_locals, &mut self.module.funcs))
+                builder
+                    .local_get(offset)
+                    .local_get(frame_pointer)
+                    .binop(BinaryOp::I32GeU)
+                    .instr(IfElse {
+                        consequent: in_frame_id,
+                        alternative: outside_frame_id,
+                    });
+            }
+            _ => {}
+        }
+        Ok(())
     }
 
     pub fn return_early(&self, builder: &mut InstrSeqBuilder) -> Result<(), GeneratorError> {
         if let Some(block_id) = self.early_return_block_id {
+            // Exit the contexts entered in the function, innermost first.
+            for exit in self.context_exits.iter().rev() {
+                builder.call(*exit);
+            }
             builder.instr(walrus::ir::Br { block: block_id });
         } else {
             // This must be from a top-leve statement, so it should cause a runtime error
             builder
-                .i32_const(7)
+                .i32_const(RuntimeError::ShortReturn as i32)
                 .call(self.func_by_name("stdlib.runtime-error"));
             builder.unreachable();
         }
@@ -529,7 +1290,28 @@ impl WasmGenerator {
         Ok(())
     }
 
-    /// Gets the result type of the given `SymbolicExpression`.
+    /// Traverse `expr` in a context entered by the caller, like the sender of
+    /// `as-contract`, then call the host function `exit` to leave it. The
+    /// context is also left by any early return from within `expr`.
+    pub(crate) fn traverse_in_context(
+        &mut self,
+        builder: &mut InstrSeqBuilder,
+        expr: &SymbolicExpression,
+        exit: &str,
+    ) -> Result<(), GeneratorError> {
+        let exit = self.func_by_name(exit);
+        self.context_exits.push(exit);
+        let traversal = self.traverse_expr(builder, expr);
+        self.context_exits.pop();
+        traversal?;
+
+        builder.call(exit);
+        Ok(())
+    }
+
+    /// Gets the result type of the given `SymbolicExpression`, or `None` if it
+    /// is not in the type map, as for definitions. Words needing the type
+    /// report its absence as an error, so a malformed AST does not panic.
     pub fn get_expr_type(&self, expr: &SymbolicExpression) -> Option<&TypeSignature> {
         self.contract_analysis
             .type_map
@@ -592,8 +1374,8 @@ impl WasmGenerator {
             }
         };
         let memory = self.get_memory()?;
-        let offset = self.literal_memory_end;
         let len = data.len() as u32;
+        let offset = self.reserve_literal_memory(len)?;
         self.module.data.add(
             DataKind::Active(ActiveData {
                 memory,
@@ -601,7 +1383,6 @@ impl WasmGenerator {
             }),
             data,
         );
-        self.literal_memory_end += len;
 
         // Save the offset in the literal memory for this string
         self.literal_memory_offset.insert(entry, offset);
@@ -619,8 +1400,8 @@ impl WasmGenerator {
         }
 
         let memory = self.get_memory()?;
-        let offset = self.literal_memory_end;
         let len = name.len() as u32;
+        let offset = self.reserve_literal_memory(len)?;
         self.module.data.add(
             DataKind::Active(ActiveData {
                 memory,
@@ -628,7 +1409,6 @@ impl WasmGenerator {
             }),
             name.as_bytes().to_vec(),
         );
-        self.literal_memory_end += name.len() as u32;
 
         // Save the offset in the literal memory for this identifier
         self.literal_memory_offset.insert(entry, offset);
@@ -687,17 +1467,23 @@ impl WasmGenerator {
                 )))
             }
         };
+        // Identical literals share the same memory, in the whole module.
+        let entry = LiteralMemoryEntry::Bytes(data.clone());
+        if let Some(offset) = self.literal_memory_offset.get(&entry) {
+            return Ok((*offset, data.len() as u32));
+        }
         let memory = self.get_memory()?;
-        let offset = self.literal_memory_end;
         let len = data.len() as u32;
+        let offset = self.reserve_literal_memory(len)?;
         self.module.data.add(
             DataKind::Active(ActiveData {
                 memory,
                 location: walrus::ActiveDataLocation::Absolute(offset),
             }),
-            data.clone(),
+            data,
         );
-        self.literal_memory_end += data.len() as u32;
+
+        self.literal_memory_offset.insert(entry, offset);
 
         Ok((offset, len))
     }
@@ -744,6 +1530,17 @@ impl WasmGenerator {
             (false, false) => unreachable!("must include either repr or value"),
         };
 
+        (self.create_call_stack_buffer(builder, size), size)
+    }
+
+    /// Reserve `size` bytes on the call stack, adjusting the stack pointer and
+    /// tracking the current function's frame size accordingly. Returns a local
+    /// which is a pointer to the beginning of the allocated stack space.
+    pub(crate) fn create_call_stack_buffer(
+        &mut self,
+        builder: &mut InstrSeqBuilder,
+        size: i32,
+    ) -> LocalId {
         // Save the offset (current stack pointer) into a local
         let offset = self.module.locals.add(ValType::I32);
         builder
@@ -765,21 +1562,53 @@ impl WasmGenerator {
             // [ new_stack_ptr ]
             .global_set(self.stack_pointer);
         // [  ]
-        self.frame_size += size;
+        // The frame size saturates, and an overflow is reported once the
+        // function has been traversed.
+        self.frame_size = self.frame_size.saturating_add(size);
+
+        offset
+    }
 
-        (offset, size)
+    /// Returns an error if the frame of the current function, `name`,
+    /// overflowed.
+    fn check_frame_size(&self, name: &str) -> Result<(), GeneratorError> {
+        if self.frame_size == i32::MAX {
+            Err(GeneratorError::InternalError(format!(
+                "call frame of {name} is too large"
+            )))
+        } else {
+            Ok(())
+        }
     }
 
     /// Write the value that is on the top of the data stack, which has type
     /// `ty`, to the memory, at offset stored in local variable,
     /// `offset_local`, plus constant offset `offset`. Returns the number of
-    /// bytes written.
+    /// bytes written, which is always `get_type_size(ty)`, the size the host
+    /// expects.
+    ///
+    /// The bytes written are canonical: the payload of a `none` and the
+    /// unused half of a response are zeroed, whatever placeholders were on
+    /// the data stack, so that equal values are always written as identical
+    /// bytes.
     pub(crate) fn write_to_memory(
         &mut self,
         builder: &mut InstrSeqBuilder,
         offset_local: LocalId,
         offset: u32,
         ty: &TypeSignature,
+    ) -> Result<u32, GeneratorError> {
+        let written = self.write_value_to_memory(builder, offset_local, offset, ty)?;
+        check_type_size("wrote", written as i64, ty)?;
+        Ok(written)
+    }
+
+    fn write_value_to_memory(
+        &mut self,
+        builder: &mut InstrSeqBuilder,
+        offset_local: LocalId,
+        offset: u32,
+        ty: &TypeSignature,
     ) -> Result<u32, GeneratorError> {
         let memory = self.get_memory()?;
         match ty {
@@ -875,24 +1704,31 @@ impl WasmGenerator {
                         MemArg { align: 4, offset },
                     );
 
+                // The payload of a `none` is a placeholder
+                self.zero_unused_payload(
+                    builder,
+                    offset_local,
+                    offset + 4,
+                    bytes_written,
+                    variant_val,
+                    0,
+                )?;
+
                 // recursively store the inner value
                 Ok(4 + bytes_written)
             }
             TypeSignature::ResponseType(ok_err_ty) => {
                 // Data stack: TOP | err_value | ok_value | (ok|err) variant
-                let mut bytes_written = 0;
+                let ok_offset = offset + 4;
+                let err_offset = ok_offset + get_type_size(&ok_err_ty.0) as u32;
 
                 // write err value at offset + size of variant (4) + size of ok_value
-                bytes_written += self.write_to_memory(
-                    builder,
-                    offset_local,
-                    offset + 4 + get_type_size(&ok_err_ty.0) as u32,
-                    &ok_err_ty.1,
-                )?;
+                let err_size =
+                    self.write_to_memory(builder, offset_local, err_offset, &ok_err_ty.1)?;
 
                 // write ok value at offset + size of variant (4)
-                bytes_written +=
-                    self.write_to_memory(builder, offset_local, offset + 4, &ok_err_ty.0)?;
+                let ok_size =
+                    self.write_to_memory(builder, offset_local, ok_offset, &ok_err_ty.0)?;
 
                 let variant_val = self.module.locals.add(ValType::I32);
                 builder
@@ -905,7 +1741,25 @@ impl WasmGenerator {
                         MemArg { align: 4, offset },
                     );
 
-                Ok(bytes_written + 4)
+                // The half of the response that is not set is a placeholder
+                self.zero_unused_payload(
+                    builder,
+                    offset_local,
+                    ok_offset,
+                    ok_size,
+                    variant_val,
+                    0,
+                )?;
+                self.zero_unused_payload(
+                    builder,
+                    offset_local,
+                    err_offset,
+                    err_size,
+                    variant_val,
+                    1,
+                )?;
+
+                Ok(ok_size + err_size + 4)
             }
             TypeSignature::TupleType(tuple_ty) => {
                 // Data stack: TOP | last_value | value_before_last | ... | first_value
@@ -939,14 +1793,63 @@ impl WasmGenerator {
         }
     }
 
-    /// Read a value from memory at offset stored in local variable `offset`,
-    /// with type `ty`, and push it onto the top of the data stack.
-    pub(crate) fn read_from_memory(
+    /// Zero the `len` bytes at offset `offset_local` plus `offset`, holding
+    /// the payload of a variant, when the variant in `variant_val` is
+    /// `unused_variant`.
+    fn zero_unused_payload(
         &mut self,
         builder: &mut InstrSeqBuilder,
-        offset: LocalId,
-        literal_offset: u32,
-        ty: &TypeSignature,
+        offset_local: LocalId,
+        offset: u32,
+        len: u32,
+        variant_val: LocalId,
+        unused_variant: i32,
+    ) -> Result<(), GeneratorError> {
+        if len == 0 {
+            return Ok(());
+        }
+        let memory = self.get_memory()?;
+        builder
+            .local_get(variant_val)
+            .i32_const(unused_variant)
+            .binop(BinaryOp::I32Eq)
+            .if_else(
+                None,
+                |unused| {
+                    unused
+                        .local_get(offset_local)
+                        .i32_const(offset as i32)
+                        .binop(BinaryOp::I32Add)
+                        .i32_const(0)
+                        .i32_const(len as i32)
+                        .memory_fill(memory);
+                },
+                |_| {},
+            );
+        Ok(())
+    }
+
+    /// Read a value from memory at offset stored in local variable `offset`,
+    /// with type `ty`, and push it onto the top of the data stack. Returns
+    /// the number of bytes read, which is always `get_type_size(ty)`.
+    pub(crate) fn read_from_memory(
+        &mut self,
+        builder: &mut InstrSeqBuilder,
+        offset: LocalId,
+        literal_offset: u32,
+        ty: &TypeSignature,
+    ) -> Result<i32, GeneratorError> {
+        let read = self.read_value_from_memory(builder, offset, literal_offset, ty)?;
+        check_type_size("read", read as i64, ty)?;
+        Ok(read)
+    }
+
+    fn read_value_from_memory(
+        &mut self,
+        builder: &mut InstrSeqBuilder,
+        offset: LocalId,
+        literal_offset: u32,
+        ty: &TypeSignature,
     ) -> Result<i32, GeneratorError> {
         let memory = self
             .module
@@ -1229,9 +2132,10 @@ impl WasmGenerator {
 
             // If `ty` is a value that stays in memory, we can just push the
             // offset and length to the stack.
-            // CAUTION: list type needs to be dereferenced, contrarily to other
-            //          in-memory types
+            // CAUTION: list type and computed constants need to be
+            //          dereferenced, contrarily to other in-memory types
             if is_in_memory_type(&ty)
+                && !self.computed_constants.contains(name)
                 && !matches!(
                     &ty,
                     TypeSignature::SequenceType(seq) if seq.is_list_type()
@@ -1251,6 +2155,71 @@ impl WasmGenerator {
         }
     }
 
+    /// Convert the value on the top of the stack, with Clarity type `from_ty`,
+    /// into a value of type `to_ty`. Both types must have the same shape,
+    /// except that a `NoType` can be widened to any type, in which case its
+    /// placeholder is replaced by a placeholder of the target type.
+    /// Sequences are left as is, the elements they point to are not converted.
+    pub(crate) fn coerce_to_type(
+        &mut self,
+        builder: &mut InstrSeqBuilder,
+        from_ty: &TypeSignature,
+        to_ty: &TypeSignature,
+    ) -> Result<(), GeneratorError> {
+        if clar2wasm_ty(from_ty) == clar2wasm_ty(to_ty) {
+            return Ok(());
+        }
+        match (from_ty, to_ty) {
+            (TypeSignature::NoType, _) => {
+                drop_value(builder, from_ty);
+                add_placeholder_for_clarity_type(builder, to_ty);
+            }
+            (TypeSignature::OptionalType(from_inner), TypeSignature::OptionalType(to_inner)) => {
+                // The inner value is on top of the variant.
+                self.coerce_to_type(builder, from_inner, to_inner)?;
+            }
+            (TypeSignature::ResponseType(from_inner), TypeSignature::ResponseType(to_inner)) => {
+                // Set the err value aside to reach the ok value.
+                let err_locals = self.save_to_locals(builder, &from_inner.1, true);
+                self.coerce_to_type(builder, &from_inner.0, &to_inner.0)?;
+                for local in err_locals {
+                    builder.local_get(local);
+                }
+                self.coerce_to_type(builder, &from_inner.1, &to_inner.1)?;
+            }
+            (TypeSignature::TupleType(from_tuple), TypeSignature::TupleType(to_tuple))
+                if from_tuple.get_type_map().len() == to_tuple.get_type_map().len() =>
+            {
+                // Save all the fields, then push them back one by one, each
+                // converted to its target type.
+                let mut fields_locals: Vec<_> = from_tuple
+                    .get_type_map()
+                    .values()
+                    .rev()
+                    .map(|ty| self.save_to_locals(builder, ty, true))
+                    .collect();
+                fields_locals.reverse();
+                for ((from_field, to_field), locals) in from_tuple
+                    .get_type_map()
+                    .values()
+                    .zip(to_tuple.get_type_map().values())
+                    .zip(fields_locals)
+                {
+                    for local in locals {
+                        builder.local_get(local);
+                    }
+                    self.coerce_to_type(builder, from_field, to_field)?;
+                }
+            }
+            _ => {
+                return Err(GeneratorError::TypeError(format!(
+                    "cannot coerce a value of type {from_ty} to {to_ty}"
+                )))
+            }
+        }
+        Ok(())
+    }
+
     /// Save the expression on the top of the stack, with Clarity type `ty`, to
     /// local variables. If `fix_ordering` is true, then the vector is reversed
     /// so that the types are in logical order. Without this, they will be in
@@ -1279,6 +2248,53 @@ impl WasmGenerator {
         locals
     }
 
+    /// Charge `amount` units of `op` to the host before the operation that
+    /// follows. This emits nothing unless cost hooks are enabled.
+    pub(crate) fn charge_cost(&mut self, builder: &mut InstrSeqBuilder, op: CostOp, amount: i64) {
+        if let Some(charge_cost) = self.charge_cost_func() {
+            builder
+                .i32_const(op as i32)
+                .i64_const(amount)
+                .call(charge_cost);
+        }
+    }
+
+    /// Charge `op` to the host for the number of bytes in the i32 local
+    /// `bytes`, which is only known at runtime. This emits nothing unless
+    /// cost hooks are enabled.
+    pub(crate) fn charge_cost_bytes(
+        &mut self,
+        builder: &mut InstrSeqBuilder,
+        op: CostOp,
+        bytes: LocalId,
+    ) {
+        if let Some(charge_cost) = self.charge_cost_func() {
+            builder
+                .i32_const(op as i32)
+                .local_get(bytes)
+                .unop(UnaryOp::I64ExtendUI32)
+                .call(charge_cost);
+        }
+    }
+
+    /// The `charge_cost` host import, which is added to the module on first
+    /// use, or `None` if cost hooks are disabled.
+    fn charge_cost_func(&mut self) -> Option<FunctionId> {
+        if !self.config.emit_cost_hooks {
+            return None;
+        }
+        let charge_cost = match self.module.funcs.by_name("stdlib.charge_cost") {
+            Some(func) => func,
+            None => {
+                let ty = self.module.types.add(&[ValType::I32, ValType::I64], &[]);
+                let (func, _) = self.module.add_import_func("clarity", "charge_cost", ty);
+                self.module.funcs.get_mut(func).name = Some("stdlib.charge_cost".to_owned());
+                func
+            }
+        };
+        Some(charge_cost)
+    }
+
     pub fn func_by_name(&self, name: &str) -> FunctionId {
         self.module
             .funcs
@@ -1295,12 +2311,67 @@ impl WasmGenerator {
             .or(analysis.get_private_function(name))
     }
 
+    /// Check that the user-defined function `func`, called back by the
+    /// higher-order function `word` with arguments of types `arg_types`,
+    /// accepts them and, if given, returns `return_ty`. Builtin callbacks are
+    /// not checked.
+    pub(crate) fn check_callback_signature(
+        &self,
+        word: &str,
+        func: &str,
+        arg_types: &[TypeSignature],
+        return_ty: Option<&TypeSignature>,
+    ) -> Result<(), GeneratorError> {
+        let Some(FunctionType::Fixed(fixed)) = self.get_function_type(func) else {
+            return Ok(());
+        };
+        if fixed.args.len() != arg_types.len() {
+            return Err(GeneratorError::TypeError(format!(
+                "{word} callback '{func}' takes {} arguments, but {} are passed",
+                fixed.args.len(),
+                arg_types.len()
+            )));
+        }
+        for (i, (param, arg_ty)) in fixed.args.iter().zip(arg_types).enumerate() {
+            // The elements of an empty list have no type, and the function
+            // is never called with them.
+            let admitted = *arg_ty == TypeSignature::NoType
+                || matches!(
+                    param
+                        .signature
+                        .admits_type(&self.contract_analysis.epoch, arg_ty),
+                    Ok(true)
+                );
+            if !admitted {
+                return Err(GeneratorError::TypeError(format!(
+                    "{word} callback '{func}' takes {} as argument {}, but {arg_ty} is passed",
+                    param.signature,
+                    i + 1
+                )));
+            }
+        }
+        match return_ty {
+            Some(ty) if &fixed.returns != ty => Err(GeneratorError::TypeError(format!(
+                "{word} callback '{func}' returns {}, but {ty} is expected",
+                fixed.returns
+            ))),
+            _ => Ok(()),
+        }
+    }
+
     fn visit_literal_value(
         &mut self,
         builder: &mut InstrSeqBuilder,
-        _expr: &SymbolicExpression,
+        expr: &SymbolicExpression,
         value: &clarity::vm::Value,
     ) -> Result<(), GeneratorError> {
+        if let Some(kind) = unsupported_literal_kind(value) {
+            return Err(GeneratorError::TypeError(format!(
+                "unsupported {kind} at {}:{}: {value}",
+                expr.span.start_line, expr.span.start_column,
+            )));
+        }
+
         match value {
             clarity::vm::Value::Int(i) => {
                 builder.i64_const((i & 0xFFFFFFFFFFFFFFFF) as i64);
@@ -1325,14 +2396,29 @@ impl WasmGenerator {
                 builder.i32_const(len as i32);
                 Ok(())
             }
-            clarity::vm::Value::Bool(_)
-            | clarity::vm::Value::Tuple(_)
-            | clarity::vm::Value::Optional(_)
-            | clarity::vm::Value::Response(_)
-            | clarity::vm::Value::CallableContract(_)
-            | clarity::vm::Value::Sequence(_) => Err(GeneratorError::TypeError(format!(
-                "Not a valid literal type: {:?}",
-                value
+            clarity::vm::Value::Sequence(SequenceData::List(list)) => {
+                // The literal carries its own type, which is also correct for
+                // the nested list literals among its elements.
+                let elem_ty = list.type_signature.get_list_item_type();
+                let ty = TypeSignature::SequenceType(SequenceSubtype::ListType(
+                    list.type_signature.clone(),
+                ));
+
+                // Allocate space on the call stack for the entire list, and
+                // write each element into it.
+                let (offset, _size) = self.create_call_stack_local(builder, &ty, false, true);
+                let mut total_size = 0;
+                for elem in &list.data {
+                    self.visit_literal_value(builder, expr, elem)?;
+                    total_size += self.write_to_memory(builder, offset, total_size, elem_ty)?;
+                }
+
+                builder.local_get(offset).i32_const(total_size as i32);
+                Ok(())
+            }
+            // Rejected above, by `unsupported_literal_kind`.
+            _ => Err(GeneratorError::InternalError(format!(
+                "unexpected literal {value}"
             ))),
         }
     }
@@ -1343,8 +2429,12 @@ impl WasmGenerator {
         expr: &SymbolicExpression,
         atom: &ClarityName,
     ) -> Result<(), GeneratorError> {
-        // Handle builtin variables
-        if self.lookup_reserved_variable(builder, atom.as_str(), expr)? {
+        // Handle parameters and local bindings first, they shadow any other
+        // name.
+        if let Some(values) = self.bindings.get(atom.as_str()) {
+            for value in values {
+                builder.local_get(*value);
+            }
             return Ok(());
         }
 
@@ -1352,16 +2442,15 @@ impl WasmGenerator {
             return Ok(());
         }
 
-        // Handle parameters and local bindings
-        let values = self.bindings.get(atom.as_str()).ok_or_else(|| {
-            GeneratorError::InternalError(format!("unable to find local for {}", atom.as_str()))
-        })?;
-
-        for value in values {
-            builder.local_get(*value);
+        // Handle builtin variables
+        if self.lookup_reserved_variable(builder, atom.as_str(), expr)? {
+            return Ok(());
         }
 
-        Ok(())
+        Err(GeneratorError::InternalError(format!(
+            "unable to find local for {}",
+            atom.as_str()
+        )))
     }
 
     fn traverse_call_user_defined(
@@ -1371,6 +2460,21 @@ impl WasmGenerator {
         name: &ClarityName,
         args: &[SymbolicExpression],
     ) -> Result<(), GeneratorError> {
+        // WORKAROUND: the type of an argument can be less precise than the
+        // type of its parameter, e.g. `none` passed as an `(optional uint)`.
+        // Its value would then not match the Wasm types of the parameter,
+        // so we set the argument types to the parameter types.
+        if let Some(FunctionType::Fixed(function_type)) = self.get_function_type(name.as_str()) {
+            let param_types: Vec<_> = function_type
+                .args
+                .iter()
+                .map(|param| param.signature.clone())
+                .collect();
+            for (arg, ty) in args.iter().zip(param_types) {
+                self.set_expr_type(arg, ty)?;
+            }
+        }
+
         self.traverse_args(builder, args)?;
 
         let return_ty = self
@@ -1390,29 +2494,16 @@ impl WasmGenerator {
         return_ty: &TypeSignature,
         name: &ClarityName,
     ) -> Result<(), GeneratorError> {
-        if self
-            .contract_analysis
-            .get_public_function_type(name.as_str())
-            .is_some()
-        {
-            self.local_call_public(builder, return_ty, name)?;
-        } else if self
-            .contract_analysis
-            .get_read_only_function_type(name.as_str())
-            .is_some()
-        {
-            self.local_call_read_only(builder, name)?;
-        } else if self
-            .contract_analysis
-            .get_private_function(name.as_str())
-            .is_some()
-        {
-            self.local_call(builder, name)?;
-        } else {
-            return Err(GeneratorError::TypeError(format!(
-                "function not found: {name}",
-                name = name.as_str()
-            )));
+        match self.user_function_kind(name.as_str()) {
+            Some(FunctionKind::Public) => self.local_call_public(builder, return_ty, name)?,
+            Some(FunctionKind::ReadOnly) => self.local_call_read_only(builder, name)?,
+            Some(FunctionKind::Private) => self.local_call(builder, name, FunctionKind::Private)?,
+            None => {
+                return Err(GeneratorError::TypeError(format!(
+                    "function not found: {name}",
+                    name = name.as_str()
+                )))
+            }
         }
 
         // If an in-memory value is returned from a function, we need to copy
@@ -1435,6 +2526,7 @@ impl WasmGenerator {
             let memory = self.get_memory()?;
 
             // Copy the result to our frame.
+            self.charge_cost_bytes(builder, CostOp::MemoryCopy, result_length);
             builder
                 .local_get(offset)
                 .local_get(result_offset)
@@ -1448,13 +2540,57 @@ impl WasmGenerator {
         Ok(())
     }
 
+    /// Returns the kind of the function `name` defined in the current
+    /// contract, or `None` if there is no such function.
+    pub(crate) fn user_function_kind(&self, name: &str) -> Option<FunctionKind> {
+        if self
+            .contract_analysis
+            .get_public_function_type(name)
+            .is_some()
+        {
+            Some(FunctionKind::Public)
+        } else if self
+            .contract_analysis
+            .get_read_only_function_type(name)
+            .is_some()
+        {
+            Some(FunctionKind::ReadOnly)
+        } else if self.contract_analysis.get_private_function(name).is_some() {
+            Some(FunctionKind::Private)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the id of the function `name` defined in the current contract.
+    pub(crate) fn user_function_by_name(&self, name: &str) -> Result<FunctionId, GeneratorError> {
+        let kind = self
+            .user_function_kind(name)
+            .ok_or_else(|| GeneratorError::TypeError(format!("function not found: {name}")))?;
+        self.generated_function(name, kind)
+    }
+
+    /// Returns the id of the function `name` of kind `kind`, or an error if
+    /// it was not generated, e.g. because its definition failed to compile.
+    fn generated_function(
+        &self,
+        name: &str,
+        kind: FunctionKind,
+    ) -> Result<FunctionId, GeneratorError> {
+        self.module
+            .funcs
+            .by_name(&kind.internal_name(name))
+            .ok_or_else(|| GeneratorError::InternalError(format!("function not generated: {name}")))
+    }
+
     /// Call a function defined in the current contract.
     fn local_call(
         &mut self,
         builder: &mut InstrSeqBuilder,
         name: &ClarityName,
+        kind: FunctionKind,
     ) -> Result<(), GeneratorError> {
-        builder.call(self.func_by_name(name.as_str()));
+        builder.call(self.generated_function(name.as_str(), kind)?);
 
         Ok(())
     }
@@ -1470,7 +2606,7 @@ impl WasmGenerator {
         // Call the host interface function, `begin_public_call`
         builder.call(self.func_by_name("stdlib.begin_public_call"));
 
-        self.local_call(builder, name)?;
+        self.local_call(builder, name, FunctionKind::Public)?;
 
         // Save the result to a local
         let result_locals = self.save_to_locals(builder, return_ty, true);
@@ -1512,7 +2648,7 @@ impl WasmGenerator {
         // Call the host interface function, `begin_readonly_call`
         builder.call(self.func_by_name("stdlib.begin_read_only_call"));
 
-        self.local_call(builder, name)?;
+        self.local_call(builder, name, FunctionKind::ReadOnly)?;
 
         // Call the host interface function, `roll_back_call`
         builder.call(self.func_by_name("stdlib.roll_back_call"));
@@ -1559,42 +2695,173 @@ impl WasmGenerator {
         &self,
         sequence: &SymbolicExpression,
     ) -> Result<SequenceElementType, GeneratorError> {
-        match self.get_expr_type(sequence).ok_or_else(|| {
-            GeneratorError::TypeError("sequence expression must be typed".to_owned())
-        })? {
-            TypeSignature::SequenceType(seq_ty) => match &seq_ty {
-                SequenceSubtype::ListType(list_type) => Ok(SequenceElementType::Other(
-                    list_type.get_list_item_type().clone(),
-                )),
-                SequenceSubtype::BufferType(_)
-                | SequenceSubtype::StringType(StringSubtype::ASCII(_)) => {
-                    // For buffer and string-ascii return none, which indicates
-                    // that elements should be read byte-by-byte.
-                    Ok(SequenceElementType::Byte)
-                }
-                SequenceSubtype::StringType(StringSubtype::UTF8(_)) => {
-                    Ok(SequenceElementType::UnicodeScalar)
-                }
-            },
-            _ => Err(GeneratorError::TypeError(
-                "expected sequence type".to_string(),
-            )),
+        self.get_expr_type(sequence)
+            .ok_or_else(|| {
+                GeneratorError::TypeError("sequence expression must be typed".to_owned())
+            })
+            .and_then(SequenceElementType::from_sequence_type)
+    }
+
+    /// Traverse `sequence` and save its bounds to locals, so that its
+    /// elements can be iterated over with [`Self::emit_sequence_loop`].
+    pub(crate) fn traverse_sequence_iter(
+        &mut self,
+        builder: &mut InstrSeqBuilder,
+        sequence: &SymbolicExpression,
+    ) -> Result<SequenceIter, GeneratorError> {
+        let elem_ty = self.get_sequence_element_type(sequence)?;
+        self.traverse_expr(builder, sequence)?;
+        // STACK: [offset, length]
+
+        // The end is computed from the runtime length of the sequence, which
+        // can be shorter than the maximum length of its type.
+        let offset = self.module.locals.add(ValType::I32);
+        let end = self.module.locals.add(ValType::I32);
+        builder
+            .local_set(end)
+            .local_tee(offset)
+            .local_get(end)
+            .binop(BinaryOp::I32Add)
+            .local_set(end);
+
+        Ok(SequenceIter {
+            offset,
+            end,
+            elem_ty,
+        })
+    }
+
+    /// Push the current element of `iter` onto the stack. Elements which are
+    /// in memory themselves are pushed as their offset and length.
+    pub(crate) fn load_sequence_element(
+        &mut self,
+        builder: &mut InstrSeqBuilder,
+        iter: &SequenceIter,
+    ) -> Result<(), GeneratorError> {
+        match &iter.elem_ty {
+            SequenceElementType::Other(elem_ty) => {
+                self.read_from_memory(builder, iter.offset, 0, elem_ty)?;
+            }
+            SequenceElementType::Byte | SequenceElementType::UnicodeScalar => {
+                builder
+                    .local_get(iter.offset)
+                    .i32_const(iter.elem_ty.size());
+            }
         }
+        Ok(())
+    }
+
+    /// Emit a loop over the elements of the sequences `iters`, which runs
+    /// once per element of the shortest sequence, and not at all if it is
+    /// empty. `body` is emitted in the loop, with the offsets of `iters` on
+    /// the current elements, and they are moved to the next elements after
+    /// it.
+    pub(crate) fn emit_sequence_loop<F>(
+        &mut self,
+        builder: &mut InstrSeqBuilder,
+        iters: &[SequenceIter],
+        mut body: F,
+    ) -> Result<(), GeneratorError>
+    where
+        F: FnMut(&mut WasmGenerator, &mut InstrSeqBuilder) -> Result<(), GeneratorError>,
+    {
+        if iters.is_empty() {
+            return Err(GeneratorError::InternalError(
+                "sequence loop without sequences".to_owned(),
+            ));
+        }
+
+        // The loop_exit block skips the loop if any of the sequences is
+        // empty. The end of the sequences is then only checked after the body,
+        // so that an iteration takes a single branch.
+        let mut loop_exit = builder.dangling_instr_seq(None);
+        let loop_exit_id = loop_exit.id();
+        for iter in iters {
+            loop_exit
+                .local_get(iter.offset)
+                .local_get(iter.end)
+                .binop(BinaryOp::I32GeU)
+                .br_if(loop_exit_id);
+        }
+
+        let mut loop_ = loop_exit.dangling_instr_seq(None);
+        let loop_id = loop_.id();
+
+        body(self, &mut loop_)?;
+
+        // Move to the next elements, and loop back to the top if none of the
+        // sequences has ended. The offsets are the only induction variables,
+        // the ends and element sizes are invariant.
+        for (i, iter) in iters.iter().enumerate() {
+            loop_
+                .local_get(iter.offset)
+                .i32_const(iter.elem_ty.size())
+                .binop(BinaryOp::I32Add)
+                .local_tee(iter.offset)
+                .local_get(iter.end)
+                .binop(BinaryOp::I32LtU);
+            if i > 0 {
+                loop_.binop(BinaryOp::I32And);
+            }
+        }
+        loop_.br_if(loop_id);
+
+        loop_exit.instr(walrus::ir::Loop { seq: loop_id });
+        builder.instr(walrus::ir::Block { seq: loop_exit_id });
+
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod misc_tests {
     use std::env;
-
-    use walrus::Module;
+    use std::panic::{self, AssertUnwindSafe};
+
+    use clarity::vm::analysis::ContractAnalysis;
+    use clarity::vm::clarity_wasm::get_type_size;
+    use clarity::vm::representations::Span;
+    use clarity::vm::types::signatures::{BufferLength, StringUTF8Length};
+    use clarity::vm::types::{SequenceSubtype, StringSubtype, TypeSignature};
+    use clarity::vm::{SymbolicExpression, SymbolicExpressionType};
+    use proptest::prelude::*;
+    use walrus::ir::{
+        dfs_in_order, BinaryOp, Binop, Const, GlobalSet, Instr, InstrSeq, Value, Visitor,
+    };
+    use walrus::{FunctionBuilder, GlobalId, Module, ValType};
 
     // Tests that don't relate to specific words
     use crate::{
-        tools::{crosscheck, evaluate},
-        wasm_generator::END_OF_STANDARD_DATA,
+        tools::{
+            compile_snippet, compile_snippet_with_config, crosscheck, evaluate, evaluate_mutated,
+            for_each_expr_mut, generate_mutated, generation_error, instantiate,
+        },
+        wasm_generator::{
+            add_placeholder_for_clarity_type, check_required_functions, clar2wasm_ty,
+            sequence_element_type, FunctionKind, GeneratorError, RuntimeError, WasmGenerator,
+            WasmGeneratorConfig, END_OF_STANDARD_DATA, SELF_TEST_EXPORT,
+        },
     };
 
+    fn compile_to_bytes(snippet: &str) -> Vec<u8> {
+        compile_snippet(snippet).module.emit_wasm()
+    }
+
+    /// Traverse the top-level statements of `analysis` without finishing the
+    /// module, so that the generator state can be inspected.
+    fn traverse_top_level(analysis: ContractAnalysis) -> WasmGenerator {
+        let mut generator = WasmGenerator::new(analysis).expect("failed to create generator");
+        let expressions = std::mem::take(&mut generator.contract_analysis.expressions);
+        let mut top_level = FunctionBuilder::new(&mut generator.module.types, &[], &[]);
+        generator
+            .register_top_level_definitions(&expressions)
+            .expect("failed to register top-level definitions");
+        generator
+            .traverse_statement_list(&mut top_level.func_body(), &expressions)
+            .expect("failed to traverse top-level statements");
+        generator
+    }
+
     #[test]
     fn is_in_mainnet() {
         crosscheck(
@@ -1633,6 +2900,457 @@ mod misc_tests {
         crosscheck(&snippet, Ok(Some(clarity::vm::Value::Bool(false))));
     }
 
+    #[test]
+    fn buffer_literal_larger_than_a_page() {
+        let data: Vec<u8> = (0..70 * 1024).map(|i| (i % 251) as u8).collect();
+        let hex: String = data.iter().map(|b| format!("{b:02x}")).collect();
+        let snippet = format!("(slice? 0x{hex} u{} u{})", data.len() - 3, data.len());
+
+        let mut generator =
+            WasmGenerator::new(compile_snippet(&snippet).contract_analysis).unwrap();
+        let offset = generator
+            .add_literal(&clarity::vm::Value::buff_from(data.clone()).unwrap())
+            .unwrap()
+            .0;
+        let memory = generator.get_memory().unwrap();
+        assert!(
+            generator.module.memories.get(memory).initial * 64 * 1024 >= offset + data.len() as u32
+        );
+
+        crosscheck(
+            &snippet,
+            Ok(Some(
+                clarity::vm::Value::some(
+                    clarity::vm::Value::buff_from(data[data.len() - 3..].to_vec()).unwrap(),
+                )
+                .unwrap(),
+            )),
+        );
+    }
+
+    #[test]
+    fn literal_memory_beyond_i32_is_an_error() {
+        let mut generator = WasmGenerator::new(compile_snippet("").contract_analysis).unwrap();
+        generator.literal_memory_end = i32::MAX as u32 - 1;
+        let err = generator
+            .add_literal(&clarity::vm::Value::buff_from(vec![1, 2]).unwrap())
+            .expect_err("the literal should not fit below i32::MAX");
+        assert!(matches!(err, GeneratorError::InternalError(msg) if msg.contains("2GiB")));
+
+        generator.literal_memory_end = i32::MAX as u32 + 1;
+        let err = generator
+            .set_memory_pages()
+            .expect_err("the stack pointer should not overflow");
+        assert!(matches!(err, GeneratorError::InternalError(msg) if msg.contains("2GiB")));
+    }
+
+    #[test]
+    fn identical_literals_share_memory_across_functions() {
+        let snippet = r#"
+(define-constant owner 'S1G2081040G2081040G2081040G208105NK8PE5)
+(define-private (first) (if (is-eq tx-sender owner) (err "error") (ok 0x0102030405)))
+(define-read-only (second) (if (is-eq tx-sender owner) (ok 0x0102030405) (err "error")))
+(list (first) (second))
+"#;
+        let module = WasmGenerator::new(compile_snippet(snippet).contract_analysis)
+            .expect("failed to create generator")
+            .generate()
+            .expect("failed to generate module");
+        for literal in [b"error".as_slice(), &[1, 2, 3, 4, 5]] {
+            assert_eq!(
+                module
+                    .data
+                    .iter()
+                    .filter(|data| data.value == literal)
+                    .count(),
+                1,
+                "{literal:?} should be in a single data segment"
+            );
+        }
+
+        crosscheck(
+            snippet,
+            evaluate(r#"(list (err "error") (ok 0x0102030405))"#),
+        );
+    }
+
+    #[test]
+    fn principal_literals_are_written_to_memory() {
+        use clarity::vm::types::PrincipalData;
+
+        let standard = "S1G2081040G2081040G2081040G208105NK8PE5";
+        let contract = format!("{standard}.my-contract");
+        let snippet = format!(
+            "
+(define-read-only (standard) '{standard})
+(define-read-only (contract) '{contract})
+"
+        );
+        let (mut store, instance) = instantiate(&mut compile_snippet(&snippet).module, (), |_| {});
+        let memory = instance.get_memory(&mut store, "memory").unwrap();
+
+        for (name, principal) in [("standard", standard), ("contract", contract.as_str())] {
+            // Version, hash160 and length-prefixed contract name.
+            let mut expected = vec![];
+            match PrincipalData::parse(principal).unwrap() {
+                PrincipalData::Standard(standard) => {
+                    expected.push(standard.0);
+                    expected.extend_from_slice(&standard.1);
+                    expected.push(0);
+                }
+                PrincipalData::Contract(contract) => {
+                    expected.push(contract.issuer.0);
+                    expected.extend_from_slice(&contract.issuer.1);
+                    expected.push(u8::try_from(contract.name.as_bytes().len()).unwrap());
+                    expected.extend_from_slice(contract.name.as_bytes());
+                }
+            }
+
+            let func = instance.get_func(&mut store, name).unwrap();
+            let mut results = [wasmtime::Val::I32(0), wasmtime::Val::I32(0)];
+            func.call(&mut store, &[], &mut results).unwrap();
+            let mut bytes = vec![0; results[1].unwrap_i32() as usize];
+            memory
+                .read(&store, results[0].unwrap_i32() as usize, &mut bytes)
+                .unwrap();
+            assert_eq!(bytes, expected, "{name}");
+        }
+
+        crosscheck(
+            &format!("{snippet} (list (standard) (contract))"),
+            evaluate(&format!("(list '{standard} '{contract})")),
+        );
+    }
+
+    /// Remove the `n`-th expression of `analysis`, in pre-order, from the
+    /// type map by giving it an unknown id. Returns `false` if there are
+    /// fewer expressions.
+    fn untype_nth_expr(analysis: &mut ContractAnalysis, n: usize) -> bool {
+        let mut index = 0;
+        for_each_expr_mut(&mut analysis.expressions, &mut |expr| {
+            if index == n {
+                expr.id = u64::MAX;
+            }
+            index += 1;
+        });
+        index > n
+    }
+
+    #[test]
+    fn untyped_expression_is_an_error() {
+        let err = generation_error(r#"(concat "a" "b")"#, |analysis| {
+            assert!(untype_nth_expr(analysis, 0));
+        });
+        assert!(matches!(err, GeneratorError::TypeError(_)));
+    }
+
+    #[test]
+    fn untyped_expressions_do_not_panic() {
+        let snippet = r#"
+(define-data-var counter uint u0)
+(define-map names uint (string-ascii 8))
+(define-private (bump (step uint))
+  (let ((next (+ (var-get counter) step)))
+    (var-set counter next)
+    (ok next)))
+(map-set names u1 "one")
+(list
+  (match (bump u2) value (some value) error none)
+  (map-get? names u1)
+  (fold + (list u1 u2) u0))
+"#;
+        for n in 0.. {
+            let mut found = true;
+            // Generation may or may not need the missing type, but must not
+            // panic.
+            let _ = generate_mutated(snippet, |analysis| found = untype_nth_expr(analysis, n));
+            if !found {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn error_of_operand_is_not_masked_by_arithmetic() {
+        // Rename the called function after the analysis, so that it is only
+        // unknown to the generator.
+        let err = generation_error("(define-private (f) u2) (+ u1 (f))", |analysis| {
+            for_each_expr_mut(&mut analysis.expressions[1..], &mut |expr| {
+                if expr.match_atom().map_or(false, |name| name.as_str() == "f") {
+                    expr.expr = SymbolicExpressionType::Atom("unsupported-fn".into());
+                }
+            });
+        });
+        assert!(
+            matches!(&err, GeneratorError::TypeError(msg) if msg.contains("unsupported-fn")),
+            "unexpected error: {err:?}"
+        );
+    }
+
+    #[test]
+    fn list_literals_are_generated() {
+        let list = |items: Vec<clarity::vm::Value>| {
+            clarity::vm::Value::cons_list_unsanitized(items).unwrap()
+        };
+        let int_list =
+            |items: &[i128]| list(items.iter().map(|i| clarity::vm::Value::Int(*i)).collect());
+        // Each `list` call is replaced by a literal with other values, of the
+        // same type, so the result shows that the literal was emitted.
+        let cases = [
+            ("(fold + (list 4 5 6) 0)", int_list(&[1, 2, 3]), "6"),
+            ("(list 4 5 6)", int_list(&[1, 2, 3]), "(list 1 2 3)"),
+            (
+                r#"(list "x" "yz")"#,
+                list(vec![
+                    clarity::vm::Value::string_ascii_from_bytes(b"a".to_vec()).unwrap(),
+                    clarity::vm::Value::string_ascii_from_bytes(b"bc".to_vec()).unwrap(),
+                ]),
+                r#"(list "a" "bc")"#,
+            ),
+            (
+                "(list (list 7 8) (list 9))",
+                list(vec![int_list(&[1, 2]), int_list(&[3])]),
+                "(list (list 1 2) (list 3))",
+            ),
+        ];
+
+        for (snippet, value, expected) in cases {
+            let result = evaluate_mutated(snippet, |analysis| {
+                // Replace the outermost call to `list`.
+                let mut replaced = false;
+                for_each_expr_mut(&mut analysis.expressions, &mut |expr| {
+                    let is_list_call = expr
+                        .match_list()
+                        .and_then(|list| list.first())
+                        .and_then(|head| head.match_atom())
+                        .map_or(false, |name| name.as_str() == "list");
+                    if is_list_call && !replaced {
+                        expr.expr = SymbolicExpressionType::LiteralValue(value.clone());
+                        replaced = true;
+                    }
+                });
+                assert!(replaced);
+            })
+            .unwrap_or_else(|err| panic!("{snippet} should run: {err:?}"));
+            assert_eq!(result, evaluate(expected).unwrap(), "{snippet}");
+        }
+    }
+
+    #[test]
+    fn private_function_returns_sequence_from_its_frame() {
+        let preamble = r#"
+(define-private (ab) (concat "a" "b"))
+(define-private (bytes) (concat 0x01 0x0203))
+(define-private (numbers) (concat (list 1 2) (list 3)))
+"#;
+        // The results must survive the frames of later calls.
+        crosscheck(
+            &format!(r#"{preamble} (let ((x (ab)) (y (concat "c" "d"))) (concat x y))"#),
+            evaluate(r#""abcd""#),
+        );
+        crosscheck(
+            &format!("{preamble} (let ((x (bytes)) (y (bytes))) (concat x y))"),
+            evaluate("0x010203010203"),
+        );
+        crosscheck(
+            &format!("{preamble} (let ((x (numbers)) (y (numbers))) (concat x y))"),
+            evaluate("(list 1 2 3 1 2 3)"),
+        );
+    }
+
+    #[test]
+    fn private_function_returns_composite_referencing_its_frame() {
+        crosscheck(
+            r#"
+(define-private (wrap (s (string-ascii 4))) (some (concat s "!")))
+(let ((x (wrap "a")) (y (wrap "bc"))) (list x y))
+"#,
+            evaluate(r#"(list (some "a!") (some "bc!"))"#),
+        );
+    }
+
+    #[test]
+    fn memory_sizes_match_type_sizes() {
+        let tuple_ty = TypeSignature::TupleType(
+            vec![
+                ("a".into(), TypeSignature::IntType),
+                ("b".into(), TypeSignature::BoolType),
+                ("c".into(), TypeSignature::PrincipalType),
+            ]
+            .try_into()
+            .unwrap(),
+        );
+        let types = [
+            TypeSignature::IntType,
+            TypeSignature::UIntType,
+            TypeSignature::BoolType,
+            TypeSignature::PrincipalType,
+            TypeSignature::SequenceType(SequenceSubtype::BufferType(
+                BufferLength::try_from(16u32).unwrap(),
+            )),
+            TypeSignature::new_option(TypeSignature::IntType).unwrap(),
+            TypeSignature::new_response(TypeSignature::BoolType, tuple_ty.clone()).unwrap(),
+            TypeSignature::list_of(tuple_ty.clone(), 3).unwrap(),
+            tuple_ty,
+        ];
+
+        let mut generator = WasmGenerator::new(compile_snippet("").contract_analysis).unwrap();
+        let mut function = FunctionBuilder::new(&mut generator.module.types, &[], &[]);
+        let offset = generator.module.locals.add(ValType::I32);
+        for ty in types {
+            let size = get_type_size(&ty);
+            let mut builder = function.func_body();
+            assert_eq!(
+                generator
+                    .write_to_memory(&mut builder, offset, 0, &ty)
+                    .unwrap(),
+                size as u32,
+                "{ty}"
+            );
+            assert_eq!(
+                generator
+                    .read_from_memory(&mut builder, offset, 0, &ty)
+                    .unwrap(),
+                size,
+                "{ty}"
+            );
+        }
+    }
+
+    /// Sources of the integers used to build wasm values: the first one for
+    /// the live parts of a value, the second one for its placeholders.
+    type ValueSources = [std::iter::Cycle<std::vec::IntoIter<i64>>; 2];
+
+    /// Push the wasm values of a value of type `ty` to `values`. The live
+    /// parts of the value are taken from `sources[0]`, and its placeholders,
+    /// such as the payload of a `none`, from `sources[1]`.
+    fn wasm_values(
+        ty: &TypeSignature,
+        live: bool,
+        sources: &mut ValueSources,
+        values: &mut Vec<wasmtime::Val>,
+    ) {
+        let next =
+            |sources: &mut ValueSources| sources[usize::from(!live)].next().unwrap_or_default();
+        match ty {
+            TypeSignature::IntType | TypeSignature::UIntType => {
+                values.push(wasmtime::Val::I64(next(sources)));
+                values.push(wasmtime::Val::I64(next(sources)));
+            }
+            TypeSignature::BoolType => values.push(wasmtime::Val::I32(next(sources) as i32)),
+            TypeSignature::PrincipalType | TypeSignature::SequenceType(_) => {
+                values.push(wasmtime::Val::I32(next(sources) as i32));
+                values.push(wasmtime::Val::I32(next(sources) as i32));
+            }
+            TypeSignature::OptionalType(some_ty) => {
+                let variant = next(sources) & 1;
+                values.push(wasmtime::Val::I32(variant as i32));
+                wasm_values(some_ty, live && variant == 1, sources, values);
+            }
+            TypeSignature::ResponseType(ok_err_ty) => {
+                let variant = next(sources) & 1;
+                values.push(wasmtime::Val::I32(variant as i32));
+                wasm_values(&ok_err_ty.0, live && variant == 1, sources, values);
+                wasm_values(&ok_err_ty.1, live && variant == 0, sources, values);
+            }
+            TypeSignature::TupleType(tuple_ty) => {
+                for field_ty in tuple_ty.get_type_map().values() {
+                    wasm_values(field_ty, live, sources, values);
+                }
+            }
+            _ => panic!("unexpected type {ty}"),
+        }
+    }
+
+    /// Write the wasm `values` of a value of type `ty` to memory, over bytes
+    /// set to `0xff`, and return the bytes written.
+    fn written_bytes(ty: &TypeSignature, values: &[wasmtime::Val]) -> Vec<u8> {
+        let mut generator = WasmGenerator::new(compile_snippet("").contract_analysis).unwrap();
+        let params = clar2wasm_ty(ty);
+        let mut function = FunctionBuilder::new(&mut generator.module.types, &params, &[]);
+        let args: Vec<_> = params
+            .iter()
+            .map(|param| generator.module.locals.add(*param))
+            .collect();
+        let address = generator.literal_memory_end;
+        let offset = generator.module.locals.add(ValType::I32);
+        let mut builder = function.func_body();
+        builder.i32_const(address as i32).local_set(offset);
+        for arg in &args {
+            builder.local_get(*arg);
+        }
+        generator
+            .write_to_memory(&mut builder, offset, 0, ty)
+            .unwrap();
+        let write = function.finish(args, &mut generator.module.funcs);
+        generator.module.exports.add("write", write);
+
+        let (mut store, instance) = instantiate(&mut generator.module, (), |_| {});
+        let memory = instance.get_memory(&mut store, "memory").unwrap();
+        let range = address as usize..address as usize + get_type_size(ty) as usize;
+        memory.data_mut(&mut store)[range.clone()].fill(0xff);
+        instance
+            .get_func(&mut store, "write")
+            .unwrap()
+            .call(&mut store, values, &mut [])
+            .unwrap();
+        memory.data(&store)[range].to_vec()
+    }
+
+    fn composite_types() -> Vec<TypeSignature> {
+        let tuple_ty = TypeSignature::TupleType(
+            vec![
+                ("a".into(), TypeSignature::IntType),
+                (
+                    "b".into(),
+                    TypeSignature::new_option(TypeSignature::BoolType).unwrap(),
+                ),
+                ("c".into(), TypeSignature::PrincipalType),
+            ]
+            .try_into()
+            .unwrap(),
+        );
+        let optional_int = TypeSignature::new_option(TypeSignature::IntType).unwrap();
+        vec![
+            optional_int.clone(),
+            TypeSignature::new_option(optional_int.clone()).unwrap(),
+            TypeSignature::new_option(tuple_ty.clone()).unwrap(),
+            TypeSignature::new_response(optional_int, tuple_ty.clone()).unwrap(),
+            TypeSignature::new_response(
+                TypeSignature::BoolType,
+                TypeSignature::new_response(TypeSignature::UIntType, tuple_ty.clone()).unwrap(),
+            )
+            .unwrap(),
+            tuple_ty,
+        ]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn equal_values_are_written_as_identical_bytes(
+            ty in prop::sample::select(composite_types()),
+            live in prop::collection::vec(any::<i64>(), 1..32),
+            first in prop::collection::vec(any::<i64>(), 1..32),
+            second in prop::collection::vec(any::<i64>(), 1..32),
+        ) {
+            let values = |placeholders: &[i64]| {
+                let mut sources = [
+                    live.clone().into_iter().cycle(),
+                    placeholders.to_vec().into_iter().cycle(),
+                ];
+                let mut values = Vec::new();
+                wasm_values(&ty, true, &mut sources, &mut values);
+                values
+            };
+            prop_assert_eq!(
+                written_bytes(&ty, &values(&first)),
+                written_bytes(&ty, &values(&second))
+            );
+        }
+    }
+
     #[test]
     fn end_of_standard_data_is_correct() {
         const STANDARD_LIB_PATH: &str =
@@ -1643,4 +3361,719 @@ mod misc_tests {
 
         assert!((initial_data_size as u32) == END_OF_STANDARD_DATA);
     }
+
+    #[test]
+    fn compilation_is_reproducible() {
+        let snippet = r#"
+(define-constant greeting "hello")
+(define-data-var counter uint u0)
+(define-map balances principal uint)
+(define-private (add (a uint) (b uint))
+  (+ a b))
+(define-public (bump)
+  (begin
+    (var-set counter (add (var-get counter) u1))
+    (map-set balances tx-sender (var-get counter))
+    (ok greeting)))
+(define-read-only (greet (name (string-ascii 10)))
+  (concat greeting name))
+(bump)
+"#;
+        assert_eq!(compile_to_bytes(snippet), compile_to_bytes(snippet));
+    }
+
+    #[test]
+    fn identifiers_are_in_stable_order() {
+        let snippet = "
+(define-constant zeta u3)
+(define-constant alpha u1)
+(define-data-var zulu uint u0)
+(define-data-var bravo bool true)
+(define-data-var mike int 0)
+";
+        for _ in 0..3 {
+            let generator = traverse_top_level(compile_snippet(snippet).contract_analysis);
+            let constants: Vec<_> = generator.constants.keys().cloned().collect();
+            let datavars: Vec<_> = generator
+                .datavars_types
+                .keys()
+                .map(|name| name.to_string())
+                .collect();
+            assert_eq!(constants, vec!["zeta", "alpha"]);
+            assert_eq!(datavars, vec!["zulu", "bravo", "mike"]);
+        }
+    }
+
+    fn buff(len: u32) -> TypeSignature {
+        TypeSignature::SequenceType(SequenceSubtype::BufferType(
+            BufferLength::try_from(len).unwrap(),
+        ))
+    }
+
+    fn string_ascii(len: u32) -> TypeSignature {
+        TypeSignature::SequenceType(SequenceSubtype::StringType(StringSubtype::ASCII(
+            BufferLength::try_from(len).unwrap(),
+        )))
+    }
+
+    fn string_utf8(len: u32) -> TypeSignature {
+        TypeSignature::SequenceType(SequenceSubtype::StringType(StringSubtype::UTF8(
+            StringUTF8Length::try_from(len).unwrap(),
+        )))
+    }
+
+    #[test]
+    fn sequence_element_type_of_list() {
+        let ty = TypeSignature::list_of(TypeSignature::IntType, 5).unwrap();
+        assert_eq!(sequence_element_type(&ty), Some(TypeSignature::IntType));
+
+        let nested = TypeSignature::list_of(buff(3), 2).unwrap();
+        assert_eq!(sequence_element_type(&nested), Some(buff(3)));
+    }
+
+    #[test]
+    fn sequence_element_type_of_buffer() {
+        assert_eq!(sequence_element_type(&buff(32)), Some(buff(1)));
+    }
+
+    #[test]
+    fn sequence_element_type_of_string_ascii() {
+        assert_eq!(
+            sequence_element_type(&string_ascii(10)),
+            Some(string_ascii(1))
+        );
+    }
+
+    #[test]
+    fn sequence_element_type_of_string_utf8() {
+        assert_eq!(
+            sequence_element_type(&string_utf8(10)),
+            Some(string_utf8(1))
+        );
+    }
+
+    #[test]
+    fn sequence_element_type_of_non_sequence() {
+        assert_eq!(sequence_element_type(&TypeSignature::UIntType), None);
+        assert_eq!(sequence_element_type(&TypeSignature::BoolType), None);
+    }
+
+    /// A minimal standard library, with only a memory and the
+    /// `stack-pointer` global.
+    const MINIMAL_STANDARD_LIB: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x05, 0x03, 0x01, 0x00, 0x01, // memory section: (memory 1)
+        0x06, 0x06, 0x01, 0x7f, 0x01, 0x41, 0x00, 0x0b, // global section: (mut i32) = 0
+        0x00, 0x17, 0x04, b'n', b'a', b'm', b'e', // custom "name" section
+        0x07, 0x10, 0x01, 0x00, 0x0d, // global names: global 0 is
+        b's', b't', b'a', b'c', b'k', b'-', b'p', b'o', b'i', b'n', b't', b'e', b'r',
+    ];
+
+    #[test]
+    fn with_minimal_standard_lib() {
+        let analysis = compile_snippet("42").contract_analysis;
+        let module = WasmGenerator::with_standard_lib(analysis, MINIMAL_STANDARD_LIB)
+            .expect("failed to create generator")
+            .generate()
+            .expect("failed to generate module");
+        assert!(module
+            .exports
+            .iter()
+            .any(|export| export.name == ".top-level"));
+    }
+
+    #[test]
+    fn custom_top_level_export() {
+        let config = WasmGeneratorConfig {
+            top_level_export: Some("init".to_owned()),
+            ..Default::default()
+        };
+        let module = compile_snippet_with_config("42", config).module;
+        assert!(module.exports.iter().any(|export| export.name == "init"));
+        assert!(module
+            .exports
+            .iter()
+            .all(|export| export.name != ".top-level"));
+    }
+
+    #[test]
+    fn unexported_top_level() {
+        let config = WasmGeneratorConfig {
+            top_level_export: None,
+            ..Default::default()
+        };
+        let module = compile_snippet_with_config("42", config).module;
+        assert!(module
+            .exports
+            .iter()
+            .all(|export| export.name != ".top-level"));
+    }
+
+    #[test]
+    fn with_standard_lib_missing_stack_pointer() {
+        let analysis = compile_snippet("42").contract_analysis;
+        let mut module = Module::default();
+        module.memories.add_local(false, 1, None);
+
+        let err = WasmGenerator::with_standard_lib(analysis, &module.emit_wasm())
+            .err()
+            .expect("standard library without a stack-pointer should be rejected");
+        assert!(matches!(err, GeneratorError::InternalError(msg) if msg.contains("stack-pointer")));
+    }
+
+    #[test]
+    fn with_standard_lib_missing_function() {
+        let mut module = Module::from_buffer(include_bytes!("standard/standard.wasm")).unwrap();
+        assert!(check_required_functions(&module).is_ok());
+
+        let runtime_error = module.funcs.by_name("stdlib.runtime-error").unwrap();
+        module.funcs.get_mut(runtime_error).name = None;
+        let err = check_required_functions(&module)
+            .expect_err("standard library without runtime-error should be rejected");
+        assert!(
+            matches!(err, GeneratorError::InternalError(msg) if msg.contains("stdlib.runtime-error"))
+        );
+    }
+
+    #[test]
+    fn with_standard_lib_invalid_wasm() {
+        let analysis = compile_snippet("42").contract_analysis;
+        let err = WasmGenerator::with_standard_lib(analysis, b"not wasm")
+            .err()
+            .expect("invalid standard library should be rejected");
+        assert!(matches!(err, GeneratorError::InternalError(_)));
+    }
+
+    #[test]
+    fn placeholder_matches_wasm_types() {
+        let tuple = TypeSignature::TupleType(
+            vec![
+                ("a".into(), TypeSignature::UIntType),
+                ("b".into(), string_ascii(5)),
+                ("c".into(), TypeSignature::BoolType),
+            ]
+            .try_into()
+            .unwrap(),
+        );
+        let types = [
+            TypeSignature::NoType,
+            TypeSignature::IntType,
+            TypeSignature::UIntType,
+            TypeSignature::BoolType,
+            TypeSignature::PrincipalType,
+            buff(10),
+            string_ascii(10),
+            string_utf8(10),
+            TypeSignature::list_of(TypeSignature::IntType, 3).unwrap(),
+            TypeSignature::new_option(string_ascii(3)).unwrap(),
+            TypeSignature::new_response(buff(2), TypeSignature::IntType).unwrap(),
+            TypeSignature::new_response(
+                TypeSignature::UIntType,
+                TypeSignature::list_of(TypeSignature::UIntType, 3).unwrap(),
+            )
+            .unwrap(),
+            TypeSignature::new_option(
+                TypeSignature::new_response(tuple.clone(), string_utf8(2)).unwrap(),
+            )
+            .unwrap(),
+            tuple,
+        ];
+
+        for ty in types {
+            let mut module = Module::default();
+            let mut function = FunctionBuilder::new(&mut module.types, &[], &[]);
+            let mut builder = function.func_body();
+            add_placeholder_for_clarity_type(&mut builder, &ty);
+
+            let pushed: Vec<_> = builder
+                .instrs()
+                .iter()
+                .map(|(instr, _)| match instr {
+                    Instr::Const(c) => match c.value {
+                        Value::I32(0) => ValType::I32,
+                        Value::I64(0) => ValType::I64,
+                        _ => panic!("unexpected placeholder value for {ty}"),
+                    },
+                    _ => panic!("unexpected placeholder instruction for {ty}"),
+                })
+                .collect();
+            assert_eq!(pushed, clar2wasm_ty(&ty), "placeholder for {ty}");
+        }
+    }
+
+    #[test]
+    fn err_placeholder_of_list_type() {
+        // The err half of `(ok u1)` is a `(0, 0)` placeholder for the list.
+        let mut module = Module::default();
+        let mut function = FunctionBuilder::new(&mut module.types, &[], &[]);
+        let mut builder = function.func_body();
+        add_placeholder_for_clarity_type(
+            &mut builder,
+            &TypeSignature::list_of(TypeSignature::UIntType, 3).unwrap(),
+        );
+        let pushed: Vec<_> = builder
+            .instrs()
+            .iter()
+            .map(|(instr, _)| match instr {
+                Instr::Const(c) => c.value,
+                _ => panic!("unexpected placeholder instruction"),
+            })
+            .collect();
+        assert!(matches!(pushed[..], [Value::I32(0), Value::I32(0)]));
+
+        let snippet = "
+(define-read-only (list-err (fail bool))
+  (if fail (err (list u1 u2 u3)) (ok u1)))
+";
+        crosscheck(&format!("{snippet} (list-err false)"), evaluate("(ok u1)"));
+        crosscheck(
+            &format!("{snippet} (match (list-err false) value (list value) error error)"),
+            evaluate("(list u1)"),
+        );
+        crosscheck(
+            &format!("{snippet} (unwrap-err-panic (list-err true))"),
+            evaluate("(list u1 u2 u3)"),
+        );
+    }
+
+    #[test]
+    fn generator_is_usable_after_failed_function() {
+        let mut analysis = compile_snippet(
+            "
+(define-private (bad (a int)) (+ a 1))
+(define-private (good (a int)) (- a 1))
+",
+        )
+        .contract_analysis;
+        let expressions = std::mem::take(&mut analysis.expressions);
+        let body = |index: usize| -> SymbolicExpression {
+            expressions[index].match_list().unwrap()[2].clone()
+        };
+
+        // Break the body of `bad` by calling a function that does not exist.
+        let mut bad_body = body(0);
+        if let SymbolicExpressionType::List(call) = &mut bad_body.expr {
+            call[0].expr = SymbolicExpressionType::Atom("missing".into());
+        }
+
+        let mut generator = WasmGenerator::new(analysis).unwrap();
+        let mut top_level = FunctionBuilder::new(&mut generator.module.types, &[], &[]);
+
+        generator
+            .traverse_define_function(
+                &mut top_level.func_body(),
+                &"bad".into(),
+                &bad_body,
+                FunctionKind::Private,
+            )
+            .expect_err("`bad` should not compile");
+
+        // The top-level state was restored
+        assert!(generator.bindings.is_empty());
+        assert!(generator.return_type.is_none());
+        assert!(generator.early_return_block_id.is_none());
+
+        generator
+            .traverse_define_function(
+                &mut top_level.func_body(),
+                &"good".into(),
+                &body(1),
+                FunctionKind::Private,
+            )
+            .expect("`good` should compile");
+    }
+
+    #[test]
+    fn generator_is_usable_after_panicking_function() {
+        let mut analysis = compile_snippet(
+            "
+(define-private (bad (a int)) (+ a 1))
+(define-private (good (a int)) (- a 1))
+",
+        )
+        .contract_analysis;
+        let expressions = std::mem::take(&mut analysis.expressions);
+        let body = |index: usize| -> SymbolicExpression {
+            expressions[index].match_list().unwrap()[2].clone()
+        };
+
+        let mut generator = WasmGenerator::new(analysis).unwrap();
+        let mut top_level = FunctionBuilder::new(&mut generator.module.types, &[], &[]);
+
+        // Hide the standard library function of `+`, so that looking it up
+        // in the body of `bad` panics.
+        let add = generator.func_by_name("stdlib.add-int");
+        generator.module.funcs.get_mut(add).name = None;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            generator.traverse_define_function(
+                &mut top_level.func_body(),
+                &"bad".into(),
+                &body(0),
+                FunctionKind::Private,
+            )
+        }));
+        assert!(result.is_err(), "`bad` should panic");
+
+        // The top-level state was restored
+        assert!(generator.bindings.is_empty());
+        assert_eq!(generator.frame_size, 0);
+        assert!(generator.return_type.is_none());
+        assert!(generator.early_return_block_id.is_none());
+
+        generator
+            .traverse_define_function(
+                &mut top_level.func_body(),
+                &"good".into(),
+                &body(1),
+                FunctionKind::Private,
+            )
+            .expect("`good` should compile");
+    }
+
+    #[test]
+    fn call_to_failed_function_is_an_error() {
+        let mut analysis = compile_snippet(
+            "
+(define-private (bad (a int)) (+ a 1))
+(define-private (caller (a int)) (bad a))
+",
+        )
+        .contract_analysis;
+        let expressions = std::mem::take(&mut analysis.expressions);
+        let body = |index: usize| -> SymbolicExpression {
+            expressions[index].match_list().unwrap()[2].clone()
+        };
+
+        let mut bad_body = body(0);
+        if let SymbolicExpressionType::List(call) = &mut bad_body.expr {
+            call[0].expr = SymbolicExpressionType::Atom("missing".into());
+        }
+
+        let mut generator = WasmGenerator::new(analysis).unwrap();
+        let mut top_level = FunctionBuilder::new(&mut generator.module.types, &[], &[]);
+
+        generator
+            .traverse_define_function(
+                &mut top_level.func_body(),
+                &"bad".into(),
+                &bad_body,
+                FunctionKind::Private,
+            )
+            .expect_err("`bad` should not compile");
+
+        // `bad` was never added to the module, calling it must not panic.
+        let err = generator
+            .traverse_define_function(
+                &mut top_level.func_body(),
+                &"caller".into(),
+                &body(1),
+                FunctionKind::Private,
+            )
+            .expect_err("`caller` should not compile");
+        assert!(matches!(err, GeneratorError::InternalError(msg) if msg.contains("bad")));
+    }
+
+    #[test]
+    fn early_return_restores_function_entry_stack_pointer() {
+        // Each call allocates a list, a concat and let bindings on the call
+        // stack before a nested `asserts!` returns early or not.
+        let snippet = "
+(define-read-only (deep (limit int))
+  (let ((numbers (list 1 2 3 4 5 6)))
+    (let ((doubled (concat numbers numbers))
+          (total (fold + doubled 0)))
+      (let ((checked (begin (asserts! (< total limit) (err u1)) total)))
+        (ok checked)))))
+";
+        let (mut store, instance) = instantiate(&mut compile_snippet(snippet).module, (), |_| {});
+
+        let stack_pointer = instance.get_global(&mut store, "stack-pointer").unwrap();
+        let entry_stack_pointer = stack_pointer.get(&mut store).unwrap_i32();
+        let deep = instance.get_func(&mut store, "deep").unwrap();
+
+        for i in 0..100 {
+            // An odd call returns early, an even call does not.
+            let limit: i64 = if i % 2 == 1 { 0 } else { 100 };
+            let mut results = [
+                wasmtime::Val::I32(0),
+                wasmtime::Val::I64(0),
+                wasmtime::Val::I64(0),
+                wasmtime::Val::I64(0),
+                wasmtime::Val::I64(0),
+            ];
+            deep.call(&mut store, &[limit.into(), 0i64.into()], &mut results)
+                .unwrap();
+
+            if limit == 0 {
+                assert_eq!(results[0].unwrap_i32(), 0);
+                assert_eq!(results[3].unwrap_i64(), 1);
+            } else {
+                assert_eq!(results[0].unwrap_i32(), 1);
+                assert_eq!(results[1].unwrap_i64(), 42);
+            }
+            assert_eq!(
+                stack_pointer.get(&mut store).unwrap_i32(),
+                entry_stack_pointer
+            );
+        }
+    }
+
+    #[test]
+    fn wasm_bytes_are_emitted_once() {
+        let mut compile_result = compile_snippet("(define-read-only (one) 1)");
+        let size = compile_result.wasm_size();
+        assert_eq!(size, compile_result.module.emit_wasm().len());
+        assert_eq!(
+            compile_result.wasm_bytes,
+            compile_to_bytes("(define-read-only (one) 1)")
+        );
+    }
+
+    /// Build a function pushing a placeholder of `from_ty` and coercing it to
+    /// `to_ty`, and check that the module validates. Returns the number of
+    /// instructions added by the coercion.
+    fn check_coercion(from_ty: &TypeSignature, to_ty: &TypeSignature) -> usize {
+        let mut generator = WasmGenerator::new(compile_snippet("").contract_analysis).unwrap();
+        let mut function =
+            FunctionBuilder::new(&mut generator.module.types, &[], &clar2wasm_ty(to_ty));
+        let mut builder = function.func_body();
+        add_placeholder_for_clarity_type(&mut builder, from_ty);
+        let placeholders = builder.instrs().len();
+        generator
+            .coerce_to_type(&mut builder, from_ty, to_ty)
+            .unwrap();
+        let added = builder.instrs().len() - placeholders;
+
+        let coerced = function.finish(vec![], &mut generator.module.funcs);
+        generator.module.exports.add("coerced", coerced);
+        wasmtime::Module::new(&wasmtime::Engine::default(), generator.module.emit_wasm())
+            .expect("coerced value should match the function results");
+        added
+    }
+
+    #[test]
+    fn coerce_no_type_to_uint() {
+        assert_ne!(
+            check_coercion(&TypeSignature::NoType, &TypeSignature::UIntType),
+            0
+        );
+    }
+
+    #[test]
+    fn coerce_response_err_half() {
+        let from =
+            TypeSignature::new_response(TypeSignature::UIntType, TypeSignature::NoType).unwrap();
+        let to =
+            TypeSignature::new_response(TypeSignature::UIntType, TypeSignature::UIntType).unwrap();
+        assert_ne!(check_coercion(&from, &to), 0);
+
+        let from =
+            TypeSignature::new_response(TypeSignature::NoType, TypeSignature::IntType).unwrap();
+        let to = TypeSignature::new_response(buff(3), TypeSignature::IntType).unwrap();
+        assert_ne!(check_coercion(&from, &to), 0);
+    }
+
+    #[test]
+    fn coerce_nested_no_type() {
+        let tuple = |ty: TypeSignature| {
+            TypeSignature::TupleType(
+                vec![("a".into(), TypeSignature::IntType), ("b".into(), ty)]
+                    .try_into()
+                    .unwrap(),
+            )
+        };
+        let from = TypeSignature::new_option(tuple(TypeSignature::NoType)).unwrap();
+        let to = TypeSignature::new_option(tuple(
+            TypeSignature::new_option(TypeSignature::UIntType).unwrap(),
+        ))
+        .unwrap();
+        assert_ne!(check_coercion(&from, &to), 0);
+    }
+
+    #[test]
+    fn coerce_identical_types_is_noop() {
+        let types = [
+            TypeSignature::UIntType,
+            TypeSignature::new_response(TypeSignature::UIntType, string_ascii(4)).unwrap(),
+            TypeSignature::new_option(buff(2)).unwrap(),
+        ];
+        for ty in types {
+            assert_eq!(check_coercion(&ty, &ty), 0, "coercion of {ty}");
+        }
+        // Only the representation matters.
+        assert_eq!(check_coercion(&string_ascii(2), &string_ascii(10)), 0);
+    }
+
+    #[test]
+    fn coerce_mismatched_types_fails() {
+        let mut generator = WasmGenerator::new(compile_snippet("").contract_analysis).unwrap();
+        let mut function = FunctionBuilder::new(&mut generator.module.types, &[], &[]);
+        let mut builder = function.func_body();
+        let err = generator
+            .coerce_to_type(&mut builder, &TypeSignature::IntType, &buff(2))
+            .expect_err("int cannot be coerced to a buffer");
+        assert!(matches!(err, GeneratorError::TypeError(_)));
+    }
+
+    #[test]
+    fn function_internal_names_are_prefixed_by_kind() {
+        let module = compile_snippet(
+            "(define-public (foo) (ok true)) (define-read-only (bar) true) (define-private (baz) true)",
+        )
+        .module;
+        for name in ["public.foo", "read-only.bar", "private.baz"] {
+            assert!(module.funcs.by_name(name).is_some(), "missing {name}");
+        }
+        for name in ["foo", "bar", "baz"] {
+            assert!(module.funcs.by_name(name).is_none());
+        }
+        let exports: Vec<_> = module.exports.iter().map(|e| e.name.as_str()).collect();
+        assert!(exports.contains(&"foo") && exports.contains(&"bar"));
+        assert!(!exports.contains(&"baz"));
+    }
+
+    #[test]
+    fn self_test_is_not_exported_by_contracts() {
+        let module = compile_snippet("(define-read-only (foo) true)").module;
+        assert!(module
+            .exports
+            .iter()
+            .all(|export| export.name != SELF_TEST_EXPORT));
+        assert!(module.funcs.by_name("stdlib.self-test").is_none());
+    }
+
+    #[test]
+    fn user_function_named_like_stdlib_function() {
+        // `log` is also the name of the debug logging import of the standard
+        // library.
+        crosscheck(
+            "(define-private (log (x int)) (+ x 1)) (log 1)",
+            Ok(Some(clarity::vm::Value::Int(2))),
+        );
+    }
+
+    #[test]
+    fn filter_with_user_function_named_like_stdlib_function() {
+        crosscheck(
+            "(define-private (log (x int)) (> x 1)) (filter log (list 1 2 3))",
+            Ok(Some(
+                clarity::vm::Value::cons_list_unsanitized(vec![
+                    clarity::vm::Value::Int(2),
+                    clarity::vm::Value::Int(3),
+                ])
+                .unwrap(),
+            )),
+        );
+    }
+
+    #[test]
+    fn runtime_error_codes_are_stable() {
+        let codes = [
+            (RuntimeError::Overflow, 0),
+            (RuntimeError::Underflow, 1),
+            (RuntimeError::DivideByZero, 2),
+            (RuntimeError::LogOfNumberLessThanOrEqualToZero, 3),
+            (RuntimeError::ExpectedANonNegativeNumber, 4),
+            (RuntimeError::BufferToIntegerLength, 5),
+            (RuntimeError::Panic, 6),
+            (RuntimeError::ShortReturn, 7),
+        ];
+        for (error, code) in codes {
+            assert_eq!(error as i32, code, "code of {error:?}");
+        }
+    }
+
+    /// The total size of the call stack allocations made by the function
+    /// `name` of `module`. Each allocation adds a constant to the stack
+    /// pointer.
+    fn allocated_frame_size(module: &Module, name: &str) -> i32 {
+        struct Allocations {
+            stack_pointer: GlobalId,
+            size: i32,
+        }
+        impl<'instr> Visitor<'instr> for Allocations {
+            fn start_instr_seq(&mut self, instr_seq: &'instr InstrSeq) {
+                let instrs: Vec<_> = instr_seq.instrs.iter().map(|(instr, _)| instr).collect();
+                for window in instrs.windows(3) {
+                    if let [Instr::Const(Const {
+                        value: Value::I32(size),
+                    }), Instr::Binop(Binop {
+                        op: BinaryOp::I32Add,
+                    }), Instr::GlobalSet(GlobalSet { global })] = window
+                    {
+                        if *global == self.stack_pointer {
+                            self.size += size;
+                        }
+                    }
+                }
+            }
+        }
+
+        let stack_pointer = module
+            .globals
+            .iter()
+            .find(|global| global.name.as_deref() == Some("stack-pointer"))
+            .expect("stack-pointer global not found")
+            .id();
+        let func = module.funcs.by_name(name).expect("function not found");
+        let walrus::FunctionKind::Local(func) = &module.funcs.get(func).kind else {
+            panic!("{name} should be a local function");
+        };
+        let mut allocations = Allocations {
+            stack_pointer,
+            size: 0,
+        };
+        dfs_in_order(&mut allocations, func, func.entry_block());
+        allocations.size
+    }
+
+    #[test]
+    fn function_frames_do_not_add_to_top_level_frame() {
+        // Each function allocates its list on its own frame, and the
+        // top-level allocates a list of two ints.
+        let snippet = "
+(define-private (three) (list u1 u2 u3))
+(define-private (four) (list 1 2 3 4))
+(list 1 2)
+";
+        let generator = traverse_top_level(compile_snippet(snippet).contract_analysis);
+        assert_eq!(generator.frame_size, 32);
+
+        let module = compile_snippet(snippet).module;
+        assert_eq!(allocated_frame_size(&module, "private.three"), 48);
+        assert_eq!(allocated_frame_size(&module, "private.four"), 64);
+    }
+
+    #[test]
+    fn frame_size_overflow_is_an_error() {
+        let mut generator =
+            WasmGenerator::new(compile_snippet("(list 1 2)").contract_analysis).unwrap();
+        generator.frame_size = i32::MAX - 16;
+        let err = generator
+            .generate()
+            .expect_err("the top-level frame should overflow");
+        assert!(matches!(err, GeneratorError::InternalError(msg) if msg.contains("too large")));
+    }
+
+    #[test]
+    fn unsupported_literal_error_names_the_literal() {
+        let mut generator = WasmGenerator::new(compile_snippet("").contract_analysis).unwrap();
+        let mut function = FunctionBuilder::new(&mut generator.module.types, &[], &[]);
+        let value = clarity::vm::Value::some(clarity::vm::Value::Int(1)).unwrap();
+        let mut expr = SymbolicExpression::literal_value(value.clone());
+        expr.span = Span {
+            start_line: 3,
+            start_column: 7,
+            end_line: 3,
+            end_column: 14,
+        };
+
+        let err = generator
+            .visit_literal_value(&mut function.func_body(), &expr, &value)
+            .expect_err("optional literals are not supported");
+        assert!(
+            matches!(&err, GeneratorError::TypeError(msg) if msg.starts_with("unsupported optional literal at 3:7")),
+            "unexpected error: {err:?}"
+        );
+    }
 }
@@ -1,16 +1,34 @@
 use clarity::vm::types::TypeSignature;
 use clarity::vm::ClarityName;
+use walrus::ValType;
 
 use super::SimpleWord;
-use crate::wasm_generator::{GeneratorError, WasmGenerator};
+use crate::wasm_generator::{CostOp, GeneratorError, WasmGenerator};
+
+/// Returns an error if one of the operands of an arithmetic or comparison
+/// word, of kind `kind`, has an indeterminate type. Such an operand has no
+/// numeric or sequence representation to operate on.
+pub(crate) fn check_operand_types(
+    kind: &str,
+    arg_types: &[TypeSignature],
+) -> Result<(), GeneratorError> {
+    if arg_types.contains(&TypeSignature::NoType) {
+        Err(GeneratorError::TypeError(format!(
+            "{kind} operand has indeterminate type"
+        )))
+    } else {
+        Ok(())
+    }
+}
 
 fn simple_typed_one_call(
     generator: &mut WasmGenerator,
     builder: &mut walrus::InstrSeqBuilder,
-    _arg_types: &[TypeSignature],
+    arg_types: &[TypeSignature],
     return_type: &TypeSignature,
     name: &str,
 ) -> Result<(), GeneratorError> {
+    check_operand_types("arithmetic", arg_types)?;
     let type_suffix = match return_type {
         TypeSignature::IntType => "int",
         TypeSignature::UIntType => "uint",
@@ -22,6 +40,7 @@ fn simple_typed_one_call(
     };
 
     let func = generator.func_by_name(&format!("stdlib.{name}-{type_suffix}"));
+    generator.charge_cost(builder, CostOp::Arithmetic, arg_types.len() as i64);
     builder.call(func);
 
     Ok(())
@@ -42,6 +61,7 @@ impl SimpleWord for Add {
         arg_types: &[TypeSignature],
         return_type: &TypeSignature,
     ) -> Result<(), GeneratorError> {
+        check_operand_types("arithmetic", arg_types)?;
         if arg_types.len() > 1 {
             let type_suffix = match return_type {
                 TypeSignature::IntType => "int",
@@ -52,6 +72,7 @@ impl SimpleWord for Add {
                     ));
                 }
             };
+            generator.charge_cost(builder, CostOp::Arithmetic, arg_types.len() as i64);
             let func = generator.func_by_name(&format!("stdlib.add-{type_suffix}"));
             builder.call(func);
         }
@@ -74,6 +95,7 @@ impl SimpleWord for Sub {
         arg_types: &[TypeSignature],
         return_type: &TypeSignature,
     ) -> Result<(), GeneratorError> {
+        check_operand_types("arithmetic", arg_types)?;
         let type_suffix = match return_type {
             TypeSignature::IntType => "int",
             TypeSignature::UIntType => "uint",
@@ -84,15 +106,18 @@ impl SimpleWord for Sub {
             }
         };
         if arg_types.len() == 1 {
-            // unary is eq to mult by -1
-            builder.i64_const(-1);
-            builder.i64_const(-1);
-            let func = generator.func_by_name(&format!("stdlib.mul-{type_suffix}"));
-            builder.call(func);
-        } else {
-            let func = generator.func_by_name(&format!("stdlib.sub-{type_suffix}"));
-            builder.call(func);
+            // Unary subtraction is `(- 0 x)`. Going through the checked
+            // subtraction makes `(- i128::MIN)` overflow and any non-zero
+            // `uint` underflow, like the interpreter does.
+            let high = generator.module.locals.add(ValType::I64);
+            let low = generator.module.locals.add(ValType::I64);
+            builder.local_set(high).local_set(low);
+            builder.i64_const(0).i64_const(0);
+            builder.local_get(low).local_get(high);
         }
+        generator.charge_cost(builder, CostOp::Arithmetic, arg_types.len() as i64);
+        let func = generator.func_by_name(&format!("stdlib.sub-{type_suffix}"));
+        builder.call(func);
         Ok(())
     }
 }
@@ -112,6 +137,7 @@ impl SimpleWord for Mul {
         arg_types: &[TypeSignature],
         return_type: &TypeSignature,
     ) -> Result<(), GeneratorError> {
+        check_operand_types("arithmetic", arg_types)?;
         if arg_types.len() > 1 {
             let type_suffix = match return_type {
                 TypeSignature::IntType => "int",
@@ -122,6 +148,7 @@ impl SimpleWord for Mul {
                     ));
                 }
             };
+            generator.charge_cost(builder, CostOp::Arithmetic, arg_types.len() as i64);
             let func = generator.func_by_name(&format!("stdlib.mul-{type_suffix}"));
             builder.call(func);
         }
@@ -144,6 +171,7 @@ impl SimpleWord for Div {
         arg_types: &[TypeSignature],
         return_type: &TypeSignature,
     ) -> Result<(), GeneratorError> {
+        check_operand_types("arithmetic", arg_types)?;
         if arg_types.len() > 1 {
             let type_suffix = match return_type {
                 TypeSignature::IntType => "int",
@@ -155,6 +183,7 @@ impl SimpleWord for Div {
                 }
             };
             let func = generator.func_by_name(&format!("stdlib.div-{type_suffix}"));
+            generator.charge_cost(builder, CostOp::Arithmetic, arg_types.len() as i64);
             builder.call(func);
         }
         Ok(())
@@ -239,9 +268,11 @@ impl SimpleWord for Sqrti {
 
 #[cfg(test)]
 mod tests {
-    use clarity::vm::Value;
+    use clarity::vm::types::TypeSignature;
+    use clarity::vm::{SymbolicExpressionType, Value};
 
-    use crate::tools::{crosscheck, evaluate};
+    use crate::tools::{crosscheck, evaluate, generation_error};
+    use crate::wasm_generator::GeneratorError;
 
     #[test]
     fn test_overflow() {
@@ -387,6 +418,37 @@ mod tests {
         crosscheck("(- u5)", Err(()));
     }
 
+    #[test]
+    fn sub_unary_zero() {
+        crosscheck("(- 0)", Ok(Some(Value::Int(0))));
+        crosscheck("(- u0)", Ok(Some(Value::UInt(0))));
+    }
+
+    #[test]
+    fn sub_unary_negative() {
+        crosscheck("(- -5)", Ok(Some(Value::Int(5))));
+        crosscheck(
+            "(- 170141183460469231731687303715884105727)",
+            Ok(Some(Value::Int(-i128::MAX))),
+        );
+    }
+
+    #[test]
+    fn sub_unary_min_overflow() {
+        crosscheck("(- -170141183460469231731687303715884105728)", Err(()));
+    }
+
+    #[test]
+    fn sub_unary_runtime_operand() {
+        crosscheck(
+            "
+(define-data-var n int 42)
+(- (var-get n))
+",
+            Ok(Some(Value::Int(-42))),
+        );
+    }
+
     #[test]
     fn sub_ordering() {
         crosscheck(
@@ -404,4 +466,27 @@ mod tests {
             Ok(Some(Value::Int(1076))),
         );
     }
+
+    #[test]
+    fn operand_with_indeterminate_type() {
+        for (snippet, kind) in [("(+ 1 2)", "arithmetic"), ("(< 1 2)", "comparison")] {
+            let err = generation_error(snippet, |analysis| {
+                let operand = match analysis.expressions.last().map(|expr| &expr.expr) {
+                    Some(SymbolicExpressionType::List(call)) => call[2].clone(),
+                    _ => panic!("expected a call"),
+                };
+                // The type map replaces the type, but reports an error
+                // because the operand was already typed.
+                let _ = analysis
+                    .type_map
+                    .as_mut()
+                    .unwrap()
+                    .set_type(&operand, TypeSignature::NoType);
+            });
+            assert!(
+                matches!(&err, GeneratorError::TypeError(msg) if msg == &format!("{kind} operand has indeterminate type")),
+                "unexpected error: {err:?}"
+            );
+        }
+    }
 }
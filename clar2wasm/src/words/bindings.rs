@@ -80,7 +80,11 @@ impl ComplexWord for Let {
 
 #[cfg(test)]
 mod tests {
-    use crate::tools::{crosscheck, crosscheck_compare_only};
+    use clarity::vm::{SymbolicExpressionType, Value};
+
+    use crate::tools::{
+        crosscheck, crosscheck_compare_only, for_each_expr_mut, generate_mutated, run_top_level,
+    };
 
     #[test]
     fn clar_let_disallow_builtin_names() {
@@ -93,6 +97,42 @@ mod tests {
         crosscheck(&format!("{ERR} (test)"), Err(()));
     }
 
+    #[test]
+    fn clar_let_disallow_keyword_names() {
+        crosscheck("(let ((block-height u5)) block-height)", Err(()));
+        crosscheck("(let ((concat u1)) concat)", Err(()));
+    }
+
+    #[test]
+    fn let_binding_shadows_keyword() {
+        // The analysis rejects such a binding, so it is renamed afterwards to
+        // check the resolution order of the generator.
+        let mut module = generate_mutated(
+            "{ inner: (let ((x u5)) x), outer: block-height }",
+            |analysis| {
+                for_each_expr_mut(&mut analysis.expressions, &mut |expr| {
+                    if expr.match_atom().map_or(false, |name| name.as_str() == "x") {
+                        expr.expr = SymbolicExpressionType::Atom("block-height".into());
+                    }
+                })
+            },
+        )
+        .expect("failed to generate module");
+
+        let (results, ()) = run_top_level(&mut module, (), |linker| {
+            linker
+                .func_wrap("clarity", "block_height", || (7i64, 0i64))
+                .unwrap();
+        });
+        assert_eq!(
+            results
+                .iter()
+                .map(|val| val.unwrap_i64())
+                .collect::<Vec<_>>(),
+            vec![5, 0, 7, 0]
+        );
+    }
+
     #[test]
     fn clar_let_disallow_user_defined_names() {
         // It's not allowed to use names of user-defined functions as bindings
@@ -119,4 +159,96 @@ mod tests {
             "#,
         )
     }
+
+    #[test]
+    fn let_binding_depends_on_earlier_binding() {
+        crosscheck(
+            "
+(define-private (test)
+  (let ((a u1) (b (+ a u1)) (c (* a b u3)))
+    (+ a b c)))
+(test)
+",
+            Ok(Some(Value::UInt(9))),
+        );
+    }
+
+    #[test]
+    fn let_binding_shadows_outer_binding() {
+        crosscheck_compare_only(
+            "
+(define-private (test (a uint))
+  (let ((b (+ a u1)))
+    (+ (let ((a (* b u10))) a) a)))
+(test u2)
+",
+        );
+    }
+
+    #[test]
+    fn let_bindings_are_restored_after_body() {
+        crosscheck(
+            "
+(define-private (test (a uint))
+  (+ (let ((b (+ a u1))) b) (let ((b (* a u2))) b)))
+(test u2)
+",
+            Ok(Some(Value::UInt(7))),
+        );
+    }
+
+    #[test]
+    fn let_without_bindings() {
+        crosscheck("(let () (+ u1 u2))", Ok(Some(Value::UInt(3))));
+    }
+
+    #[test]
+    fn let_without_bindings_keeps_outer_bindings() {
+        crosscheck(
+            "
+(define-private (test (a uint))
+  (let ((b (* a u2)))
+    (+ (let () (+ a b)) b)))
+(test u3)
+",
+            Ok(Some(Value::UInt(15))),
+        );
+    }
+
+    #[test]
+    fn let_binding_of_response() {
+        crosscheck(
+            "(let ((r (ok u1))) (unwrap-panic r))",
+            Ok(Some(Value::UInt(1))),
+        );
+    }
+
+    #[test]
+    fn let_binding_of_response_reads_all_slots() {
+        crosscheck(
+            r#"
+(define-private (test (fail bool))
+  (let ((r (if fail (err "failed") (ok u1))))
+    (list (is-ok r) (match r value false error (is-eq error "failed")))))
+(list (test true) (test false))
+"#,
+            Ok(Some(
+                Value::cons_list_unsanitized(vec![
+                    Value::cons_list_unsanitized(vec![Value::Bool(false), Value::Bool(true)])
+                        .unwrap(),
+                    Value::cons_list_unsanitized(vec![Value::Bool(true), Value::Bool(false)])
+                        .unwrap(),
+                ])
+                .unwrap(),
+            )),
+        );
+    }
+
+    #[test]
+    fn let_binding_of_optional() {
+        crosscheck(
+            "(let ((o (some u2)) (n (if true none o))) (+ (unwrap-panic o) (default-to u3 n)))",
+            Ok(Some(Value::UInt(5))),
+        );
+    }
 }
@@ -248,4 +248,16 @@ mod test {
             evaluate("(ok 1)"),
         )
     }
+
+    //- Clarity 3 bitwise
+
+    // The pinned Clarity version predates Clarity 3, so `bit-count` is
+    // unknown to the analysis and rejected by both the compiler and the
+    // interpreter.
+
+    #[test]
+    fn bit_count_is_rejected() {
+        crosscheck("(bit-count u7)", Err(()));
+        crosscheck("(bit-count -1)", Err(()));
+    }
 }
@@ -1,7 +1,12 @@
+use clarity::vm::types::TypeSignature;
 use clarity::vm::{ClarityName, SymbolicExpression};
+use walrus::ir::{IfElse, InstrSeqType, LoadKind, MemArg};
+use walrus::LocalId;
 
 use super::ComplexWord;
-use crate::wasm_generator::{ArgumentsExt, GeneratorError, WasmGenerator};
+use crate::wasm_generator::{
+    add_placeholder_for_clarity_type, clar2wasm_ty, ArgumentsExt, GeneratorError, WasmGenerator,
+};
 
 #[derive(Debug)]
 pub struct GetBlockInfo;
@@ -49,12 +54,54 @@ impl ComplexWord for GetBlockInfo {
 
         // Host interface fills the result into the specified memory. Read it
         // back out, and place the value on the data stack.
-        generator.read_from_memory(builder, return_offset, 0, &return_ty)?;
-
-        Ok(())
+        read_block_info(generator, builder, return_offset, &return_ty)
     }
 }
 
+/// Read the optional written by the host at `return_offset`. For a block out
+/// of range, the host only clears the indicator, so the payload is not read
+/// and `none` is pushed with placeholders for the type of the property.
+fn read_block_info(
+    generator: &mut WasmGenerator,
+    builder: &mut walrus::InstrSeqBuilder,
+    return_offset: LocalId,
+    return_ty: &TypeSignature,
+) -> Result<(), GeneratorError> {
+    let TypeSignature::OptionalType(inner_ty) = return_ty else {
+        return Err(GeneratorError::TypeError(
+            "get-block-info? should return an optional".to_owned(),
+        ));
+    };
+    let ty = InstrSeqType::new(&mut generator.module.types, &[], &clar2wasm_ty(return_ty));
+
+    let mut some = builder.dangling_instr_seq(ty);
+    generator.read_from_memory(&mut some, return_offset, 0, return_ty)?;
+    let some_id = some.id();
+
+    let mut none = builder.dangling_instr_seq(ty);
+    none.i32_const(0);
+    add_placeholder_for_clarity_type(&mut none, inner_ty);
+    let none_id = none.id();
+
+    let memory = generator.get_memory()?;
+    builder
+        .local_get(return_offset)
+        .load(
+            memory,
+            LoadKind::I32 { atomic: false },
+            MemArg {
+                align: 4,
+                offset: 0,
+            },
+        )
+        .instr(IfElse {
+            consequent: some_id,
+            alternative: none_id,
+        });
+
+    Ok(())
+}
+
 #[derive(Debug)]
 pub struct GetBurnBlockInfo;
 
@@ -133,11 +180,9 @@ impl ComplexWord for AtBlock {
         // Call the host interface function, `enter_at_block`
         builder.call(generator.func_by_name("stdlib.enter_at_block"));
 
-        // Traverse the inner expression
-        generator.traverse_expr(builder, e)?;
-
-        // Call the host interface function, `exit_at_block`
-        builder.call(generator.func_by_name("stdlib.exit_at_block"));
+        // Traverse the inner expression, then call the host interface
+        // function, `exit_at_block`
+        generator.traverse_in_context(builder, e, "stdlib.exit_at_block")?;
 
         Ok(())
     }
@@ -148,10 +193,67 @@ mod tests {
     use clarity::vm::types::{OptionalData, PrincipalData, TupleData};
     use clarity::vm::Value;
 
-    use crate::tools::{crosscheck, evaluate, TestEnvironment};
+    use crate::tools::{
+        compile_snippet, crosscheck, crosscheck_compare_only_advancing_tip, evaluate,
+        run_top_level, TestEnvironment,
+    };
 
     //- Block Info
 
+    /// Run the top-level of `snippet` with a host for which every block is
+    /// out of range. The host clears the indicator and fills the rest of the
+    /// return buffer with garbage.
+    fn run_with_absent_blocks(snippet: &str) -> Vec<wasmtime::Val> {
+        run_top_level(&mut compile_snippet(snippet).module, (), |linker| {
+            linker
+                .func_wrap(
+                    "clarity",
+                    "get_block_info",
+                    |mut caller: wasmtime::Caller<'_, ()>,
+                     _name_offset: i32,
+                     _name_length: i32,
+                     _height_lo: i64,
+                     _height_hi: i64,
+                     return_offset: i32,
+                     return_length: i32| {
+                        let memory = caller
+                            .get_export("memory")
+                            .and_then(|export| export.into_memory())
+                            .unwrap();
+                        let mut bytes = vec![0xff; return_length as usize];
+                        bytes[..4].fill(0);
+                        memory
+                            .write(&mut caller, return_offset as usize, &bytes)
+                            .unwrap();
+                    },
+                )
+                .unwrap();
+        })
+        .0
+    }
+
+    #[test]
+    fn get_block_info_out_of_range_is_none_with_placeholders() {
+        let results = run_with_absent_blocks("(get-block-info? header-hash u100)");
+        assert_eq!(
+            results
+                .iter()
+                .map(|val| val.unwrap_i32())
+                .collect::<Vec<_>>(),
+            vec![0, 0, 0]
+        );
+
+        let results = run_with_absent_blocks("(get-block-info? time u100)");
+        assert_eq!(results[0].unwrap_i32(), 0);
+        assert_eq!(
+            results[1..]
+                .iter()
+                .map(|val| val.unwrap_i64())
+                .collect::<Vec<_>>(),
+            vec![0, 0]
+        );
+    }
+
     #[test]
     fn get_block_info_non_existent() {
         crosscheck("(get-block-info? time u9999999)", Ok(Some(Value::none())));
@@ -330,6 +432,66 @@ mod tests {
         );
     }
 
+    #[test]
+    fn get_burn_block_info_header_hash_in_range() {
+        crosscheck_compare_only_advancing_tip("(get-burn-block-info? header-hash u2)", 5);
+    }
+
+    #[test]
+    fn get_burn_block_info_header_hash_from_host() {
+        // The host writes `(some hash)`: the indicator, then the offset and
+        // length of the hash, which it writes right after them.
+        let hash: Vec<u8> = (0..32).collect();
+        let hex: String = hash.iter().map(|byte| format!("{byte:02x}")).collect();
+        let snippet = format!("(is-eq (get-burn-block-info? header-hash u2) (some 0x{hex}))");
+
+        let (results, requests) = run_top_level(
+            &mut compile_snippet(&snippet).module,
+            Vec::new(),
+            |linker| {
+                linker
+                    .func_wrap(
+                        "clarity",
+                        "get_burn_block_info",
+                        move |mut caller: wasmtime::Caller<'_, Vec<(String, i64, i64)>>,
+                              name_offset: i32,
+                              name_length: i32,
+                              height_lo: i64,
+                              height_hi: i64,
+                              return_offset: i32,
+                              _return_length: i32| {
+                            let memory = caller
+                                .get_export("memory")
+                                .and_then(|export| export.into_memory())
+                                .unwrap();
+                            let mut name = vec![0; name_length as usize];
+                            memory
+                                .read(&caller, name_offset as usize, &mut name)
+                                .unwrap();
+                            caller.data_mut().push((
+                                String::from_utf8(name).unwrap(),
+                                height_lo,
+                                height_hi,
+                            ));
+
+                            let mut bytes = vec![];
+                            bytes.extend_from_slice(&1i32.to_le_bytes());
+                            bytes.extend_from_slice(&(return_offset + 12).to_le_bytes());
+                            bytes.extend_from_slice(&32i32.to_le_bytes());
+                            bytes.extend_from_slice(&hash);
+                            memory
+                                .write(&mut caller, return_offset as usize, &bytes)
+                                .unwrap();
+                        },
+                    )
+                    .unwrap();
+            },
+        );
+
+        assert_eq!(results[0].unwrap_i32(), 1);
+        assert_eq!(requests, vec![("header-hash".to_owned(), 2, 0)]);
+    }
+
     //- At Block
     #[test]
     fn at_block() {
@@ -338,6 +500,31 @@ mod tests {
         )
     }
 
+    #[test]
+    fn early_return_from_nested_contexts() {
+        let preamble = "
+(define-public (nested (fail bool))
+  (as-contract
+    (at-block 0x0000000000000000000000000000000000000000000000000000000000000000
+      (begin
+        (try! (if fail (err u1) (ok u0)))
+        (ok block-height)))))
+
+(define-read-only (context)
+  {as-contract: (is-eq tx-sender (as-contract tx-sender)), height: block-height})
+";
+        crosscheck(
+            &format!("{preamble} (nested false)"),
+            evaluate("(ok u4294967295)"),
+        );
+        crosscheck(&format!("{preamble} (nested true)"), evaluate("(err u1)"));
+        // Both contexts are left by the early return.
+        crosscheck(
+            &format!("{preamble} (begin (unwrap-err-panic (nested true)) (context))"),
+            evaluate("{as-contract: false, height: u0}"),
+        );
+    }
+
     #[test]
     fn at_block_var() {
         let mut env = TestEnvironment::default();
@@ -382,4 +569,36 @@ mod tests {
             evaluate("(ok u2147483648)"),
         );
     }
+
+    #[test]
+    fn chain_id_is_read_from_the_host() {
+        let (results, ()) = run_top_level(&mut compile_snippet("chain-id").module, (), |linker| {
+            linker
+                .func_wrap("clarity", "chain_id", || (0x1234i64, 0i64))
+                .unwrap();
+        });
+        assert_eq!(
+            results
+                .iter()
+                .map(|val| val.unwrap_i64())
+                .collect::<Vec<_>>(),
+            vec![0x1234, 0]
+        );
+    }
+
+    //- Clarity 3 block info
+
+    // The pinned Clarity version predates Clarity 3, so its block info
+    // functions are unknown to the analysis and rejected by both the
+    // compiler and the interpreter.
+
+    #[test]
+    fn get_stacks_block_info_is_rejected() {
+        crosscheck("(get-stacks-block-info? time u0)", Err(()));
+    }
+
+    #[test]
+    fn get_tenure_info_is_rejected() {
+        crosscheck("(get-tenure-info? time u0)", Err(()));
+    }
 }
@@ -1,6 +1,7 @@
 use clarity::vm::types::{SequenceSubtype, StringSubtype, TypeSignature};
 use clarity::vm::ClarityName;
 
+use super::arithmetic::check_operand_types;
 use super::SimpleWord;
 use crate::wasm_generator::{GeneratorError, WasmGenerator};
 
@@ -11,6 +12,7 @@ fn traverse_comparison(
     arg_types: &[TypeSignature],
     _return_type: &TypeSignature,
 ) -> Result<(), GeneratorError> {
+    check_operand_types("comparison", arg_types)?;
     let ty = &arg_types[0];
 
     let type_suffix = match ty {
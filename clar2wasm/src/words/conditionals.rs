@@ -1,12 +1,14 @@
+use std::slice;
+
 use clarity::vm::types::TypeSignature;
 use clarity::vm::{ClarityName, SymbolicExpression};
-use walrus::ir::{self, InstrSeqType, Loop};
+use walrus::ir::{self, InstrSeqType};
 use walrus::ValType;
 
 use super::{ComplexWord, SimpleWord};
 use crate::wasm_generator::{
-    add_placeholder_for_clarity_type, clar2wasm_ty, drop_value, ArgumentsExt, GeneratorError,
-    SequenceElementType, WasmGenerator,
+    add_placeholder_for_clarity_type, clar2wasm_ty, drop_value, sequence_element_type,
+    ArgumentsExt, CostOp, GeneratorError, WasmGenerator,
 };
 use crate::words;
 
@@ -76,6 +78,22 @@ impl ComplexWord for Match {
             .clone();
 
         let match_on = args.get_expr(0)?;
+        let match_on_ty = generator.get_expr_type(match_on).cloned();
+
+        // An optional is matched with a binding and two branches, and a
+        // response with a binding for each of its two branches.
+        let (kind, expected_args) = match &match_on_ty {
+            Some(TypeSignature::OptionalType(_)) => ("an optional", 4),
+            Some(TypeSignature::ResponseType(_)) => ("a response", 5),
+            _ => return Err(GeneratorError::TypeError("Invalid type for match".into())),
+        };
+        if args.len() != expected_args {
+            return Err(GeneratorError::InternalError(format!(
+                "match on {kind} expects {expected_args} arguments, found {}",
+                args.len()
+            )));
+        }
+
         let success_binding = args.get_name(1)?;
 
         if generator.is_reserved_name(success_binding) {
@@ -94,7 +112,7 @@ impl ComplexWord for Match {
 
         generator.traverse_expr(builder, match_on)?;
 
-        match generator.get_expr_type(match_on).cloned() {
+        match match_on_ty {
             Some(TypeSignature::OptionalType(inner_type)) => {
                 let none_body = args.get_expr(3)?;
 
@@ -187,8 +205,6 @@ impl ComplexWord for Filter {
         let discriminator = args.get_name(0)?;
         let sequence = args.get_expr(1)?;
 
-        generator.traverse_expr(builder, sequence)?;
-
         // Get the type of the sequence
         let ty = generator
             .get_expr_type(sequence)
@@ -197,134 +213,84 @@ impl ComplexWord for Filter {
             })?
             .clone();
 
-        let elem_ty = generator.get_sequence_element_type(sequence)?;
+        // The discriminator is called with an element and must return a bool.
+        let elem_ty = sequence_element_type(&ty)
+            .ok_or_else(|| GeneratorError::TypeError("expected sequence type".to_owned()))?;
+        generator.check_callback_signature(
+            "filter",
+            discriminator,
+            &[elem_ty],
+            Some(&TypeSignature::BoolType),
+        )?;
 
-        // Setup neccesary locals for the operations.
-        let input_len = generator.module.locals.add(ValType::I32);
-        let input_offset = generator.module.locals.add(ValType::I32);
-        let input_end = generator.module.locals.add(ValType::I32);
-        let output_len = generator.module.locals.add(ValType::I32);
+        let iter = generator.traverse_sequence_iter(builder, sequence)?;
+        let elem_size = iter.elem_ty.size();
 
-        builder
-            // [ input_offset, input_len ]
-            .local_set(input_len)
-            // [ input_offset ]
-            .local_tee(input_offset)
-            // [ input_offset ]
-            .local_get(input_len)
-            // [ input_offset, input_len ]
-            .binop(ir::BinaryOp::I32Add)
-            // [ input_end ]
-            .local_set(input_end);
-        // [ ]
-        // now we have an empty stack, and three initialized locals
-
-        // reserve space for the length of the output list
+        // reserve space for the output list
         let (output_offset, _) = generator.create_call_stack_local(builder, &ty, false, true);
+        let output_len = generator.module.locals.add(ValType::I32);
+        builder.i32_const(0).local_set(output_len);
 
         let memory = generator.get_memory()?;
 
-        let mut loop_result = Ok(());
-
-        let mut loop_ = builder.dangling_instr_seq(None);
-        let loop_id = loop_.id();
-
-        // Load an element from the sequence
-        let elem_size = match elem_ty {
-            SequenceElementType::Other(elem_ty) => {
-                generator.read_from_memory(&mut loop_, input_offset, 0, &elem_ty)?
+        generator.emit_sequence_loop(builder, slice::from_ref(&iter), |generator, loop_| {
+            generator.charge_cost(loop_, CostOp::LoopIteration, 1);
+
+            // Load an element from the sequence. Note that this can be
+            // multiple values in case of sequences.
+            generator.load_sequence_element(loop_, &iter)?;
+            // [ Value ]
+
+            // call the discriminator
+            if let Some(simple) = words::lookup_simple(discriminator) {
+                // Call simple builtin
+                simple.visit(
+                    generator,
+                    loop_,
+                    &[TypeSignature::BoolType],
+                    &TypeSignature::BoolType,
+                )?;
+            } else {
+                // user defined
+                loop_.call(generator.user_function_by_name(discriminator.as_str())?);
             }
-            SequenceElementType::Byte => {
-                // The element type is a byte, so we can just push the
-                // offset and length (1) to the stack.
-                loop_.local_get(input_offset).i32_const(1);
-                1
-            }
-            SequenceElementType::UnicodeScalar => {
-                // The element type is a 32-bit unicode scalar, so we can just push the
-                // offset and length (4) to the stack.
-                loop_.local_get(input_offset).i32_const(4);
-                4
-            }
-        };
-
-        // Stack now contains the value read from memory, note that this can be multiple values in case of
-        // sequences.
-
-        // [ Value ]
-
-        // call the discriminator
-
-        if let Some(simple) = words::lookup_simple(discriminator) {
-            // Call simple builtin
-            loop_result = simple.visit(
-                generator,
-                &mut loop_,
-                &[TypeSignature::BoolType],
-                &TypeSignature::BoolType,
+            // [ Discriminator result (bool) ]
+
+            // on success, increment length and copy value
+            // memory.copy takes source, destination and size in push order
+            // (reverse on stack)
+            loop_.if_else(
+                None,
+                |success_branch| {
+                    generator.charge_cost(success_branch, CostOp::MemoryCopy, elem_size.into());
+                    success_branch
+                        // []
+                        .local_get(output_offset)
+                        // [ output_ofs ]
+                        .local_get(output_len)
+                        // [ output_ofs, output_len ]
+                        .binop(ir::BinaryOp::I32Add)
+                        // [ output_write_pos ]
+                        .local_get(iter.offset)
+                        // [ output_write_pos, input_offset ]
+                        .i32_const(elem_size)
+                        // [ output_write_pos, input_offset, element_size ]
+                        .memory_copy(memory, memory)
+                        // [  ]
+                        .local_get(output_len)
+                        // [ output_len ]
+                        .i32_const(elem_size)
+                        // [ output_len, elem_size ]
+                        .binop(ir::BinaryOp::I32Add)
+                        // [ new_output_len ]
+                        .local_set(output_len);
+                    // [  ]
+                },
+                |_| {},
             );
-        } else {
-            // user defined
-            loop_.call(generator.func_by_name(discriminator.as_str()));
-        }
-        // [ Discriminator result (bool) ]
-
-        let mut success_branch = loop_.dangling_instr_seq(None);
-        let succ_id = success_branch.id();
-
-        // on success, increment length and copy value
-        // memory.copy takes source, destination and size in push order
-        // (reverse on stack)
-
-        success_branch
-            // []
-            .local_get(output_offset)
-            // [ output_ofs ]
-            .local_get(output_len)
-            // [ output_ofs, output_len ]
-            .binop(ir::BinaryOp::I32Add)
-            // [ output_write_pos ]
-            .local_get(input_offset)
-            // [ output_write_pos, input_offset ]
-            .i32_const(elem_size)
-            // [ output_write_pos, input_offset, element_size ]
-            .memory_copy(memory, memory)
-            // [  ]
-            .local_get(output_len)
-            // [ output_len ]
-            .i32_const(elem_size)
-            // [ output_len, elem_size ]
-            .binop(ir::BinaryOp::I32Add)
-            // [ new_output_len ]
-            .local_set(output_len);
-        // [  ]
-
-        // fail branch is a no-op (FIXME there is most certainly a better way to do this)
-
-        let fail_branch = loop_.dangling_instr_seq(None);
-        let fail_id = fail_branch.id();
-
-        loop_.instr(ir::IfElse {
-            consequent: succ_id,
-            alternative: fail_id,
-        });
-
-        // increment offset, leaving the new offset on the stack for the end check
-        loop_
-            .local_get(input_offset)
-            .i32_const(elem_size)
-            .binop(ir::BinaryOp::I32Add)
-            .local_tee(input_offset);
 
-        // Loop if we haven't reached the end of the sequence
-        loop_
-            .local_get(input_end)
-            .binop(ir::BinaryOp::I32LtU)
-            .br_if(loop_id);
-
-        builder.instr(Loop { seq: loop_id });
-
-        loop_result?;
+            Ok(())
+        })?;
 
         builder.local_get(output_offset);
         builder.local_get(output_len);
@@ -790,20 +756,23 @@ impl ComplexWord for Try {
                 // then add a placeholder for the ok-type of the return type
                 // and restore the err value from the locals.
                 throw_branch.i32_const(0);
-                let placeholder_ty = match generator.return_type.as_ref() {
-                    Some(TypeSignature::ResponseType(inner_types)) => &inner_types.0,
+                let (placeholder_ty, return_err_ty) = match generator.return_type.clone() {
+                    Some(TypeSignature::ResponseType(inner_types)) => *inner_types,
                     Some(other) => {
                         return Err(GeneratorError::TypeError(format!(
                             "expected response type, got {:?}",
                             other
                         )));
                     }
-                    None => &TypeSignature::NoType,
+                    None => (TypeSignature::NoType, err_type.clone()),
                 };
-                add_placeholder_for_clarity_type(&mut throw_branch, placeholder_ty);
+                add_placeholder_for_clarity_type(&mut throw_branch, &placeholder_ty);
                 for local in &err_locals {
                     throw_branch.local_get(*local);
                 }
+                // The err type of the input can be narrower than the one of
+                // the function, e.g. `NoType` for an input known to be `ok`.
+                generator.coerce_to_type(&mut throw_branch, err_type, &return_err_ty)?;
                 generator.return_early(&mut throw_branch)?;
 
                 let throw_branch_id = throw_branch.id();
@@ -841,9 +810,10 @@ impl ComplexWord for Try {
 
 #[cfg(test)]
 mod tests {
-    use clarity::vm::Value;
+    use clarity::vm::{SymbolicExpressionType, Value};
 
-    use crate::tools::{crosscheck, evaluate};
+    use crate::tools::{crosscheck, crosscheck_compare_only, evaluate, generation_error};
+    use crate::wasm_generator::GeneratorError;
 
     #[test]
     fn trivial() {
@@ -902,6 +872,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn filter_empty_runtime_sequence() {
+        // The element past the end of the slice must not be read.
+        crosscheck(
+            "
+(define-private (is-odd (number int))
+  (is-eq (mod number 2) 1))
+
+(filter is-odd (unwrap-panic (slice? (list 1 2 3) u0 u0)))
+",
+            evaluate("(list)"),
+        );
+    }
+
     #[test]
     fn and() {
         crosscheck(
@@ -998,6 +982,74 @@ mod tests {
         );
     }
 
+    #[test]
+    fn match_response_with_composite_payloads() {
+        const DESCRIBE: &str = r#"
+(define-private (describe (x (response (string-ascii 5) uint)))
+  (match x
+    name (concat "name:" name)
+    code (if (> code u9) "big" "small")))"#;
+
+        crosscheck(
+            &format!(
+                r#"{DESCRIBE} (list (describe (ok "alice")) (describe (err u42)) (describe (err u1)))"#
+            ),
+            evaluate(r#"(list "name:alice" "big" "small")"#),
+        );
+    }
+
+    #[test]
+    fn match_response_tuple_and_list_payloads() {
+        crosscheck(
+            "
+(define-private (test (value uint) (x (response { a: uint, b: bool } (list 2 uint))))
+  (+ (match x
+       pair (if (get b pair) (get a pair) u0)
+       items (fold + items u0))
+     value))
+(list (test u100 (ok { a: u1, b: true })) (test u100 (err (list u2 u3))))",
+            evaluate("(list u101 u105)"),
+        );
+    }
+
+    // The ok type of `(err u1)` is `NoType`, which is represented by a single
+    // `i32` placeholder wherever the ok half is produced or bound.
+    const ONLY_ERR: &str = "
+(define-private (only-err)
+  (err u1))";
+
+    #[test]
+    fn match_response_with_no_ok_type() {
+        crosscheck_compare_only("(match (err u1) val u0 error error)");
+        crosscheck_compare_only(&format!("{ONLY_ERR} (match (only-err) val u0 error error)"));
+    }
+
+    #[test]
+    fn try_response_with_no_ok_type() {
+        crosscheck_compare_only(&format!(
+            "{ONLY_ERR}
+(define-private (relay)
+  (begin (try! (only-err)) (ok u2)))
+(relay)"
+        ));
+    }
+
+    #[test]
+    fn consume_response_with_no_ok_type() {
+        crosscheck(
+            &format!("{ONLY_ERR} (is-ok (only-err))"),
+            Ok(Some(Value::Bool(false))),
+        );
+        crosscheck(
+            &format!("{ONLY_ERR} (unwrap-err-panic (only-err))"),
+            Ok(Some(Value::UInt(1))),
+        );
+        crosscheck(
+            &format!("{ONLY_ERR} (is-eq (only-err) (err u1))"),
+            Ok(Some(Value::Bool(true))),
+        );
+    }
+
     #[test]
     fn unwrap_a() {
         const FN: &str = "
@@ -1046,6 +1098,41 @@ mod tests {
         );
     }
 
+    #[test]
+    fn unwrap_err_returns_early() {
+        const FN: &str = "
+(define-data-var reached bool false)
+(define-private (check (resp (response uint uint)))
+  (let ((e (unwrap-err! resp u100)))
+    (var-set reached true)
+    e))";
+
+        crosscheck(
+            &format!("{FN} (list (check (ok u1)) (if (var-get reached) u1 u0))"),
+            evaluate("(list u100 u0)"),
+        );
+        crosscheck(
+            &format!("{FN} (list (check (err u2)) (if (var-get reached) u1 u0))"),
+            evaluate("(list u2 u1)"),
+        );
+    }
+
+    #[test]
+    fn unwrap_err_composite_payload_and_thrown() {
+        const FN: &str = r#"
+(define-private (reason (resp (response uint { code: uint, msg: (string-ascii 8) })))
+  (ok (get msg (unwrap-err! resp (err "no error")))))"#;
+
+        crosscheck(
+            &format!(r#"{FN} (reason (err {{ code: u2, msg: "denied" }}))"#),
+            evaluate(r#"(ok "denied")"#),
+        );
+        crosscheck(
+            &format!("{FN} (reason (ok u1))"),
+            evaluate(r#"(err "no error")"#),
+        );
+    }
+
     /// Verify that the full response type is set correctly for the throw
     /// expression.
     #[test]
@@ -1142,6 +1229,36 @@ mod tests {
         );
     }
 
+    const TRY_FN_COERCE: &str = "
+(define-private (is-big (x (response uint uint)))
+  (ok (> (try! x) u10)))
+(define-private (describe (x (response uint uint)))
+  (ok (if (> (try! x) u10) \"big\" \"small\")))";
+
+    #[test]
+    fn try_coerces_ok_type_on_success() {
+        crosscheck(
+            &format!("{TRY_FN_COERCE} (is-big (ok u42))"),
+            evaluate("(ok true)"),
+        );
+    }
+
+    #[test]
+    fn try_coerces_ok_type_on_error() {
+        crosscheck(
+            &format!("{TRY_FN_COERCE} (is-big (err u7))"),
+            evaluate("(err u7)"),
+        );
+    }
+
+    #[test]
+    fn try_coerces_in_memory_ok_type_on_error() {
+        crosscheck(
+            &format!("{TRY_FN_COERCE} (describe (err u7))"),
+            evaluate("(err u7)"),
+        );
+    }
+
     const ASSERT: &str = "
       (define-private (is-even (x int))
         (is-eq (* (/ x 2) 2) x))
@@ -1177,4 +1294,59 @@ mod tests {
     fn asserts_top_level_false() {
         crosscheck("(asserts! false (err u1))", Err(()))
     }
+
+    #[test]
+    fn filter_callback_return_type_mismatch() {
+        let err = generation_error(
+            "
+(define-private (is-positive (a int)) (> a 0))
+(define-private (negate (a int)) (- 0 a))
+(filter is-positive (list 1 -2 3))
+",
+            |analysis| {
+                // Replace the discriminator with a function that does not return a bool
+                match analysis.expressions.last_mut().map(|expr| &mut expr.expr) {
+                    Some(SymbolicExpressionType::List(call)) => {
+                        call[1].expr = SymbolicExpressionType::Atom("negate".into())
+                    }
+                    _ => panic!("expected a call"),
+                }
+            },
+        );
+        assert!(
+            matches!(err, GeneratorError::TypeError(msg) if msg.contains("filter callback 'negate'"))
+        );
+    }
+
+    /// Compile `snippet`, remove the last argument of its final `match`, and
+    /// return the error of the generator.
+    fn match_without_last_branch(snippet: &str) -> GeneratorError {
+        generation_error(snippet, |analysis| {
+            match analysis.expressions.last_mut().map(|expr| &mut expr.expr) {
+                Some(SymbolicExpressionType::List(call)) => {
+                    *call = call[..call.len() - 1].to_vec().into();
+                }
+                _ => panic!("expected a call"),
+            }
+        })
+    }
+
+    #[test]
+    fn match_optional_missing_branch() {
+        let err = match_without_last_branch("(match (some 1) x (+ x 1) 0)");
+        assert!(
+            matches!(&err, GeneratorError::InternalError(msg) if msg == "match on an optional expects 4 arguments, found 3"),
+            "unexpected error: {err:?}"
+        );
+    }
+
+    #[test]
+    fn match_response_missing_branch() {
+        let err =
+            match_without_last_branch("(match (if true (ok 1) (err u2)) x (+ x 1) e (to-int e))");
+        assert!(
+            matches!(&err, GeneratorError::InternalError(msg) if msg == "match on a response expects 5 arguments, found 4"),
+            "unexpected error: {err:?}"
+        );
+    }
 }
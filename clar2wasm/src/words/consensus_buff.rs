@@ -1,4 +1,4 @@
-use clarity::vm::types::{TypeSignature, MAX_VALUE_SIZE};
+use clarity::vm::types::{SequenceSubtype, TypeSignature};
 use walrus::ir::{BinaryOp, InstrSeqType};
 
 use super::ComplexWord;
@@ -19,9 +19,27 @@ impl ComplexWord for ToConsensusBuff {
         &self,
         generator: &mut crate::wasm_generator::WasmGenerator,
         builder: &mut walrus::InstrSeqBuilder,
-        _expr: &clarity::vm::SymbolicExpression,
+        expr: &clarity::vm::SymbolicExpression,
         args: &[clarity::vm::SymbolicExpression],
     ) -> Result<(), crate::wasm_generator::GeneratorError> {
+        // The result is a buffer of the maximum serialized size of the value
+        // type, as computed by the type-checker, which is at most
+        // MAX_VALUE_SIZE.
+        let max_length: u32 = match generator.get_expr_type(expr) {
+            Some(TypeSignature::OptionalType(buff_ty)) => match &**buff_ty {
+                TypeSignature::SequenceType(SequenceSubtype::BufferType(length)) => {
+                    Some(length.into())
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+        .ok_or_else(|| {
+            GeneratorError::TypeError(
+                "to-consensus-buff? expression must be an optional buffer".to_owned(),
+            )
+        })?;
+
         generator.traverse_args(builder, args)?;
 
         let ty = generator
@@ -46,10 +64,10 @@ impl ComplexWord for ToConsensusBuff {
 
         builder.local_set(length);
 
-        // Check if the serialized value size < MAX_VALUE_SIZE
+        // Check if the serialized value fits in the result buffer
         builder
             .local_get(length)
-            .i32_const(MAX_VALUE_SIZE as i32)
+            .i32_const(max_length as i32)
             .binop(walrus::ir::BinaryOp::I32LeU)
             .if_else(
                 InstrSeqType::new(
@@ -153,11 +171,15 @@ impl ComplexWord for FromConsensusBuff {
 
 #[cfg(test)]
 mod tests {
-    use clarity::vm::types::{BuffData, PrincipalData, SequenceData, TupleData};
+    use clarity::vm::types::signatures::BufferLength;
+    use clarity::vm::types::{
+        BuffData, PrincipalData, SequenceData, SequenceSubtype, TupleData, TypeSignature,
+    };
     use clarity::vm::Value;
     use hex::FromHex as _;
 
-    use crate::tools::crosscheck;
+    use crate::tools::{compile_snippet, crosscheck, run_top_level};
+    use crate::wasm_generator::WasmGenerator;
 
     #[test]
     fn to_consensus_buff_int() {
@@ -341,6 +363,41 @@ mod tests {
         )
     }
 
+    #[test]
+    fn to_consensus_buff_max_length_value() {
+        // A value of the maximum length of its type serializes to the
+        // maximum length of the result buffer.
+        crosscheck(
+            r#"
+(define-read-only (serialize (s (string-ascii 4))) (to-consensus-buff? s))
+(serialize "abcd")"#,
+            Ok(Some(
+                Value::some(Value::Sequence(SequenceData::Buffer(BuffData {
+                    data: Vec::from_hex("0d0000000461626364").unwrap(),
+                })))
+                .unwrap(),
+            )),
+        )
+    }
+
+    #[test]
+    fn to_consensus_buff_larger_than_result_is_none() {
+        // The type-checker sizes the result for any value of the type, so a
+        // smaller result type can only come from a malformed type map.
+        let mut generator =
+            WasmGenerator::new(compile_snippet("(to-consensus-buff? u42)").contract_analysis)
+                .unwrap();
+        let expr = generator.contract_analysis.expressions[0].clone();
+        let small_buff = TypeSignature::SequenceType(SequenceSubtype::BufferType(
+            BufferLength::try_from(16u32).unwrap(),
+        ));
+        generator
+            .set_expr_type(&expr, TypeSignature::new_option(small_buff).unwrap())
+            .unwrap();
+        let (results, ()) = run_top_level(&mut generator.generate().unwrap(), (), |_| {});
+        assert_eq!(results[0].unwrap_i32(), 0);
+    }
+
     //--- `from-consensus-buff?` tests
 
     #[test]
@@ -51,6 +51,7 @@ impl ComplexWord for DefineConstant {
 
             // Write the initial value to the memory, to be read by the host.
             generator.write_to_memory(builder, offset_local, 0, &ty)?;
+            generator.computed_constants.insert(name.to_string());
 
             offset
         };
@@ -160,4 +161,51 @@ mod tests {
             evaluate("(ok 0x12345678)"),
         );
     }
+
+    #[test]
+    fn define_constant_sum() {
+        crosscheck(
+            "
+(define-constant SUM (+ u1 u2))
+(define-read-only (get-sum)
+  (* SUM u10))
+(get-sum)",
+            Ok(Some(Value::UInt(30))),
+        );
+    }
+
+    #[test]
+    fn define_constant_from_private_function() {
+        crosscheck(
+            "
+(define-private (compute) (- 10 3))
+(define-constant X (compute))
+(define-constant Y (* X 2))
+Y",
+            Ok(Some(Value::Int(14))),
+        );
+    }
+
+    #[test]
+    fn define_constant_computed_string() {
+        crosscheck(
+            r#"
+(define-constant greeting (concat "hello " "world"))
+(define-public (get-greeting)
+  (ok greeting))
+(get-greeting)"#,
+            evaluate(r#"(ok "hello world")"#),
+        );
+    }
+
+    #[test]
+    fn define_constant_computed_buffer() {
+        crosscheck(
+            "
+(define-private (pick (a (buff 4)) (b (buff 4))) (if true a b))
+(define-constant bytes (pick 0x0102 0x03040506))
+(len bytes)",
+            Ok(Some(Value::UInt(2))),
+        );
+    }
 }
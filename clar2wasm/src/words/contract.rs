@@ -1,6 +1,6 @@
+use clarity::vm::clarity_wasm::get_type_size;
 use clarity::vm::types::PrincipalData;
 use clarity::vm::{ClarityName, SymbolicExpression, SymbolicExpressionType, Value};
-use walrus::ValType;
 
 use super::ComplexWord;
 use crate::wasm_generator::{ArgumentsExt, GeneratorError, WasmGenerator};
@@ -25,11 +25,9 @@ impl ComplexWord for AsContract {
         // Call the host interface function, `enter_as_contract`
         builder.call(generator.func_by_name("stdlib.enter_as_contract"));
 
-        // Traverse the inner expression
-        generator.traverse_expr(builder, inner)?;
-
-        // Call the host interface function, `exit_as_contract`
-        builder.call(generator.func_by_name("stdlib.exit_as_contract"));
+        // Traverse the inner expression, then call the host interface
+        // function, `exit_as_contract`
+        generator.traverse_in_context(builder, inner, "stdlib.exit_as_contract")?;
 
         Ok(())
     }
@@ -56,7 +54,14 @@ impl ComplexWord for ContractCall {
             ref contract_identifier,
         ))) = contract_expr.expr
         {
-            // This is a static contract call.
+            // This is a static contract call. A contract cannot call itself,
+            // which the type-checker should already have rejected.
+            if *contract_identifier == generator.contract_analysis.contract_identifier {
+                return Err(GeneratorError::InternalError(format!(
+                    "contract {contract_identifier} cannot call itself"
+                )));
+            }
+
             // Push the contract identifier onto the stack
             // TODO(#111): These should be tracked for reuse, similar to the string literals
             let (id_offset, id_length) =
@@ -80,12 +85,9 @@ impl ComplexWord for ContractCall {
             .i32_const(fn_offset as i32)
             .i32_const(fn_length as i32);
 
-        // Write the arguments to the call stack, to be read by the host
-        let arg_offset = generator.module.locals.add(ValType::I32);
-        builder
-            .global_get(generator.stack_pointer)
-            .local_set(arg_offset);
-        let mut arg_length = 0;
+        // Evaluate all the arguments before writing any of them, since the
+        // evaluation of an argument may allocate on the call stack.
+        let mut evaluated_args = Vec::with_capacity(args.len());
         for arg in args {
             // Traverse the argument, pushing it onto the stack
             generator.traverse_expr(builder, arg)?;
@@ -96,7 +98,23 @@ impl ComplexWord for ContractCall {
                     GeneratorError::TypeError("contract-call? argument must be typed".to_owned())
                 })?
                 .clone();
+            let arg_locals = generator.save_to_locals(builder, &arg_ty, true);
+            evaluated_args.push((arg_ty, arg_locals));
+        }
 
+        // Write the arguments to a buffer on the call stack, to be read by the
+        // host. They are laid out contiguously, in order, each one in the
+        // format of `write_to_memory`.
+        let args_size = evaluated_args
+            .iter()
+            .map(|(arg_ty, _)| get_type_size(arg_ty))
+            .sum();
+        let arg_offset = generator.create_call_stack_buffer(builder, args_size);
+        let mut arg_length = 0;
+        for (arg_ty, arg_locals) in evaluated_args {
+            for local in arg_locals {
+                builder.local_get(local);
+            }
             arg_length += generator.write_to_memory(builder, arg_offset, arg_length, &arg_ty)?;
         }
 
@@ -131,7 +149,7 @@ impl ComplexWord for ContractCall {
 mod tests {
     use clarity::vm::Value;
 
-    use crate::tools::TestEnvironment;
+    use crate::tools::{evaluate, TestEnvironment};
 
     #[test]
     fn static_no_args() {
@@ -687,4 +705,188 @@ mod tests {
             .expect("Failed to init contract.");
         assert_eq!(val.unwrap(), Value::Int(-123));
     }
+
+    #[test]
+    fn static_mixed_args() {
+        let mut env = TestEnvironment::default();
+        env.init_contract_with_snippet(
+            "contract-callee",
+            r#"
+(define-public (mixed-args (a uint) (b (string-ascii 10)) (c {x: int, y: (string-ascii 5)}))
+    (ok {a: a, b: b, c: c})
+)
+            "#,
+        )
+        .expect("Failed to init contract.");
+        // The evaluation of `b` allocates on the call stack, which must not
+        // overwrite `a`, already evaluated.
+        let val = env
+            .init_contract_with_snippet(
+                "contract-caller",
+                r#"(contract-call? .contract-callee mixed-args u1 (concat "ab" "cd") {x: -2, y: "hey"})"#,
+            )
+            .expect("Failed to init contract.");
+
+        assert_eq!(
+            val,
+            evaluate(r#"(ok {a: u1, b: "abcd", c: {x: -2, y: "hey"}})"#).unwrap()
+        );
+    }
+
+    #[test]
+    fn static_sequence_args() {
+        let mut env = TestEnvironment::default();
+        env.init_contract_with_snippet(
+            "contract-callee",
+            r#"
+(define-public (string-len (s (string-ascii 20)))
+    (ok (len s))
+)
+(define-public (sequence-lens (s (string-ascii 20)) (b (buff 8)) (l (list 4 int)))
+    (ok {s: (len s), b: (len b), l: (len l)})
+)
+;; The argument must still be readable after the callee allocates on its
+;; own call stack.
+(define-public (prefix (s (string-ascii 20)))
+    (let ((prefixed (concat "> " s)))
+        (ok {s: s, prefixed: prefixed})
+    )
+)
+            "#,
+        )
+        .expect("Failed to init contract.");
+
+        let val = env
+            .init_contract_with_snippet(
+                "contract-caller-len",
+                r#"(contract-call? .contract-callee string-len (concat "hello" " world"))"#,
+            )
+            .expect("Failed to init contract.");
+        assert_eq!(val, evaluate("(ok u11)").unwrap());
+
+        let val = env
+            .init_contract_with_snippet(
+                "contract-caller-lens",
+                r#"(contract-call? .contract-callee sequence-lens "abc" 0x0102 (list 1 2 3 4))"#,
+            )
+            .expect("Failed to init contract.");
+        assert_eq!(val, evaluate("(ok {s: u3, b: u2, l: u4})").unwrap());
+
+        let val = env
+            .init_contract_with_snippet(
+                "contract-caller-prefix",
+                r#"(contract-call? .contract-callee prefix "hey")"#,
+            )
+            .expect("Failed to init contract.");
+        assert_eq!(
+            val,
+            evaluate(r#"(ok {s: "hey", prefixed: "> hey"})"#).unwrap()
+        );
+    }
+
+    #[test]
+    fn read_only_sequence_args() {
+        let id = format!("0x{}", "01".repeat(32));
+        let mut env = TestEnvironment::default();
+        env.init_contract_with_snippet(
+            "read-only-callee",
+            &format!(
+                r#"
+(define-map items (buff 32) uint)
+(map-set items {id} u42)
+(define-read-only (get-item (id (buff 32)))
+    (default-to u0 (map-get? items id))
+)
+(define-read-only (describe (name (string-utf8 10)) (ids (list 2 (buff 32))))
+    {{name: (len name), items: (map get-item ids)}}
+)
+"#
+            ),
+        )
+        .expect("Failed to init contract.");
+
+        let val = env
+            .init_contract_with_snippet(
+                "read-only-caller-item",
+                &format!("(contract-call? .read-only-callee get-item {id})"),
+            )
+            .expect("Failed to init contract.");
+        assert_eq!(val, evaluate("u42").unwrap());
+
+        let val = env
+            .init_contract_with_snippet(
+                "read-only-caller-describe",
+                &format!(
+                    r#"(contract-call? .read-only-callee describe u"caf\u{{e9}}" (list {id} 0x02))"#
+                ),
+            )
+            .expect("Failed to init contract.");
+        assert_eq!(val, evaluate("{name: u4, items: (list u42 u0)}").unwrap());
+    }
+
+    #[test]
+    fn read_only_cannot_write() {
+        let mut env = TestEnvironment::default();
+        env.init_contract_with_snippet(
+            "read-only-writer",
+            "
+(define-data-var count uint u0)
+(define-read-only (increment (by (buff 1)))
+    (var-set count (+ (var-get count) (len by)))
+)
+",
+        )
+        .expect_err("a read-only function must not write");
+    }
+
+    #[test]
+    fn static_call_to_self_is_an_error() {
+        let mut env = TestEnvironment::default();
+        env.init_contract_with_snippet("self-caller", "(define-public (f) (ok u1))")
+            .expect("Failed to init contract.");
+
+        // The analysis of the first deployment lets the type-checker accept
+        // the call, so it must be rejected by the generator.
+        let err = env
+            .init_contract_with_snippet(
+                "self-caller",
+                "
+(define-public (f) (ok u1))
+(define-public (g) (contract-call? .self-caller f))
+",
+            )
+            .expect_err("a contract must not call itself");
+        assert!(
+            format!("{err:?}").contains("cannot call itself"),
+            "unexpected error: {err:?}"
+        );
+    }
+
+    #[test]
+    fn begin_drops_ignored_call_results() {
+        let mut env = TestEnvironment::default();
+        env.init_contract_with_snippet(
+            "contract-callee",
+            r#"
+(define-public (f)
+    (ok { a: u1, b: (some "x"), c: (list 1 2) }))
+            "#,
+        )
+        .expect("Failed to init contract.");
+        let val = env
+            .init_contract_with_snippet(
+                "contract-caller",
+                "
+(define-public (g)
+  (begin
+    (unwrap-panic (contract-call? .contract-callee f))
+    (try! (contract-call? .contract-callee f))
+    (is-ok (contract-call? .contract-callee f))
+    (ok true)))
+(g)",
+            )
+            .expect("Failed to init contract.");
+
+        assert_eq!(val.unwrap(), Value::okay_true());
+    }
 }
@@ -3,20 +3,9 @@ use clarity::vm::{ClarityName, SymbolicExpression};
 use walrus::ir::{IfElse, UnaryOp};
 
 use super::ComplexWord;
-use crate::wasm_generator::{drop_value, ArgumentsExt, GeneratorError, WasmGenerator};
-
-/// `Trap` should match the values used in the standard library and is used to
-/// indicate the reason for a runtime error from the Clarity code.
-#[allow(dead_code)]
-#[repr(i32)]
-enum Trap {
-    Overflow = 0,
-    Underflow = 1,
-    DivideByZero = 2,
-    LogOfNumberLessThanOrEqualToZero = 3,
-    ExpectedANonNegativeNumber = 4,
-    Panic = 5,
-}
+use crate::wasm_generator::{
+    drop_value, ArgumentsExt, GeneratorError, RuntimeError, WasmGenerator,
+};
 
 #[derive(Debug)]
 pub struct Begin;
@@ -92,7 +81,7 @@ impl ComplexWord for UnwrapPanic {
                 // If the indicator is 0, throw a runtime error
                 let if_id = {
                     let mut if_case = builder.dangling_instr_seq(None);
-                    if_case.i32_const(Trap::Panic as i32).call(
+                    if_case.i32_const(RuntimeError::Panic as i32).call(
                         generator
                             .module
                             .funcs
@@ -146,7 +135,7 @@ impl ComplexWord for UnwrapPanic {
                 // If the indicator is 0, throw a runtime error
                 let if_id = {
                     let mut if_case = builder.dangling_instr_seq(None);
-                    if_case.i32_const(Trap::Panic as i32).call(
+                    if_case.i32_const(RuntimeError::Panic as i32).call(
                         generator
                             .module
                             .funcs
@@ -242,7 +231,7 @@ impl ComplexWord for UnwrapErrPanic {
 
                 let else_id = {
                     let mut else_case = builder.dangling_instr_seq(None);
-                    else_case.i32_const(Trap::Panic as i32).call(
+                    else_case.i32_const(RuntimeError::Panic as i32).call(
                         generator
                             .module
                             .funcs
@@ -278,7 +267,10 @@ mod tests {
     use clarity::vm::errors::{Error, WasmError};
     use clarity::vm::Value;
 
-    use crate::tools::{crosscheck, evaluate, TestEnvironment};
+    use crate::tools::{
+        compile_snippet, crosscheck, evaluate, top_level_runtime_error, TestEnvironment,
+    };
+    use crate::wasm_generator::RuntimeError;
 
     #[test]
     fn test_unwrap_panic_some() {
@@ -301,6 +293,30 @@ mod tests {
         matches!(err, Error::Wasm(WasmError::Runtime(_)));
     }
 
+    #[test]
+    fn unwrap_panic_raises_a_panic_runtime_error() {
+        let runtime_error = |snippet| {
+            let mut module = compile_snippet(snippet).module;
+            top_level_runtime_error(&mut module, |linker| {
+                linker
+                    .func_wrap("clarity", "define_function", |_: i32, _: i32, _: i32| {})
+                    .unwrap();
+            })
+        };
+        assert_eq!(runtime_error("(unwrap-panic (some u1))"), None);
+        assert_eq!(
+            runtime_error(
+                "
+(define-private (unwrap-opt (x (optional uint)))
+    (unwrap-panic x)
+)
+(unwrap-opt none)
+"
+            ),
+            Some(RuntimeError::Panic)
+        );
+    }
+
     #[test]
     fn test_unwrap_panic_ok() {
         crosscheck("(unwrap-panic (ok u2))", Ok(Some(Value::UInt(2))));
@@ -74,12 +74,9 @@ impl ComplexWord for DefineDataVar {
                 })?,
         );
 
-        // Add type to the datavars_types (for var-set workaround)
-        if generator.datavars_types.insert(name.clone(), ty).is_some() {
-            return Err(GeneratorError::InternalError(format!(
-                "Data var defined twice: {name}"
-            )));
-        }
+        // The type was added to the datavars_types (for var-set workaround),
+        // and a var defined twice was rejected, when the top-level definitions
+        // were registered, before the traversal.
 
         Ok(())
     }
@@ -218,7 +215,10 @@ impl ComplexWord for GetDataVar {
 
 #[cfg(test)]
 mod tests {
-    use crate::tools::{crosscheck, evaluate};
+    use clarity::vm::Value;
+
+    use crate::tools::{crosscheck, crosscheck_compare_only, evaluate, generation_error};
+    use crate::wasm_generator::GeneratorError;
 
     #[test]
     fn test_var_get() {
@@ -251,4 +251,153 @@ mod tests {
             evaluate("(ok 5368002525449479521366)"),
         );
     }
+
+    #[test]
+    fn test_var_forward_reference() {
+        crosscheck(
+            "
+(define-public (bump)
+  (begin
+    (var-set counter (+ (var-get counter) 1))
+    (ok (var-get counter))))
+
+(define-data-var counter int 1)
+
+(bump)
+",
+            evaluate("(ok 2)"),
+        );
+    }
+
+    #[test]
+    fn test_var_set_uses_declared_type() {
+        crosscheck(
+            "
+(define-data-var something (optional int) none)
+
+(define-public (simple)
+  (begin
+    (var-set something (some 42))
+    (ok (var-get something))))
+
+(simple)
+",
+            evaluate("(ok (some 42))"),
+        );
+    }
+
+    #[test]
+    fn test_var_get_undefined() {
+        let err = generation_error(
+            "
+(define-data-var something int 123)
+(var-get something)
+",
+            |analysis| {
+                // Drop the definition, so that `something` is truly undefined.
+                analysis.expressions.remove(0);
+                analysis.persisted_variable_types.clear();
+            },
+        );
+        assert!(matches!(err, GeneratorError::TypeError(msg) if msg.contains("something")));
+    }
+
+    #[test]
+    fn test_var_defined_twice() {
+        let err = generation_error(
+            "
+(define-data-var something int 123)
+(var-get something)
+",
+            |analysis| {
+                // The analysis rejects a second definition, so it is added
+                // afterwards.
+                let definition = analysis.expressions[0].clone();
+                analysis.expressions.insert(1, definition);
+            },
+        );
+        assert!(
+            matches!(err, GeneratorError::InternalError(msg) if msg == "Data var defined twice: something")
+        );
+    }
+
+    #[test]
+    fn test_var_large_list_round_trip() {
+        let items = (1..=100)
+            .map(|i| format!("u{i}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        crosscheck(
+            &format!(
+                "
+(define-data-var numbers (list 100 uint) (list))
+
+(define-public (store)
+  (begin
+    (var-set numbers (list {items}))
+    (ok (var-get numbers))))
+
+(store)
+"
+            ),
+            evaluate(&format!("(ok (list {items}))")),
+        );
+    }
+
+    #[test]
+    fn test_var_computed_initial_value() {
+        crosscheck(
+            "
+(define-data-var total uint (+ u10 u5))
+(var-get total)
+",
+            Ok(Some(Value::UInt(15))),
+        );
+    }
+
+    #[test]
+    fn test_var_initial_value_from_constant() {
+        crosscheck(
+            "
+(define-constant base u40)
+(define-data-var total uint (+ base u2))
+(var-get total)
+",
+            Ok(Some(Value::UInt(42))),
+        );
+    }
+
+    #[test]
+    fn test_var_initial_value_from_function() {
+        crosscheck_compare_only(
+            "
+(define-read-only (double (n uint)) (* n u2))
+(define-data-var total uint (double u21))
+(var-get total)
+",
+        );
+    }
+
+    #[test]
+    fn test_var_tuple_round_trip() {
+        let preamble = r#"
+(define-data-var config
+    {owner: (optional principal), limit: uint, label: (string-ascii 8)}
+    {owner: none, limit: u0, label: ""})
+
+(define-public (configure)
+  (begin
+    (var-set config {owner: (some tx-sender), limit: u500, label: "primary"})
+    (ok (var-get config))))
+"#;
+        crosscheck_compare_only(&format!("{preamble} (configure)"));
+        crosscheck(
+            &format!("{preamble} (configure) (get limit (var-get config))"),
+            Ok(Some(Value::UInt(500))),
+        );
+        crosscheck(
+            &format!("{preamble} (configure) (get label (var-get config))"),
+            evaluate(r#""primary""#),
+        );
+    }
 }
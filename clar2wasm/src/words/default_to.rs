@@ -89,3 +89,55 @@ impl ComplexWord for DefaultTo {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::tools::{crosscheck, evaluate};
+
+    #[test]
+    fn default_to_composite_some() {
+        crosscheck(
+            "(default-to { a: u0 } (some { a: u5 }))",
+            evaluate("{ a: u5 }"),
+        );
+    }
+
+    #[test]
+    fn default_to_composite_none() {
+        crosscheck(
+            r#"
+(define-data-var o (optional { a: uint, b: (string-ascii 4) }) none)
+(default-to { a: u0, b: "zero" } (var-get o))"#,
+            evaluate(r#"{ a: u0, b: "zero" }"#),
+        );
+    }
+
+    #[test]
+    fn default_to_response_and_sequence() {
+        crosscheck(
+            r#"
+(define-data-var r (optional (response (list 3 int) uint)) none)
+(list
+  (default-to (err u1) (var-get r))
+  (default-to (err u1) (some (ok (list 1 2 3)))))"#,
+            evaluate("(list (err u1) (ok (list 1 2 3)))"),
+        );
+    }
+
+    #[test]
+    fn default_to_evaluates_its_default_with_some() {
+        // As in the interpreter, the default is evaluated even when it is not
+        // used.
+        crosscheck(
+            "
+(define-data-var count uint u0)
+(define-private (bump)
+  (begin (var-set count (+ (var-get count) u1)) { a: u0 }))
+(begin
+  (default-to (bump) (some { a: u5 }))
+  (default-to (bump) none)
+  (var-get count))",
+            evaluate("u2"),
+        );
+    }
+}
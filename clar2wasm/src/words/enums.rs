@@ -3,7 +3,7 @@ use clarity::vm::{ClarityName, SymbolicExpression};
 
 use super::ComplexWord;
 use crate::wasm_generator::{
-    add_placeholder_for_type, clar2wasm_ty, ArgumentsExt, GeneratorError, WasmGenerator,
+    add_placeholder_for_clarity_type, ArgumentsExt, GeneratorError, WasmGenerator,
 };
 
 #[derive(Debug)]
@@ -77,10 +77,7 @@ impl ComplexWord for ClarityOk {
         generator.traverse_expr(builder, value)?;
 
         // deal with err placeholders
-        let err_types = clar2wasm_ty(&inner_types.1);
-        for err_type in err_types.iter() {
-            add_placeholder_for_type(builder, *err_type);
-        }
+        add_placeholder_for_clarity_type(builder, &inner_types.1);
 
         Ok(())
     }
@@ -109,10 +106,7 @@ impl ComplexWord for ClarityErr {
             .get_expr_type(expr)
             .ok_or_else(|| GeneratorError::TypeError("err expression must be typed".to_owned()))?;
         if let TypeSignature::ResponseType(inner_types) = ty {
-            let ok_types = clar2wasm_ty(&inner_types.0);
-            for ok_type in ok_types.iter() {
-                add_placeholder_for_type(builder, *ok_type);
-            }
+            add_placeholder_for_clarity_type(builder, &inner_types.0);
             // WORKAROUND: set full type to err value
             generator.set_expr_type(value, inner_types.1.clone())?
         } else {
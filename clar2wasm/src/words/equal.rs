@@ -60,7 +60,9 @@ impl ComplexWord for IsEq {
         // Initialize boolean result accumulator to TRUE
         builder.i32_const(1);
 
-        // Loop through remainder operands, if the case.
+        // Loop through remainder operands, if the case. Every operand is
+        // evaluated, but once an operand differs from the first one, the
+        // next ones are not compared anymore.
         for operand in args.iter().skip(1) {
             // push the new operand on the stack
             generator.traverse_expr(builder, operand)?;
@@ -74,18 +76,28 @@ impl ComplexWord for IsEq {
                 .clone();
             assign_to_locals(builder, &ty, &operand_ty, &nth_locals)?;
 
-            // check equality
+            // check equality if all the previous operands were equal,
+            // the accumulator being the condition.
+            let block_ty = InstrSeqType::new(&mut generator.module.types, &[], &[ValType::I32]);
+            let mut compare = builder.dangling_instr_seq(block_ty);
+            let compare_id = compare.id();
             wasm_equal(
                 &ty,
                 &operand_ty,
                 generator,
-                builder,
+                &mut compare,
                 &val_locals,
                 &nth_locals,
             )?;
 
-            // Do an "and" operation with the result from the previous function call.
-            builder.binop(BinaryOp::I32And);
+            let mut not_equal = builder.dangling_instr_seq(block_ty);
+            let not_equal_id = not_equal.id();
+            not_equal.i32_const(0);
+
+            builder.instr(IfElse {
+                consequent: compare_id,
+                alternative: not_equal_id,
+            });
         }
 
         Ok(())
@@ -815,6 +827,13 @@ fn wasm_equal_tuple(
     Ok(())
 }
 
+/// Compares two lists element by element.
+///
+/// Elements are read back into locals and compared with [`wasm_equal`] rather
+/// than comparing the raw bytes of both lists. The in-memory layout of an
+/// element is not canonical: the payload of a `none` or of the unused side of
+/// a response is a placeholder, and offsets of in-memory values depend on
+/// where they were allocated. Only the recursive comparison ignores those.
 fn wasm_equal_list(
     generator: &mut WasmGenerator,
     builder: &mut InstrSeqBuilder,
@@ -970,7 +989,7 @@ mod tests {
     use clarity::vm::types::{ListData, ListTypeData, SequenceData};
     use clarity::vm::Value;
 
-    use crate::tools::{crosscheck, TestEnvironment};
+    use crate::tools::{crosscheck, evaluate, TestEnvironment};
 
     #[test]
     fn index_of_list_not_present() {
@@ -1130,6 +1149,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn index_of_utf8_after_multi_byte_chars() {
+        crosscheck(
+            r#"
+(define-data-var s (string-utf8 10) u"\u{E9}\u{1F98A}\u{4E2D}a\u{1F98A}")
+{
+  fox: (index-of? (var-get s) u"\u{1F98A}"),
+  han: (index-of? (var-get s) u"\u{4E2D}"),
+  a: (index-of? (var-get s) u"a"),
+  missing: (index-of? (var-get s) u"b")
+}"#,
+            evaluate("{ fox: (some u1), han: (some u2), a: (some u3), missing: none }"),
+        );
+    }
+
     #[test]
     fn index_of_utf8_zero_len() {
         crosscheck("(index-of u\"Stacks\" u\"\")", Ok(Some(Value::none())));
@@ -1202,4 +1236,108 @@ mod tests {
             Ok(Some(Value::none()))
         );
     }
+
+    #[test]
+    fn is_eq_list_of_optionals_with_runtime_none() {
+        crosscheck(
+            "
+(define-private (keep-odd (n int))
+  (if (is-eq (mod n 2) 1) (some n) none))
+(is-eq
+  (map keep-odd (list 1 2 3 4))
+  (list (some 1) (element-at? (list 5 6) u9) (some 3) none))
+",
+            Ok(Some(Value::Bool(true))),
+        );
+    }
+
+    #[test]
+    fn is_eq_list_of_optionals_differs_on_some() {
+        crosscheck(
+            "(is-eq (list none (some 1)) (list none (some 2)))",
+            Ok(Some(Value::Bool(false))),
+        );
+    }
+
+    #[test]
+    fn is_eq_nested_lists_of_optionals() {
+        crosscheck(
+            "
+(define-data-var nested (list 2 (list 2 (optional (buff 4)))) (list (list (some 0x0102) none)))
+(var-set nested (list (list (some 0x0102) (some 0x03)) (list none)))
+(var-set nested (list (list (some 0x0102) none)))
+(is-eq (var-get nested) (list (list (some 0x0102) none)))
+",
+            Ok(Some(Value::Bool(true))),
+        );
+    }
+
+    #[test]
+    fn is_eq_list_of_responses() {
+        crosscheck(
+            "(is-eq (list (ok 1) (err u2)) (list (if true (ok 1) (err u9)) (err u2)))",
+            Ok(Some(Value::Bool(true))),
+        );
+    }
+
+    #[test]
+    fn is_eq_many_composite_operands() {
+        crosscheck(
+            "
+(is-eq
+  {a: (list (some 1)), b: 0x01}
+  {a: (list (some 1)), b: 0x01}
+  {a: (list (some 1)), b: 0x01}
+  {a: (list (some 1)), b: 0x01})
+",
+            Ok(Some(Value::Bool(true))),
+        );
+        crosscheck(
+            "(is-eq (list (some 1)) (list (some 1)) (list none) (list (some 1)))",
+            Ok(Some(Value::Bool(false))),
+        );
+    }
+
+    #[test]
+    fn is_eq_evaluates_operands_after_a_difference() {
+        crosscheck(
+            "
+(define-data-var counter uint u0)
+(define-private (bump (x uint))
+  (begin (var-set counter (+ (var-get counter) u1)) x))
+(let ((eq (is-eq u1 (bump u2) (bump u1) (bump u1))))
+  {eq: eq, count: (var-get counter)})
+",
+            evaluate("{eq: false, count: u3}"),
+        );
+    }
+
+    #[test]
+    fn is_eq_tx_sender() {
+        crosscheck("(is-eq tx-sender tx-sender)", Ok(Some(Value::Bool(true))));
+        crosscheck(
+            "(is-eq tx-sender 'S1G2081040G2081040G2081040G208105NK8PE5)",
+            Ok(Some(Value::Bool(true))),
+        );
+        crosscheck(
+            "(is-eq tx-sender 'ST1PQHQKV0RJXZFY1DGX8MNSNYVE3VGZJSRTPGZGM)",
+            Ok(Some(Value::Bool(false))),
+        );
+    }
+
+    #[test]
+    fn is_eq_contract_principals() {
+        crosscheck(
+            "(is-eq tx-sender 'S1G2081040G2081040G2081040G208105NK8PE5.contract)",
+            Ok(Some(Value::Bool(false))),
+        );
+        crosscheck(
+            "(is-eq 'S1G2081040G2081040G2081040G208105NK8PE5.foo 'S1G2081040G2081040G2081040G208105NK8PE5.bar)",
+            Ok(Some(Value::Bool(false))),
+        );
+        crosscheck(
+            "(is-eq 'S1G2081040G2081040G2081040G208105NK8PE5.foo 'S1G2081040G2081040G2081040G208105NK8PE5.foo)",
+            Ok(Some(Value::Bool(true))),
+        );
+    }
 }
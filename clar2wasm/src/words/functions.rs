@@ -142,6 +142,48 @@ mod tests {
         );
     }
 
+    #[test]
+    fn call_private_with_uint_args() {
+        crosscheck(
+            "
+(define-private (sub-uint (a uint) (b uint))
+  (- a b))
+
+(sub-uint u5 u3)
+",
+            evaluate("u2"),
+        );
+    }
+
+    #[test]
+    fn call_private_with_string_arg() {
+        crosscheck(
+            r#"
+(define-private (greet (name (string-ascii 10)))
+  (concat "hello " name))
+
+(greet "world")
+"#,
+            evaluate(r#""hello world""#),
+        );
+    }
+
+    #[test]
+    fn call_private_with_less_precise_args() {
+        crosscheck(
+            "
+(define-private (or-default (a (optional uint)) (b (response uint int)))
+  (+ (default-to u1 a) (unwrap! b u0)))
+
+(define-private (total (l (list 3 uint)))
+  (fold + l u0))
+
+(list (or-default none (ok u2)) (or-default (some u3) (err 1)) (total (list)))
+",
+            evaluate("(list u3 u0 u0)"),
+        );
+    }
+
     #[test]
     fn call_public() {
         let preamble = "
@@ -195,4 +237,22 @@ mod tests {
             evaluate("(ok none)"),
         );
     }
+
+    #[test]
+    fn private_without_params_returns_statement_result() {
+        let preamble = "
+(define-data-var x uint u0)
+(define-private (setup) (var-set x u1))
+";
+        // The result of `setup` is used as a value.
+        crosscheck(
+            &format!("{preamble} (define-public (run) (ok (setup))) (run)"),
+            evaluate("(ok true)"),
+        );
+        // The result of `setup` is dropped.
+        crosscheck(
+            &format!("{preamble} (define-public (run) (begin (setup) (ok (var-get x)))) (run)"),
+            evaluate("(ok u1)"),
+        );
+    }
 }
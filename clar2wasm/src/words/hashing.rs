@@ -1,8 +1,9 @@
 use clarity::vm::types::{SequenceSubtype, TypeSignature, BUFF_32};
 use clarity::vm::ClarityName;
+use walrus::ValType;
 
 use super::SimpleWord;
-use crate::wasm_generator::{GeneratorError, WasmGenerator};
+use crate::wasm_generator::{CostOp, GeneratorError, WasmGenerator};
 
 pub fn traverse_hash(
     name: &'static str,
@@ -16,8 +17,17 @@ pub fn traverse_hash(
     generator.literal_memory_end += mem_size as u32; // 5 u32
 
     let hash_type = match arg_types[0] {
-        TypeSignature::IntType | TypeSignature::UIntType => "int",
-        TypeSignature::SequenceType(SequenceSubtype::BufferType(_)) => "buf",
+        TypeSignature::IntType | TypeSignature::UIntType => {
+            generator.charge_cost(builder, CostOp::Hash, 16);
+            "int"
+        }
+        TypeSignature::SequenceType(SequenceSubtype::BufferType(_)) => {
+            // The length of the buffer is on the top of the stack.
+            let length = generator.module.locals.add(ValType::I32);
+            builder.local_tee(length);
+            generator.charge_cost_bytes(builder, CostOp::Hash, length);
+            "buf"
+        }
         _ => {
             return Err(GeneratorError::NotImplemented);
         }
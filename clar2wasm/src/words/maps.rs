@@ -1,8 +1,57 @@
+use clarity::vm::types::TypeSignature;
 use clarity::vm::{ClarityName, SymbolicExpression};
 
 use super::ComplexWord;
 use crate::wasm_generator::{ArgumentsExt, GeneratorError, LiteralMemoryEntry, WasmGenerator};
 
+/// The declared key and value types of the map `name`.
+fn map_types(
+    generator: &WasmGenerator,
+    name: &ClarityName,
+) -> Result<(TypeSignature, TypeSignature), GeneratorError> {
+    generator
+        .contract_analysis
+        .map_types
+        .get(name)
+        .cloned()
+        .ok_or_else(|| GeneratorError::InternalError(format!("map not found: {name}")))
+}
+
+/// Write the key `key` of a map with keys of type `key_ty` to a new local on
+/// the call stack, and push the offset and size of the local.
+///
+/// WORKAROUND: the key is written with the type of the map rather than the
+/// type of `key`, so that it has the same layout whatever the type of `key`,
+/// like a `none` or a shorter list, which is the layout the host reads it
+/// back with.
+fn write_map_key(
+    generator: &mut WasmGenerator,
+    builder: &mut walrus::InstrSeqBuilder,
+    key_ty: &TypeSignature,
+    key: &SymbolicExpression,
+) -> Result<(), GeneratorError> {
+    generator.set_expr_type(key, key_ty.clone())?;
+    let (offset, size) = generator.create_call_stack_local(builder, key_ty, true, false);
+
+    // Push the key to the data stack, and write it to the memory
+    generator.traverse_expr(builder, key)?;
+    generator.write_to_memory(builder, offset, 0, key_ty)?;
+
+    builder.local_get(offset).i32_const(size);
+    Ok(())
+}
+
+/// Write the value `value` of a map with values of type `value_ty` to a new
+/// local on the call stack, in the same way as a key, see [`write_map_key`].
+fn write_map_value(
+    generator: &mut WasmGenerator,
+    builder: &mut walrus::InstrSeqBuilder,
+    value_ty: &TypeSignature,
+    value: &SymbolicExpression,
+) -> Result<(), GeneratorError> {
+    write_map_key(generator, builder, value_ty, value)
+}
+
 #[derive(Debug)]
 pub struct MapDefinition;
 
@@ -73,23 +122,9 @@ impl ComplexWord for MapGet {
             .i32_const(id_offset as i32)
             .i32_const(id_length as i32);
 
-        // Create space on the call stack to write the key
-        let ty = generator
-            .get_expr_type(key)
-            .ok_or_else(|| {
-                GeneratorError::TypeError("map-set value expression must be typed".to_owned())
-            })?
-            .clone();
-        let (key_offset, key_size) = generator.create_call_stack_local(builder, &ty, true, false);
-
-        // Push the key to the data stack
-        generator.traverse_expr(builder, key)?;
-
-        // Write the key to the memory (it's already on the data stack)
-        generator.write_to_memory(builder, key_offset, 0, &ty)?;
-
-        // Push the key offset and size to the data stack
-        builder.local_get(key_offset).i32_const(key_size);
+        // Write the key to the call stack, and push its offset and size
+        let (key_ty, _) = map_types(generator, name)?;
+        write_map_key(generator, builder, &key_ty, key)?;
 
         // Create a new local to hold the result on the call stack
         let ty = generator
@@ -146,41 +181,12 @@ impl ComplexWord for MapSet {
             .i32_const(id_offset as i32)
             .i32_const(id_length as i32);
 
-        // Create space on the call stack to write the key
-        let ty = generator
-            .get_expr_type(key)
-            .ok_or_else(|| {
-                GeneratorError::TypeError("map-set value expression must be typed".to_owned())
-            })?
-            .clone();
-        let (key_offset, key_size) = generator.create_call_stack_local(builder, &ty, true, false);
-
-        // Push the key to the data stack
-        generator.traverse_expr(builder, key)?;
-
-        // Write the key to the memory (it's already on the data stack)
-        generator.write_to_memory(builder, key_offset, 0, &ty)?;
+        // Write the key to the call stack, and push its offset and size
+        let (key_ty, value_ty) = map_types(generator, name)?;
+        write_map_key(generator, builder, &key_ty, key)?;
 
-        // Push the key offset and size to the data stack
-        builder.local_get(key_offset).i32_const(key_size);
-
-        // Create space on the call stack to write the value
-        let ty = generator
-            .get_expr_type(value)
-            .ok_or_else(|| {
-                GeneratorError::TypeError("map-set value expression must be typed".to_owned())
-            })?
-            .clone();
-        let (val_offset, val_size) = generator.create_call_stack_local(builder, &ty, true, false);
-
-        // Push the value to the data stack
-        generator.traverse_expr(builder, value)?;
-
-        // Write the value to the memory (it's already on the data stack)
-        generator.write_to_memory(builder, val_offset, 0, &ty)?;
-
-        // Push the value offset and size to the data stack
-        builder.local_get(val_offset).i32_const(val_size);
+        // Write the value to the call stack, and push its offset and size
+        write_map_value(generator, builder, &value_ty, value)?;
 
         // Call the host interface function, `map_set`
         builder.call(generator.func_by_name("stdlib.map_set"));
@@ -220,41 +226,12 @@ impl ComplexWord for MapInsert {
             .i32_const(id_offset as i32)
             .i32_const(id_length as i32);
 
-        // Create space on the call stack to write the key
-        let ty = generator
-            .get_expr_type(key)
-            .ok_or_else(|| {
-                GeneratorError::TypeError("map-set value expression must be typed".to_owned())
-            })?
-            .clone();
-        let (key_offset, key_size) = generator.create_call_stack_local(builder, &ty, true, false);
-
-        // Push the key to the data stack
-        generator.traverse_expr(builder, key)?;
-
-        // Write the key to the memory (it's already on the data stack)
-        generator.write_to_memory(builder, key_offset, 0, &ty)?;
-
-        // Push the key offset and size to the data stack
-        builder.local_get(key_offset).i32_const(key_size);
-
-        // Create space on the call stack to write the value
-        let ty = generator
-            .get_expr_type(value)
-            .ok_or_else(|| {
-                GeneratorError::TypeError("map-set value expression must be typed".to_owned())
-            })?
-            .clone();
-        let (val_offset, val_size) = generator.create_call_stack_local(builder, &ty, true, false);
-
-        // Push the value to the data stack
-        generator.traverse_expr(builder, value)?;
-
-        // Write the value to the memory (it's already on the data stack)
-        generator.write_to_memory(builder, val_offset, 0, &ty)?;
+        // Write the key to the call stack, and push its offset and size
+        let (key_ty, value_ty) = map_types(generator, name)?;
+        write_map_key(generator, builder, &key_ty, key)?;
 
-        // Push the value offset and size to the data stack
-        builder.local_get(val_offset).i32_const(val_size);
+        // Write the value to the call stack, and push its offset and size
+        write_map_value(generator, builder, &value_ty, value)?;
 
         // Call the host interface function, `map_insert`
         builder.call(generator.func_by_name("stdlib.map_insert"));
@@ -294,23 +271,9 @@ impl ComplexWord for MapDelete {
             .i32_const(id_offset as i32)
             .i32_const(id_length as i32);
 
-        // Create space on the call stack to write the key
-        let ty = generator
-            .get_expr_type(key)
-            .ok_or_else(|| {
-                GeneratorError::TypeError("map-set value expression must be typed".to_owned())
-            })?
-            .clone();
-        let (key_offset, key_size) = generator.create_call_stack_local(builder, &ty, true, false);
-
-        // Push the key to the data stack
-        generator.traverse_expr(builder, key)?;
-
-        // Write the key to the memory (it's already on the data stack)
-        generator.write_to_memory(builder, key_offset, 0, &ty)?;
-
-        // Push the key offset and size to the data stack
-        builder.local_get(key_offset).i32_const(key_size);
+        // Write the key to the call stack, and push its offset and size
+        let (key_ty, _) = map_types(generator, name)?;
+        write_map_key(generator, builder, &key_ty, key)?;
 
         // Call the host interface function, `map_delete`
         builder.call(
@@ -326,3 +289,166 @@ impl ComplexWord for MapDelete {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use clarity::vm::Value;
+
+    use crate::tools::{crosscheck, evaluate};
+
+    const USERS: &str = "
+(define-map users { id: uint, name: (string-ascii 8) } { score: int, tag: (optional (buff 4)) })
+";
+
+    #[test]
+    fn map_get_tuple_key() {
+        crosscheck(
+            &format!(
+                r#"{USERS}
+(map-set users {{ id: u1, name: "alice" }} {{ score: 10, tag: none }})
+(map-get? users {{ name: "alice", id: u1 }})"#
+            ),
+            evaluate("(some { score: 10, tag: none })"),
+        );
+    }
+
+    #[test]
+    fn map_get_tuple_key_built_at_runtime() {
+        crosscheck(
+            &format!(
+                r#"{USERS}
+(map-insert users {{ id: u2, name: "bob" }} {{ score: -3, tag: (some 0x0102) }})
+(map-get? users {{ id: (+ u1 u1), name: (concat "b" "ob") }})"#
+            ),
+            evaluate("(some { score: -3, tag: (some 0x0102) })"),
+        );
+    }
+
+    #[test]
+    fn map_get_tuple_key_not_found() {
+        crosscheck(
+            &format!(
+                r#"{USERS}
+(map-set users {{ id: u1, name: "alice" }} {{ score: 10, tag: none }})
+(map-get? users {{ id: u1, name: "alic" }})"#
+            ),
+            Ok(Some(Value::none())),
+        );
+    }
+
+    #[test]
+    fn map_delete_tuple_key() {
+        crosscheck(
+            &format!(
+                r#"{USERS}
+(map-set users {{ id: u1, name: "alice" }} {{ score: 10, tag: none }})
+(map-delete users {{ id: u1, name: (concat "ali" "ce") }})"#
+            ),
+            Ok(Some(Value::Bool(true))),
+        );
+    }
+
+    #[test]
+    fn map_optional_key() {
+        crosscheck(
+            "
+(define-map flags (optional uint) bool)
+(map-set flags none true)
+(map-set flags (some u1) false)
+(list (map-get? flags none) (map-get? flags (some u1)) (map-get? flags (some u2)))",
+            evaluate("(list (some true) (some false) none)"),
+        );
+    }
+
+    #[test]
+    fn map_set_from_map_get() {
+        crosscheck(
+            r#"
+(define-map notes uint { body: (string-ascii 16), data: (buff 8) })
+(map-set notes u1 { body: "first note", data: 0x0102 })
+(map-set notes u2 { body: "second", data: 0x03040506 })
+(map-set notes u1 (unwrap-panic (map-get? notes u2)))
+(map-set notes u3 (unwrap-panic (map-get? notes u1)))
+(list (map-get? notes u1) (map-get? notes u2) (map-get? notes u3))"#,
+            evaluate(
+                r#"(list
+  (some { body: "second", data: 0x03040506 })
+  (some { body: "second", data: 0x03040506 })
+  (some { body: "second", data: 0x03040506 }))"#,
+            ),
+        );
+    }
+
+    #[test]
+    fn host_reads_do_not_clobber_each_other() {
+        crosscheck(
+            r#"
+(define-map words uint (string-ascii 8))
+(define-data-var sep (string-ascii 1) "-")
+(map-set words u1 "left")
+(map-set words u2 "right")
+(concat
+  (concat (unwrap-panic (map-get? words u1)) (var-get sep))
+  (concat (unwrap-panic (map-get? words u2)) (var-get sep)))"#,
+            evaluate(r#""left-right-""#),
+        );
+    }
+
+    #[test]
+    fn map_tuple_value_fields() {
+        crosscheck(
+            "
+(define-map accounts uint { balance: uint, active: bool })
+(map-set accounts u7 { balance: u1500, active: true })
+(let ((account (unwrap-panic (map-get? accounts u7))))
+  { balance: (get balance account), active: (get active account), missing: (map-get? accounts u8) })",
+            evaluate("{ balance: u1500, active: true, missing: none }"),
+        );
+    }
+
+    #[test]
+    fn map_response_value() {
+        crosscheck(
+            "
+(define-map results uint (response { balance: uint, active: bool } uint))
+(map-set results u1 (ok { balance: u10, active: false }))
+(map-set results u2 (err u404))
+(list (map-get? results u1) (map-get? results u2) (map-get? results u3))",
+            evaluate("(list (some (ok { balance: u10, active: false })) (some (err u404)) none)"),
+        );
+    }
+
+    #[test]
+    fn map_insert_duplicate_key() {
+        crosscheck(
+            "
+(define-map balances principal uint)
+(let ((first (map-insert balances tx-sender u10))
+      (duplicate (map-insert balances tx-sender u20))
+      (kept (map-get? balances tx-sender))
+      (overwrite (map-set balances tx-sender u30)))
+  { first: first, duplicate: duplicate, kept: kept, overwrite: overwrite,
+    value: (map-get? balances tx-sender) })",
+            evaluate(
+                "{ first: true, duplicate: false, kept: (some u10), overwrite: true, value: (some u30) }",
+            ),
+        );
+    }
+
+    #[test]
+    fn map_insert_evaluates_value_once() {
+        // The key and value are evaluated before the map is written, even if
+        // the key already exists, as in the interpreter.
+        crosscheck(
+            "
+(define-map balances uint uint)
+(define-data-var calls uint u0)
+(define-private (next-value)
+  (begin (var-set calls (+ (var-get calls) u1)) (var-get calls)))
+(let ((first (map-insert balances u1 (next-value)))
+      (duplicate (map-insert balances u1 (next-value))))
+  { first: first, duplicate: duplicate, calls: (var-get calls), value: (map-get? balances u1) })",
+            evaluate("{ first: true, duplicate: false, calls: u2, value: (some u1) }"),
+        );
+    }
+}
@@ -24,6 +24,11 @@ impl ComplexWord for Print {
         // Traverse the value, leaving it on the data stack
         generator.traverse_expr(builder, value)?;
 
+        // Without events, print is a pass-through of its argument.
+        if !generator.config.emit_events {
+            return Ok(());
+        }
+
         // Save the value to locals
         let ty = generator
             .get_expr_type(value)
@@ -41,19 +46,14 @@ impl ComplexWord for Print {
             .global_get(generator.stack_pointer)
             .local_set(offset);
 
-        let ty = generator
-            .get_expr_type(value)
-            .ok_or_else(|| {
-                GeneratorError::TypeError("print value expression must be typed".to_owned())
-            })?
-            .clone();
-
         // Push the value back onto the data stack
         for val_local in &val_locals {
             builder.local_get(*val_local);
         }
 
-        // Write the serialized value to the top of the call stack
+        // Write the serialized value to the top of the call stack. This is a
+        // copy for the host, so the original value, for in-memory types the
+        // bytes pointed to by its offset and length, is left untouched.
         generator.serialize_to_memory(builder, offset, 0, &ty)?;
 
         // Save the length to a local
@@ -66,7 +66,7 @@ impl ComplexWord for Print {
         builder.call(generator.func_by_name("stdlib.print"));
 
         // Print always returns its input, so read the input value back from
-        // the locals.
+        // the locals. No copy is needed for in-memory types.
         for val_local in val_locals {
             builder.local_get(val_local);
         }
@@ -74,3 +74,107 @@ impl ComplexWord for Print {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use clarity::vm::events::StacksTransactionEvent;
+    use clarity::vm::types::{BuffData, OptionalData, SequenceData};
+    use clarity::vm::Value;
+
+    use crate::tools::{
+        compile_snippet_with_config, crosscheck, interpret, run_top_level, TestEnvironment,
+    };
+    use crate::wasm_generator::WasmGeneratorConfig;
+
+    #[test]
+    fn print_returns_its_argument() {
+        crosscheck("(print u42)", Ok(Some(Value::UInt(42))));
+    }
+
+    #[test]
+    fn print_concat_result() {
+        let expected = Value::string_ascii_from_bytes(b"ab".to_vec()).unwrap();
+
+        let mut env = TestEnvironment::default();
+        let result = env
+            .evaluate(r#"(print (concat "a" "b"))"#)
+            .expect("evaluation failed");
+        assert_eq!(result, Some(expected.clone()));
+
+        let printed: Vec<_> = env
+            .get_events()
+            .iter()
+            .filter_map(|event| match event {
+                StacksTransactionEvent::SmartContractEvent(data) => Some(&data.value),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(printed, vec![&expected]);
+    }
+
+    /// Run the top-level of `snippet`, compiled with `config`, with a mocked
+    /// host. Return the results of the top-level and the bytes passed to each
+    /// call of the `print` host function.
+    fn run_with_print_host(
+        snippet: &str,
+        config: WasmGeneratorConfig,
+    ) -> (Vec<wasmtime::Val>, Vec<Vec<u8>>) {
+        let mut module = compile_snippet_with_config(snippet, config).module;
+        run_top_level(&mut module, Vec::new(), |linker| {
+            linker
+                .func_wrap(
+                    "clarity",
+                    "print",
+                    |mut caller: wasmtime::Caller<'_, Vec<Vec<u8>>>, offset: i32, length: i32| {
+                        let memory = caller
+                            .get_export("memory")
+                            .and_then(|export| export.into_memory())
+                            .unwrap();
+                        let mut bytes = vec![0; length as usize];
+                        memory.read(&caller, offset as usize, &mut bytes).unwrap();
+                        caller.data_mut().push(bytes);
+                    },
+                )
+                .unwrap();
+        })
+    }
+
+    /// Run the top-level of `snippet` with a mocked host, and return the
+    /// bytes passed to each call of the `print` host function.
+    fn printed_bytes(snippet: &str) -> Vec<Vec<u8>> {
+        run_with_print_host(snippet, WasmGeneratorConfig::default()).1
+    }
+
+    #[test]
+    fn print_tuple_passes_consensus_bytes_to_host() {
+        let value = r#"{a: 1, b: (list u2 u3), c: (some "x"), d: (ok 0x0102)}"#;
+
+        let Ok(Some(Value::Optional(OptionalData { data: Some(buff) }))) =
+            interpret(&format!("(to-consensus-buff? {value})"))
+        else {
+            panic!("to-consensus-buff? should serialize the tuple");
+        };
+        let Value::Sequence(SequenceData::Buffer(BuffData { data: expected })) = *buff else {
+            panic!("to-consensus-buff? should return a buffer");
+        };
+
+        assert_eq!(printed_bytes(&format!("(print {value})")), vec![expected]);
+    }
+
+    #[test]
+    fn print_without_events_returns_its_argument() {
+        let config = WasmGeneratorConfig {
+            emit_events: false,
+            ..Default::default()
+        };
+        let (results, printed) = run_with_print_host("(+ (print u40) u2)", config);
+        assert!(printed.is_empty());
+        assert_eq!(
+            results
+                .iter()
+                .map(|val| val.unwrap_i64())
+                .collect::<Vec<_>>(),
+            vec![42, 0]
+        );
+    }
+}
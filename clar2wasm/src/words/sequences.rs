@@ -1,12 +1,16 @@
-use clarity::vm::clarity_wasm::get_type_size;
-use clarity::vm::types::{FunctionType, SequenceSubtype, StringSubtype, TypeSignature};
-use clarity::vm::{ClarityName, SymbolicExpression};
-use walrus::ir::{self, BinaryOp, IfElse, InstrSeqType, Loop, UnaryOp};
-use walrus::ValType;
+use std::slice;
+
+use clarity::vm::clarity_wasm::{get_type_in_memory_size, get_type_size};
+use clarity::vm::types::{
+    FunctionType, SequenceData, SequenceSubtype, StringSubtype, TypeSignature,
+};
+use clarity::vm::{ClarityName, SymbolicExpression, SymbolicExpressionType, Value};
+use walrus::ir::{self, BinaryOp, IfElse, InstrSeqType, UnaryOp};
+use walrus::{LocalId, ValType};
 
 use crate::wasm_generator::{
-    add_placeholder_for_clarity_type, clar2wasm_ty, drop_value, type_from_sequence_element,
-    ArgumentsExt, GeneratorError, SequenceElementType, WasmGenerator,
+    add_placeholder_for_clarity_type, clar2wasm_ty, drop_value, sequence_element_type,
+    ArgumentsExt, CostOp, GeneratorError, SequenceElementType, WasmGenerator,
 };
 use crate::words::{self, ComplexWord};
 
@@ -85,6 +89,24 @@ impl ComplexWord for Fold {
         let sequence = args.get_expr(1)?;
         let initial = args.get_expr(2)?;
 
+        // Get the type of the sequence elements
+        let arg_a_ty = generator
+            .get_expr_type(sequence)
+            .and_then(sequence_element_type)
+            .ok_or_else(|| GeneratorError::TypeError("expected sequence type".to_string()))?;
+
+        // The function is called with an element and the accumulator, which
+        // is the initial value for the first call.
+        let initial_ty = generator
+            .get_expr_type(initial)
+            .ok_or_else(|| {
+                GeneratorError::TypeError(
+                    "fold's initial value expression must be typed".to_owned(),
+                )
+            })?
+            .clone();
+        generator.check_callback_signature("fold", func, &[arg_a_ty.clone(), initial_ty], None)?;
+
         // Fold takes an initial value, and a sequence, and applies a function
         // to the output of the previous call, or the initial value in the case
         // of the first call, and each element of the sequence.
@@ -121,133 +143,144 @@ impl ComplexWord for Fold {
                 )
             })?
             .clone();
-        let result_wasm_types = clar2wasm_ty(&result_clar_ty);
 
-        // Get the type of the sequence
-        let elem_ty = generator.get_sequence_element_type(sequence)?;
+        // A sequence returned by the function lives in the function's call
+        // frame, which is reused by the next call. It is copied into a buffer
+        // allocated here, large enough for any value of the result type.
+        let stable_buffer = match &result_clar_ty {
+            TypeSignature::SequenceType(SequenceSubtype::BufferType(_))
+            | TypeSignature::SequenceType(SequenceSubtype::StringType(_)) => {
+                let (buffer, _) =
+                    generator.create_call_stack_local(builder, &result_clar_ty, false, true);
+                Some(buffer)
+            }
+            _ => None,
+        };
 
-        // Evaluate the sequence, which will load it into the call stack,
-        // leaving the offset and size on the data stack.
-        generator.traverse_expr(builder, sequence)?;
-        // STACK: [offset, length]
+        // A short list literal has a length known at compile time, so the
+        // loop can be unrolled.
+        if let Some(elements) = unrollable_fold_elements(generator, sequence) {
+            // Evaluate the elements first, as they would be to build the list.
+            let mut elements_locals = Vec::with_capacity(elements.len());
+            for element in elements {
+                generator.set_expr_type(element, arg_a_ty.clone())?;
+                generator.traverse_expr(builder, element)?;
+                elements_locals.push(generator.save_to_locals(builder, &arg_a_ty, true));
+            }
 
-        let length = generator.module.locals.add(ValType::I32);
-        let offset = generator.module.locals.add(ValType::I32);
-        let end_offset = generator.module.locals.add(ValType::I32);
+            generator.traverse_expr(builder, initial)?;
+            // STACK: [initial_val]
 
-        // Store the length and offset into locals.
-        builder.local_set(length).local_tee(offset);
-        // STACK: [offset]
+            for element_locals in elements_locals {
+                generator.charge_cost(builder, CostOp::LoopIteration, 1);
+                let result_locals = generator.save_to_locals(builder, &result_clar_ty, true);
+                for local in element_locals.into_iter().chain(result_locals) {
+                    builder.local_get(local);
+                }
+                // STACK: [element, intermediate_val]
+                call_fold_function(
+                    generator,
+                    builder,
+                    func,
+                    &arg_a_ty,
+                    &result_clar_ty,
+                    stable_buffer,
+                )?;
+                // STACK: [intermediate_val]
+            }
+            return Ok(());
+        }
 
-        // Compute the ending offset of the sequence.
-        builder
-            .local_get(length)
-            .binop(BinaryOp::I32Add)
-            .local_set(end_offset);
-        // STACK: []
+        // Evaluate the sequence, which will load it into the call stack, and
+        // save its bounds to locals.
+        let iter = generator.traverse_sequence_iter(builder, sequence)?;
 
-        // Evaluate the initial value, so that its result is on the data stack
+        // Evaluate the initial value, and save it to the local(s) holding the
+        // intermediate result.
         generator.traverse_expr(builder, initial)?;
-        // STACK: [initial_val]
-
-        // If the length of the sequence is 0, then just return the initial
-        // value which is already on the stack. Else, loop over the sequence
-        // and apply the function.
-        let then = builder.dangling_instr_seq(InstrSeqType::new(
-            &mut generator.module.types,
-            &result_wasm_types,
-            &result_wasm_types,
-        ));
-        let then_id = then.id();
-
-        let mut else_ = builder.dangling_instr_seq(InstrSeqType::new(
-            &mut generator.module.types,
-            &result_wasm_types,
-            &result_wasm_types,
-        ));
-        let else_id = else_.id();
-
-        // Define local(s) to hold the intermediate result, and initialize them
-        // with the initial value. Note that we are looping in reverse order,
-        // to pop values from the top of the stack.
-        let result_locals = generator.save_to_locals(&mut else_, &result_clar_ty, true);
-
-        // Define the body of a loop, to loop over the sequence and make the
-        // function call.
-        let mut loop_ = else_.dangling_instr_seq(None);
-        let loop_id = loop_.id();
-
-        // Load the element from the sequence
-        let elem_size = match &elem_ty {
-            SequenceElementType::Other(elem_ty) => {
-                generator.read_from_memory(&mut loop_, offset, 0, elem_ty)?
-            }
-            SequenceElementType::Byte => {
-                // The element type is a byte, so we can just push the
-                // offset and length (1) to the stack.
-                loop_.local_get(offset).i32_const(1);
-                1
+        let result_locals = generator.save_to_locals(builder, &result_clar_ty, true);
+
+        // Apply the function to each element and the intermediate result. If
+        // the sequence is empty, the result is the initial value.
+        generator.emit_sequence_loop(builder, slice::from_ref(&iter), |generator, loop_| {
+            generator.charge_cost(loop_, CostOp::LoopIteration, 1);
+            generator.load_sequence_element(loop_, &iter)?;
+            for result_local in &result_locals {
+                loop_.local_get(*result_local);
             }
-            SequenceElementType::UnicodeScalar => {
-                // The element type is a 32-bit unicode scalar, so we can just push the
-                // offset and length (4) to the stack.
-                loop_.local_get(offset).i32_const(4);
-                4
+            // STACK: [element, intermediate_val]
+            call_fold_function(
+                generator,
+                loop_,
+                func,
+                &arg_a_ty,
+                &result_clar_ty,
+                stable_buffer,
+            )?;
+            // Save the result into the locals (in reverse order as we pop)
+            for result_local in result_locals.iter().rev() {
+                loop_.local_set(*result_local);
             }
-        };
+            Ok(())
+        })?;
 
         // Push the locals to the stack
-        for result_local in &result_locals {
-            loop_.local_get(*result_local);
-        }
-
-        if let Some(simple) = words::lookup_simple(func).or(words::lookup_variadic_simple(func)) {
-            // Call simple builtin
-
-            let arg_a_ty = type_from_sequence_element(&elem_ty);
-            let arg_types = &[arg_a_ty, result_clar_ty.clone()];
-
-            simple.visit(generator, &mut loop_, arg_types, &result_clar_ty)?;
-        } else {
-            // Call user defined function
-            generator.visit_call_user_defined(&mut loop_, &result_clar_ty, func)?;
-        }
-        // Save the result into the locals (in reverse order as we pop)
-        for result_local in result_locals.iter().rev() {
-            loop_.local_set(*result_local);
+        for result_local in result_locals {
+            builder.local_get(result_local);
         }
 
-        // Increment the offset by the size of the element, leaving the
-        // offset on the top of the stack
-        loop_
-            .local_get(offset)
-            .i32_const(elem_size)
-            .binop(BinaryOp::I32Add)
-            .local_tee(offset);
-
-        // Loop if we haven't reached the end of the sequence
-        loop_
-            .local_get(end_offset)
-            .binop(BinaryOp::I32LtU)
-            .br_if(loop_id);
+        Ok(())
+    }
+}
 
-        else_.instr(Loop { seq: loop_id });
+/// Returns the elements of `sequence` if it is a list literal short enough
+/// for a `fold` over it to be unrolled.
+fn unrollable_fold_elements<'a>(
+    generator: &WasmGenerator,
+    sequence: &'a SymbolicExpression,
+) -> Option<&'a [SymbolicExpression]> {
+    let threshold = generator.config.fold_unroll_threshold;
+    let (name, elements) = sequence.match_list()?.split_first()?;
+    (threshold > 0 && name.match_atom()?.as_str() == "list" && elements.len() <= threshold)
+        .then_some(elements)
+}
 
-        // Push the locals to the stack
-        for result_local in result_locals {
-            else_.local_get(result_local);
-        }
+/// Call the `fold` function `func` with the element and intermediate value on
+/// top of the stack. If `stable_buffer` is given, the resulting sequence is
+/// copied into it.
+fn call_fold_function(
+    generator: &mut WasmGenerator,
+    builder: &mut walrus::InstrSeqBuilder,
+    func: &ClarityName,
+    element_ty: &TypeSignature,
+    result_ty: &TypeSignature,
+    stable_buffer: Option<LocalId>,
+) -> Result<(), GeneratorError> {
+    if let Some(simple) = words::lookup_simple(func).or(words::lookup_variadic_simple(func)) {
+        // Call simple builtin
+        let arg_types = &[element_ty.clone(), result_ty.clone()];
+        simple.visit(generator, builder, arg_types, result_ty)?;
+    } else {
+        // Call user defined function
+        generator.visit_call_user_defined(builder, result_ty, func)?;
+    }
 
+    if let Some(buffer) = stable_buffer {
+        // STACK: [offset, length]
+        let memory = generator.get_memory()?;
+        let offset = generator.module.locals.add(ValType::I32);
+        let length = generator.module.locals.add(ValType::I32);
+        builder.local_set(length).local_set(offset);
+        generator.charge_cost_bytes(builder, CostOp::MemoryCopy, length);
         builder
+            .local_get(buffer)
+            .local_get(offset)
             .local_get(length)
-            .unop(UnaryOp::I32Eqz)
-            .instr(IfElse {
-                consequent: then_id,
-                alternative: else_id,
-            });
-
-        Ok(())
+            .memory_copy(memory, memory);
+        builder.local_get(buffer).local_get(length);
+        // STACK: [buffer, length]
     }
+    Ok(())
 }
 
 #[derive(Debug)]
@@ -273,17 +306,23 @@ impl ComplexWord for Append {
         let memory = generator.get_memory()?;
 
         // Allocate stack space for the new list.
-        let (write_ptr, length) = generator.create_call_stack_local(builder, &ty, false, true);
+        let (offset, _) = generator.create_call_stack_local(builder, &ty, false, true);
 
-        // Push the offset and length of this list to the stack to be returned.
-        builder.local_get(write_ptr).i32_const(length);
+        // A chain of nested `append`s, like `(append (append lst a) b)`, is
+        // built in the single buffer allocated above: the innermost list is
+        // copied once and each element is written after it, in order.
+        let (list, elems) = if generator.config.flatten_sequence_chains {
+            collect_append_chain(args)?
+        } else {
+            (args.get_expr(0)?, vec![args.get_expr(1)?])
+        };
 
-        // Push the write pointer onto the stack for `memory.copy`.
-        builder.local_get(write_ptr);
+        // The write pointer is where the next element will be written.
+        let write_ptr = generator.module.locals.add(ValType::I32);
+        builder.local_get(offset).local_tee(write_ptr);
 
         // Traverse the list to append to, leaving the offset and length on
         // top of the stack.
-        let list = args.get_expr(0)?;
         // WORKAROUND: setting types of list argument
         generator.set_expr_type(list, ty.clone())?;
         generator.traverse_expr(builder, list)?;
@@ -293,6 +332,7 @@ impl ComplexWord for Append {
         // list. Save a copy of the length for later.
         let src_length = generator.module.locals.add(ValType::I32);
         builder.local_tee(src_length);
+        generator.charge_cost_bytes(builder, CostOp::MemoryCopy, src_length);
         builder.memory_copy(memory, memory);
 
         // Increment the write pointer by the length of the source list.
@@ -302,34 +342,75 @@ impl ComplexWord for Append {
             .binop(BinaryOp::I32Add)
             .local_set(write_ptr);
 
-        // Traverse the element that we're appending to the list.
-        let elem = args.get_expr(1)?;
-        // WORKAROUND: setting type of elem
-        match ty {
+        let item_ty = match &ty {
             TypeSignature::SequenceType(SequenceSubtype::ListType(ltd)) => {
-                generator.set_expr_type(elem, ltd.get_list_item_type().clone())?;
+                ltd.get_list_item_type().clone()
             }
             _ => {
                 return Err(GeneratorError::TypeError(
                     "append result should be a list".to_owned(),
                 ))
             }
-        }
-        generator.traverse_expr(builder, elem)?;
+        };
 
-        // Get the type of the element that we're appending.
-        let elem_ty = generator
-            .get_expr_type(elem)
-            .ok_or_else(|| GeneratorError::TypeError("append element must be typed".to_string()))?
-            .clone();
+        for elem in elems {
+            // Traverse the element that we're appending to the list.
+            // WORKAROUND: setting type of elem
+            generator.set_expr_type(elem, item_ty.clone())?;
+            generator.traverse_expr(builder, elem)?;
+
+            // Get the type of the element that we're appending.
+            let elem_ty = generator
+                .get_expr_type(elem)
+                .ok_or_else(|| {
+                    GeneratorError::TypeError("append element must be typed".to_string())
+                })?
+                .clone();
+
+            // Store the element at the write pointer, and move the write
+            // pointer past it.
+            generator.charge_cost(builder, CostOp::MemoryCopy, get_type_size(&elem_ty).into());
+            let elem_size = generator.write_to_memory(builder, write_ptr, 0, &elem_ty)?;
+            builder
+                .local_get(write_ptr)
+                .i32_const(elem_size as i32)
+                .binop(BinaryOp::I32Add)
+                .local_set(write_ptr);
+        }
 
-        // Store the element at the write pointer.
-        generator.write_to_memory(builder, write_ptr, 0, &elem_ty)?;
+        // Push the offset of the new list and its size, which is the distance
+        // covered by the write pointer.
+        builder
+            .local_get(offset)
+            .local_get(write_ptr)
+            .local_get(offset)
+            .binop(BinaryOp::I32Sub);
 
         Ok(())
     }
 }
 
+/// Split a chain of nested `append` expressions into its innermost list and
+/// the elements appended to it, in order.
+fn collect_append_chain(
+    args: &[SymbolicExpression],
+) -> Result<(&SymbolicExpression, Vec<&SymbolicExpression>), GeneratorError> {
+    let mut list = args.get_expr(0)?;
+    let mut elems = vec![args.get_expr(1)?];
+    while let Some([function, inner_list, inner_elem]) = list.match_list() {
+        if !function
+            .match_atom()
+            .map_or(false, |name| name.as_str() == "append")
+        {
+            break;
+        }
+        elems.push(inner_elem);
+        list = inner_list;
+    }
+    elems.reverse();
+    Ok((list, elems))
+}
+
 #[derive(Debug)]
 pub struct AsMaxLen;
 
@@ -369,7 +450,7 @@ impl ComplexWord for AsMaxLen {
         // Get the length.
         generator
             .get_expr_type(seq)
-            .ok_or_else(|| GeneratorError::TypeError("append result must be typed".to_string()))
+            .ok_or_else(|| GeneratorError::TypeError("sequence must be typed".to_string()))
             .and_then(|ty| match ty {
                 TypeSignature::SequenceType(SequenceSubtype::ListType(list)) => {
                     // The length of the list in bytes is on the top of the stack. If we
@@ -410,12 +491,19 @@ impl ComplexWord for AsMaxLen {
         builder.unop(UnaryOp::I64ExtendUI32);
 
         // Traverse the second argument, the desired length, leaving the low
-        // and high parts on the stack, then drop the high part.
+        // and high parts on the stack, then save the high part for later.
         generator.traverse_expr(builder, args.get_expr(1)?)?;
-        builder.drop();
+        let max_high = generator.module.locals.add(ValType::I64);
+        builder.local_set(max_high);
 
-        // Compare the length of the list to the desired length.
-        builder.binop(BinaryOp::I64GtU);
+        // Compare the length of the list to the low part of the desired
+        // length. The length can only exceed the desired length if its high
+        // part is zero.
+        builder
+            .binop(BinaryOp::I64GtU)
+            .local_get(max_high)
+            .unop(UnaryOp::I64Eqz)
+            .binop(BinaryOp::I32And);
 
         // Select from the `0` and `1` that we pushed to the stack earlier,
         // based on the result of the comparison.
@@ -454,50 +542,105 @@ impl ComplexWord for Concat {
             .clone();
         let (offset, _) = generator.create_call_stack_local(builder, &ty, false, true);
 
-        builder.local_get(offset);
-
-        // Traverse the lhs, leaving it on the data stack (offset, size)
-        let lhs = args.get_expr(0)?;
-        // WORKAROUND: typechecker issue for lists
-        generator.set_expr_type(lhs, ty.clone())?;
-        generator.traverse_expr(builder, lhs)?;
-
-        // Save the length of the lhs
-        let lhs_length = generator.module.locals.add(ValType::I32);
-        builder.local_tee(lhs_length);
+        // A chain of nested `concat`s, like `(concat (concat a b) c)`, is
+        // flattened so that its leaves are copied exactly once into the single
+        // buffer allocated above, instead of allocating a buffer for each
+        // intermediate result.
+        let mut operands = Vec::new();
+        if generator.config.flatten_sequence_chains {
+            collect_concat_operands(args, &mut operands);
+        } else {
+            operands.extend(args);
+        }
 
-        // Copy the lhs to the new sequence
-        builder.memory_copy(memory, memory);
+        // The buffer allocated above must be large enough for all operands.
+        let operand_tys = operands
+            .iter()
+            .map(|operand| {
+                generator.get_expr_type(operand).cloned().ok_or_else(|| {
+                    GeneratorError::TypeError("concat operand must be typed".to_owned())
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        check_concat_size(&ty, &operand_tys)?;
+
+        // The write pointer is where the next operand will be copied
+        let write_ptr = generator.module.locals.add(ValType::I32);
+        builder.local_get(offset).local_set(write_ptr);
+
+        for operand in operands {
+            builder.local_get(write_ptr);
+
+            // Traverse the operand, leaving it on the data stack (offset, size)
+            // WORKAROUND: typechecker issue for lists
+            generator.set_expr_type(operand, ty.clone())?;
+            generator.traverse_expr(builder, operand)?;
+
+            // Save the length of the operand
+            let operand_length = generator.module.locals.add(ValType::I32);
+            builder.local_tee(operand_length);
+
+            // Copy the operand to the new sequence
+            generator.charge_cost_bytes(builder, CostOp::MemoryCopy, operand_length);
+            builder.memory_copy(memory, memory);
+
+            // Move the write pointer past the copied operand
+            builder
+                .local_get(write_ptr)
+                .local_get(operand_length)
+                .binop(BinaryOp::I32Add)
+                .local_set(write_ptr);
+        }
 
-        // Load the adjusted destination offset
+        // Push the offset of the new sequence and its total size, which is
+        // the distance covered by the write pointer.
         builder
             .local_get(offset)
-            .local_get(lhs_length)
-            .binop(BinaryOp::I32Add);
-
-        // Traverse the rhs, leaving it on the data stack (offset, size)
-        let rhs = args.get_expr(1)?;
-        // WORKAROUND: typechecker issue for lists
-        generator.set_expr_type(rhs, ty.clone())?;
-        generator.traverse_expr(builder, rhs)?;
-
-        // Save the length of the rhs
-        let rhs_length = generator.module.locals.add(ValType::I32);
-        builder.local_tee(rhs_length);
-
-        // Copy the rhs to the new sequence
-        builder.memory_copy(memory, memory);
+            .local_get(write_ptr)
+            .local_get(offset)
+            .binop(BinaryOp::I32Sub);
 
-        // Load the offset of the new sequence
-        builder.local_get(offset);
+        Ok(())
+    }
+}
 
-        // Total size = lhs_length + rhs_length
-        builder
-            .local_get(lhs_length)
-            .local_get(rhs_length)
-            .binop(BinaryOp::I32Add);
+/// Check that a sequence of type `result_ty` can hold the maximum length of
+/// all the operands of a `concat`. This should be guaranteed by the analysis,
+/// but a disagreement would otherwise overflow the buffer at runtime.
+fn check_concat_size(
+    result_ty: &TypeSignature,
+    operand_tys: &[TypeSignature],
+) -> Result<(), GeneratorError> {
+    let result_size = get_type_in_memory_size(result_ty, false);
+    let operands_size: i32 = operand_tys
+        .iter()
+        .map(|ty| get_type_in_memory_size(ty, false))
+        .sum();
+    if operands_size > result_size {
+        return Err(GeneratorError::InternalError(format!(
+            "concat operands of {operands_size} bytes exceed the {result_size} bytes of its result"
+        )));
+    }
+    Ok(())
+}
 
-        Ok(())
+/// Collect the leaf operands of a chain of nested `concat` expressions, in
+/// order. Operands which are not themselves a `concat` are leaves.
+fn collect_concat_operands<'a>(
+    args: &'a [SymbolicExpression],
+    operands: &mut Vec<&'a SymbolicExpression>,
+) {
+    for arg in args {
+        match arg.match_list() {
+            Some([function, inner_args @ ..])
+                if function
+                    .match_atom()
+                    .map_or(false, |name| name.as_str() == "concat") =>
+            {
+                collect_concat_operands(inner_args, operands)
+            }
+            _ => operands.push(arg),
+        }
     }
 }
 
@@ -518,6 +661,19 @@ impl ComplexWord for Map {
     ) -> Result<(), GeneratorError> {
         let fname = args.get_name(0)?;
 
+        // The function is called with an element of each sequence.
+        let arg_types = args
+            .iter()
+            .skip(1)
+            .map(|arg| {
+                generator
+                    .get_expr_type(arg)
+                    .and_then(sequence_element_type)
+                    .ok_or_else(|| GeneratorError::TypeError("expected sequence type".to_owned()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        generator.check_callback_signature("map", fname, &arg_types, None)?;
+
         let ty = generator
             .get_expr_type(expr)
             .ok_or_else(|| GeneratorError::TypeError("list expression must be typed".to_owned()))?
@@ -539,76 +695,31 @@ impl ComplexWord for Map {
         builder.i32_const(i32::MAX);
         builder.local_set(min_num_elements);
 
-        let mut input_offsets = vec![];
-        let mut input_element_types = vec![];
-        let mut input_element_sizes = vec![];
-        let mut input_num_elements = vec![];
+        let mut iters = vec![];
 
         for arg in args.iter().skip(1) {
-            // get the type of the seq, and the sizes.
-
-            let (element_ty, element_size) = match generator
-                .get_expr_type(arg)
-                .ok_or_else(|| {
-                    GeneratorError::TypeError("sequence expression must be typed".to_owned())
-                })?
-                .clone()
-            {
-                TypeSignature::SequenceType(SequenceSubtype::ListType(lt)) => {
-                    let element_ty = lt.get_list_item_type().clone();
-                    let element_size = get_type_size(&element_ty);
-                    (SequenceElementType::Other(element_ty), element_size)
-                }
-                TypeSignature::SequenceType(SequenceSubtype::BufferType(_))
-                | TypeSignature::SequenceType(SequenceSubtype::StringType(StringSubtype::ASCII(
-                    _,
-                ))) => (SequenceElementType::Byte, 1),
-                TypeSignature::SequenceType(SequenceSubtype::StringType(StringSubtype::UTF8(
-                    _,
-                ))) => (SequenceElementType::UnicodeScalar, 4),
-                _ => {
-                    return Err(GeneratorError::TypeError(
-                        "expected sequence type".to_string(),
-                    ));
-                }
-            };
-            input_element_types.push(element_ty);
-            input_element_sizes.push(element_size);
-
-            generator.traverse_expr(builder, arg)?;
-            // [ offset, length ]
-            builder.i32_const(element_size);
-            // [ offset, length, element_size ]
-            builder.binop(ir::BinaryOp::I32DivS);
-            // [ offset, num_elements ]
+            let iter = generator.traverse_sequence_iter(builder, arg)?;
 
+            // Keep the minimum number of elements of the input sequences.
             let num_elements = generator.module.locals.add(ValType::I32);
-            builder.local_tee(num_elements);
-            builder.local_get(num_elements);
-            // [ offset, num_elements, num_elements ]
-            input_num_elements.push(num_elements);
-
-            builder.local_get(min_num_elements);
-            // [ offset, num_elements, num_elements, min_num_elements ]
-
-            builder.binop(ir::BinaryOp::I32LeS);
-            // [ offset, num_elements, is_less ]
-
-            builder.if_else(
-                InstrSeqType::new(&mut generator.module.types, &[ValType::I32], &[]),
-                |t| {
-                    t.local_set(min_num_elements);
-                },
-                |e| {
-                    e.drop();
-                },
-            );
-            // [ offset ]
-
-            let offset = generator.module.locals.add(ValType::I32);
-            builder.local_set(offset);
-            // [ ]
-            input_offsets.push(offset);
+            builder
+                .local_get(iter.end)
+                .local_get(iter.offset)
+                .binop(BinaryOp::I32Sub)
+                .i32_const(iter.elem_ty.size())
+                .binop(BinaryOp::I32DivU)
+                .local_tee(num_elements)
+                .local_get(min_num_elements)
+                .binop(BinaryOp::I32LtU)
+                .if_else(
+                    None,
+                    |then| {
+                        then.local_get(num_elements).local_set(min_num_elements);
+                    },
+                    |_| {},
+                );
+
+            iters.push(iter);
         }
 
         // Allocate space on the call stack for the output list.
@@ -631,19 +742,6 @@ impl ComplexWord for Map {
         builder.global_set(generator.stack_pointer);
         // [ ]
 
-        // Create an index to count the number of elements to loop over.
-        let index = generator.module.locals.add(ValType::I32);
-        builder.i32_const(0).local_set(index);
-
-        // Loop over the min_num_elements of the input sequences, calling the
-        // function on each set of elements. The result of the function call
-        // will be written to the output sequence. The loop_exit block allows
-        // us to put the condition at the top of the loop.
-        let mut loop_exit = builder.dangling_instr_seq(None);
-        let loop_exit_id = loop_exit.id();
-        let mut loop_ = loop_exit.dangling_instr_seq(None);
-        let loop_id = loop_.id();
-
         // See if we're calling a simple function, and if it's variadic
 
         let mut simple = words::lookup_simple(fname);
@@ -656,93 +754,52 @@ impl ComplexWord for Map {
             }
         }
 
-        let arg_types: Vec<_> = input_element_types
-            .iter()
-            .map(type_from_sequence_element)
-            .collect();
-
-        // Check if we've reached the min_num_elements
-        loop_
-            .local_get(index)
-            .local_get(min_num_elements)
-            .binop(BinaryOp::I32GeU)
-            .br_if(loop_exit_id);
-
-        // For each input sequence, load the next element, and adjust the
-        // offset for the next iteration.
-        for (i, offset) in input_offsets.iter().enumerate() {
-            match &input_element_types[i] {
-                SequenceElementType::Other(elem_ty) => {
-                    generator.read_from_memory(&mut loop_, *offset, 0, elem_ty)?;
-                }
-                SequenceElementType::Byte => {
-                    // The element type is a byte, so we can just push the
-                    // offset and length (1) to the stack.
-                    loop_.local_get(*offset).i32_const(1);
-                }
-                SequenceElementType::UnicodeScalar => {
-                    // The element type is a 32-bit unicode scalar, so we can just push the
-                    // offset and length (4) to the stack.
-                    loop_.local_get(*offset).i32_const(4);
+        // Loop over the min_num_elements of the input sequences, calling the
+        // function on each set of elements. The result of the function call
+        // will be written to the output sequence.
+        generator.emit_sequence_loop(builder, &iters, |generator, loop_| {
+            generator.charge_cost(loop_, CostOp::LoopIteration, 1);
+
+            // Load the current element of each input sequence.
+            for (i, iter) in iters.iter().enumerate() {
+                generator.load_sequence_element(loop_, iter)?;
+
+                // If we have variadics, we need to interleave the calls
+                // if the arg length is 1, this is a no-op
+                if let Some(simple) = simple {
+                    if variadic && i > 0 {
+                        simple.visit(
+                            generator,
+                            loop_,
+                            &arg_types[i - 1..=i],
+                            return_element_type,
+                        )?;
+                    }
                 }
             }
 
-            // If we have variadics, we need to interleave the calls
-            // if the arg length is 1, this is a no-op
             if let Some(simple) = simple {
-                if variadic && i > 0 {
-                    simple.visit(
-                        generator,
-                        &mut loop_,
-                        &arg_types[i - 1..=i],
-                        return_element_type,
-                    )?;
+                // If not variadic, _or_ if the arg length is one (unary operations)
+                if !variadic || arg_types.len() == 1 {
+                    simple.visit(generator, loop_, &arg_types, return_element_type)?;
                 }
+            } else {
+                // Call user defined function.
+                generator.visit_call_user_defined(loop_, return_element_type, fname)?;
             }
 
-            // Increment the offset by the size of the element.
+            // Write the result to the output sequence.
+            generator.write_to_memory(loop_, output_offset, 0, return_element_type)?;
+
+            // Increment the output offset by the size of the element.
             loop_
-                .local_get(*offset)
-                .i32_const(input_element_sizes[i])
+                .local_get(output_offset)
+                .i32_const(return_element_size)
                 .binop(BinaryOp::I32Add)
-                .local_set(*offset);
-        }
-
-        if let Some(simple) = simple {
-            // If not variadic, _or_ if the arg length is one (unary operations)
-            if !variadic || arg_types.len() == 1 {
-                simple.visit(generator, &mut loop_, &arg_types, return_element_type)?;
-            }
-        } else {
-            // Call user defined function.
-            generator.visit_call_user_defined(&mut loop_, return_element_type, fname)?;
-        }
-
-        // Write the result to the output sequence.
-        generator.write_to_memory(&mut loop_, output_offset, 0, return_element_type)?;
-
-        // Increment the output offset by the size of the element.
-        loop_
-            .local_get(output_offset)
-            .i32_const(return_element_size)
-            .binop(BinaryOp::I32Add)
-            .local_set(output_offset);
-
-        // Increment the index.
-        loop_
-            .local_get(index)
-            .i32_const(1)
-            .binop(BinaryOp::I32Add)
-            .local_tee(index);
-
-        // Loop back to the top.
-        loop_.br(loop_id);
+                .local_set(output_offset);
 
-        // Add the loop to the loop_exit block.
-        loop_exit.instr(Loop { seq: loop_id });
-
-        // Add the loop_exit block to the main block.
-        builder.instr(walrus::ir::Block { seq: loop_exit_id });
+            Ok(())
+        })?;
 
         builder
             .local_get(output_base)
@@ -769,8 +826,16 @@ impl ComplexWord for Len {
         _expr: &SymbolicExpression,
         args: &[clarity::vm::SymbolicExpression],
     ) -> Result<(), GeneratorError> {
-        // Traverse the sequence, leaving the offset and length on the stack.
         let seq = args.get_expr(0)?;
+
+        // The length of a literal is known at compile time, and the literal
+        // does not need to be written to memory.
+        if let Some(length) = literal_sequence_length(seq) {
+            builder.i64_const(length as i64).i64_const(0);
+            return Ok(());
+        }
+
+        // Traverse the sequence, leaving the offset and length on the stack.
         generator.traverse_expr(builder, seq)?;
 
         // Save the length, then drop the offset and push the length back.
@@ -828,6 +893,25 @@ impl ComplexWord for Len {
     }
 }
 
+/// Returns the number of elements of `seq` if it is a literal string or
+/// buffer, or a `list` of literals, whose evaluation has no side effects.
+fn literal_sequence_length(seq: &SymbolicExpression) -> Option<usize> {
+    match &seq.expr {
+        SymbolicExpressionType::LiteralValue(Value::Sequence(
+            data @ (SequenceData::String(_) | SequenceData::Buffer(_)),
+        )) => Some(data.len()),
+        SymbolicExpressionType::List(exprs) => {
+            let (name, elements) = exprs.split_first()?;
+            (name.match_atom()?.as_str() == "list"
+                && elements
+                    .iter()
+                    .all(|element| element.match_literal_value().is_some()))
+            .then_some(elements.len())
+        }
+        _ => None,
+    }
+}
+
 #[derive(Debug)]
 pub enum ElementAt {
     Original,
@@ -885,45 +969,19 @@ impl ComplexWord for ElementAt {
         builder.local_get(index_local);
 
         // Record the element type, for use later.
-        let element_ty: SequenceElementType = generator
+        let element_ty = generator
             .get_expr_type(seq)
             .ok_or_else(|| GeneratorError::TypeError("append result must be typed".to_string()))
-            .and_then(|ty| match ty {
-                TypeSignature::SequenceType(SequenceSubtype::ListType(list)) => {
-                    // The length of the list in bytes is on the top of the stack. If we
-                    // divide that by the length of each element, then we'll have the
-                    // length of the list in elements.
-                    let elem_ty = list.get_list_item_type();
-                    let element_length = get_type_size(elem_ty);
-                    builder.i64_const(element_length as i64);
-
-                    // Multiply the index by the length of each element to get
-                    // byte-offset into the list.
-                    builder.binop(BinaryOp::I64Mul);
-
-                    Ok(SequenceElementType::Other(elem_ty.clone()))
-                }
-                TypeSignature::SequenceType(SequenceSubtype::BufferType(_))
-                | TypeSignature::SequenceType(SequenceSubtype::StringType(StringSubtype::ASCII(
-                    _,
-                ))) => {
-                    // The index is the same as the byte-offset, so just leave
-                    // it as-is.
-                    Ok(SequenceElementType::Byte)
-                }
-                TypeSignature::SequenceType(SequenceSubtype::StringType(StringSubtype::UTF8(
-                    _,
-                ))) => {
-                    // UTF8 is represented as 32-bit unicode scalars values.
-                    builder.i64_const(4);
-                    builder.binop(BinaryOp::I64Mul);
-
-                    Ok(SequenceElementType::UnicodeScalar)
-                }
-                _ => Err(GeneratorError::TypeError(
-                    "expected sequence type".to_string(),
-                )),
-            })?;
+            .and_then(SequenceElementType::from_sequence_type)?;
+
+        // Multiply the index by the size of each element to get the
+        // byte-offset into the sequence. The index of a byte is already its
+        // byte-offset.
+        if element_ty.size() != 1 {
+            builder
+                .i64_const(element_ty.size() as i64)
+                .binop(BinaryOp::I64Mul);
+        }
 
         // Save the element offset to the local.
         builder.local_tee(index_local);
@@ -1049,6 +1107,9 @@ impl ComplexWord for ReplaceAt {
         let memory = generator.get_memory()?;
 
         // Copy the input list to the new stack local
+        let src_length = generator.module.locals.add(ValType::I32);
+        builder.local_tee(src_length);
+        generator.charge_cost_bytes(builder, CostOp::MemoryCopy, src_length);
         builder.memory_copy(memory, memory);
 
         // Extend the sequence length to 64-bits.
@@ -1082,40 +1143,14 @@ impl ComplexWord for ReplaceAt {
         // Push the index onto the stack again.
         builder.local_get(index_local);
 
-        // Get the offset of the specified index.
-        let element_ty = match &seq_ty {
-            TypeSignature::SequenceType(SequenceSubtype::ListType(list)) => {
-                // The length of the list in bytes is on the top of the stack. If we
-                // divide that by the length of each element, then we'll have the
-                // length of the list in elements.
-                let elem_ty = list.get_list_item_type();
-                let element_length = get_type_size(elem_ty);
-                builder.i64_const(element_length as i64);
-
-                // Multiply the index by the length of each element to get
-                // byte-offset into the list.
-                builder.binop(BinaryOp::I64Mul);
-
-                Ok(SequenceElementType::Other(elem_ty.clone()))
-            }
-            TypeSignature::SequenceType(SequenceSubtype::BufferType(_))
-            | TypeSignature::SequenceType(SequenceSubtype::StringType(StringSubtype::ASCII(_))) => {
-                // The index is the same as the byte-offset, so just leave
-                // it as-is.
-
-                Ok(SequenceElementType::Byte)
-            }
-            TypeSignature::SequenceType(SequenceSubtype::StringType(StringSubtype::UTF8(_))) => {
-                // UTF8 is represented as 32-bit unicode scalars values.
-                builder.i64_const(4);
-                builder.binop(BinaryOp::I64Mul);
-
-                Ok(SequenceElementType::UnicodeScalar)
-            }
-            _ => Err(GeneratorError::TypeError(
-                "expected sequence type".to_string(),
-            )),
-        }?;
+        // Get the offset of the specified index, by multiplying it by the
+        // size of each element. The index of a byte is already its offset.
+        let element_ty = SequenceElementType::from_sequence_type(&seq_ty)?;
+        if element_ty.size() != 1 {
+            builder
+                .i64_const(element_ty.size() as i64)
+                .binop(BinaryOp::I64Mul);
+        }
 
         // Save the element offset to the local.
         builder.local_tee(index_local);
@@ -1204,6 +1239,7 @@ impl ComplexWord for ReplaceAt {
                 let src_local = generator.module.locals.add(ValType::I32);
                 else_.local_set(src_local);
 
+                generator.charge_cost(&mut else_, CostOp::MemoryCopy, 1);
                 else_
                     .local_get(offset_local)
                     .local_get(src_local)
@@ -1221,6 +1257,7 @@ impl ComplexWord for ReplaceAt {
                 let src_local = generator.module.locals.add(ValType::I32);
                 else_.local_set(src_local);
 
+                generator.charge_cost(&mut else_, CostOp::MemoryCopy, 4);
                 else_
                     .local_get(offset_local)
                     .local_get(src_local)
@@ -1376,12 +1413,14 @@ impl ComplexWord for Slice {
         // Traverse the right position, leaving it on the stack.
         generator.traverse_expr(builder, args.get_expr(2)?)?;
 
-        // Check if the upper 64-bits are greater than 0.
-        builder.i64_const(0).binop(BinaryOp::I64GtU);
-
-        // Save the overflow indicator to a local.
-        let overflow_local = generator.module.locals.add(ValType::I32);
-        builder.local_set(overflow_local);
+        // Check if the upper 64-bits are greater than 0, and or with the
+        // overflow indicator of the left position.
+        builder
+            .i64_const(0)
+            .binop(BinaryOp::I64GtU)
+            .local_get(overflow_local)
+            .binop(BinaryOp::I32Or)
+            .local_set(overflow_local);
 
         // Save the lower part of the index, which will ultimately be
         // multiplied by the element size and added to the source offset to be
@@ -1464,6 +1503,15 @@ impl ComplexWord for Slice {
             .binop(BinaryOp::I64Add)
             .local_set(right_local);
 
+        // The right bound must not be before the left bound.
+        builder
+            .local_get(right_local)
+            .local_get(left_local)
+            .binop(BinaryOp::I64LtU)
+            .local_get(overflow_local)
+            .binop(BinaryOp::I32Or)
+            .local_set(overflow_local);
+
         // Push a `0` and a `1` to the stack, for none or some, to be selected
         // by the `select` instruction, using the overflow indicator.
         builder.i32_const(0).i32_const(1).local_get(overflow_local);
@@ -1491,9 +1539,39 @@ impl ComplexWord for Slice {
 
 #[cfg(test)]
 mod tests {
-    use clarity::vm::Value;
-
-    use crate::tools::{crosscheck, evaluate};
+    use clarity::vm::types::{SequenceSubtype, TypeSignature};
+    use clarity::vm::{SymbolicExpressionType, Value};
+    use walrus::ir::{
+        dfs_in_order, Call, Const, GlobalSet, Instr, InstrSeqId, Loop, MemoryCopy,
+        Value as WasmValue, Visitor,
+    };
+    use walrus::{ExportItem, FunctionId, FunctionKind, LocalFunction, Module};
+
+    use super::check_concat_size;
+    use crate::tools::{
+        compile_snippet, compile_snippet_with_config, crosscheck, crosscheck_compare_only,
+        evaluate, generation_error, instantiate, run_top_level,
+    };
+    use crate::wasm_generator::{CostOp, GeneratorError, WasmGeneratorConfig};
+
+    /// Count the stack-pointer updates (call stack allocations) made directly
+    /// in the body of the `.top-level` function of `module`.
+    fn count_top_level_allocations(module: &Module) -> usize {
+        let stack_pointer = module
+            .globals
+            .iter()
+            .find(|global| global.name.as_deref() == Some("stack-pointer"))
+            .expect("stack-pointer global not found")
+            .id();
+        let func = top_level_function(module);
+        func.block(func.entry_block())
+            .instrs
+            .iter()
+            .filter(|(instr, _)| {
+                matches!(instr, Instr::GlobalSet(GlobalSet { global }) if *global == stack_pointer)
+            })
+            .count()
+    }
 
     #[test]
     fn test_fold_sub() {
@@ -1603,14 +1681,151 @@ mod tests {
     }
 
     #[test]
-    fn test_fold_buffer() {
-        crosscheck(
-            r"
-(define-private (concat-buff (a (buff 20)) (b (buff 20)))
-    (unwrap-panic (as-max-len? (concat a b) u20))
+    fn fold_concat_single_chars() {
+        let snippet = r#"
+(define-private (append-char (c (string-ascii 1)) (acc (string-ascii 10)))
+    (unwrap-panic (as-max-len? (concat acc c) u10))
 )
-(fold concat-buff 0x03040506 0x0102)
-",
+(define-private (prepend-char (c (string-ascii 1)) (acc (string-ascii 10)))
+    (unwrap-panic (as-max-len? (concat c acc) u10))
+)
+"#;
+        crosscheck(
+            &format!(r#"{snippet} (fold append-char (list "a" "b" "c" "d" "e" "f") "")"#),
+            Ok(Some(
+                Value::string_ascii_from_bytes("abcdef".to_string().into_bytes()).unwrap(),
+            )),
+        );
+        crosscheck(
+            &format!(r#"{snippet} (fold prepend-char (list "a" "b" "c" "d" "e" "f") "")"#),
+            Ok(Some(
+                Value::string_ascii_from_bytes("fedcba".to_string().into_bytes()).unwrap(),
+            )),
+        );
+        crosscheck(
+            &format!(r#"{snippet} (fold prepend-char (list "a" "b" "c") "z")"#),
+            Ok(Some(
+                Value::string_ascii_from_bytes("cbaz".to_string().into_bytes()).unwrap(),
+            )),
+        );
+    }
+
+    #[test]
+    fn fold_buffer_accumulator_with_allocations() {
+        crosscheck(
+            "
+(define-private (push-byte (b (buff 1)) (acc (buff 8)))
+    (let ((doubled (concat b b)))
+        (unwrap-panic (as-max-len? (concat acc doubled) u8))
+    )
+)
+(fold push-byte 0x01020304 0x)
+",
+            Ok(Some(
+                Value::buff_from(vec![1, 1, 2, 2, 3, 3, 4, 4]).unwrap(),
+            )),
+        );
+    }
+
+    const FOLD_LIST_OF_STRINGS: &str = r#"
+(define-private (sum-len (s (string-ascii 5)) (acc uint))
+    (+ acc (len s))
+)
+(define-private (join (s (string-ascii 5)) (acc (string-ascii 30)))
+    (unwrap-panic (as-max-len? (concat acc s) u30))
+)
+"#;
+
+    #[test]
+    fn fold_list_of_strings_sees_runtime_lengths() {
+        crosscheck(
+            &format!(
+                r#"{FOLD_LIST_OF_STRINGS} (fold sum-len (list "a" "" "abcde" "ab" "abc") u0)"#
+            ),
+            Ok(Some(Value::UInt(11))),
+        )
+    }
+
+    #[test]
+    fn fold_list_of_strings_sees_contents() {
+        crosscheck(
+            &format!(r#"{FOLD_LIST_OF_STRINGS} (fold join (list "a" "" "bcdef" "gh" "ijk") "")"#),
+            Ok(Some(
+                Value::string_ascii_from_bytes("abcdefghijk".to_string().into_bytes()).unwrap(),
+            )),
+        )
+    }
+
+    #[test]
+    fn fold_short_list_of_strings() {
+        crosscheck(
+            &format!(r#"{FOLD_LIST_OF_STRINGS} (fold join (list "abcde" "" "f") "")"#),
+            Ok(Some(
+                Value::string_ascii_from_bytes("abcdef".to_string().into_bytes()).unwrap(),
+            )),
+        )
+    }
+
+    #[test]
+    fn fold_over_concat() {
+        crosscheck(
+            "(fold + (concat (list 1 2) (list 3 4)) 0)",
+            Ok(Some(Value::Int(10))),
+        );
+    }
+
+    #[test]
+    fn higher_order_functions_over_expressions() {
+        crosscheck(
+            "
+(define-data-var numbers (list 5 uint) (list u1 u2 u3))
+(define-map lists uint (list 5 uint))
+(define-private (evens (n uint)) (list (* n u2) (* n u4)))
+(define-private (double (n uint)) (* n u2))
+(define-private (is-big (n uint)) (> n u4))
+(map-set lists u1 (list u5 u6))
+{
+  map-var: (map double (var-get numbers)),
+  filter-call: (filter is-big (evens u2)),
+  fold-map-get: (fold + (unwrap-panic (map-get? lists u1)) u0),
+  map-nested: (map double (map double (concat (evens u1) (var-get numbers))))
+}
+",
+            evaluate(
+                "{
+  map-var: (list u2 u4 u6),
+  filter-call: (list u8),
+  fold-map-get: u11,
+  map-nested: (list u8 u16 u4 u8 u12)
+}",
+            ),
+        );
+    }
+
+    #[test]
+    fn fold_list_of_buffers() {
+        crosscheck(
+            r#"
+(define-private (join (b (buff 4)) (acc (buff 16)))
+    (unwrap-panic (as-max-len? (concat b acc) u16))
+)
+(fold join (list 0x01 0x0203 0x 0x04050607 0x08) 0x)
+"#,
+            Ok(Some(
+                Value::buff_from(vec![8, 4, 5, 6, 7, 2, 3, 1]).unwrap(),
+            )),
+        )
+    }
+
+    #[test]
+    fn test_fold_buffer() {
+        crosscheck(
+            r"
+(define-private (concat-buff (a (buff 20)) (b (buff 20)))
+    (unwrap-panic (as-max-len? (concat a b) u20))
+)
+(fold concat-buff 0x03040506 0x0102)
+",
             Ok(Some(
                 Value::buff_from(vec![0x06, 0x05, 0x04, 0x03, 0x01, 0x02]).unwrap(),
             )),
@@ -1678,6 +1893,32 @@ mod tests {
         )
     }
 
+    #[test]
+    fn map_increments_buffer_bytes() {
+        crosscheck(
+            "
+(define-private (inc (byte (buff 1))) (+ (buff-to-uint-be byte) u1))
+(map inc 0x00017ffe)",
+            evaluate("(list u1 u2 u128 u255)"),
+        );
+        crosscheck(
+            "
+(define-private (size (byte (buff 1))) (len byte))
+(map size 0x0a0b0c)",
+            evaluate("(list u1 u1 u1)"),
+        );
+    }
+
+    #[test]
+    fn fold_sums_buffer_bytes() {
+        crosscheck(
+            "
+(define-private (add-byte (byte (buff 1)) (sum uint)) (+ sum (buff-to-uint-be byte)))
+(fold add-byte 0x0102fdff u0)",
+            Ok(Some(Value::UInt(1 + 2 + 253 + 255))),
+        );
+    }
+
     #[test]
     fn test_map_simple_string_ascii() {
         crosscheck(
@@ -1875,6 +2116,192 @@ mod tests {
         );
     }
 
+    #[test]
+    fn as_max_len_string_utf8_boundary() {
+        // 3 scalars, stored in 12 bytes.
+        crosscheck(
+            r#"(as-max-len? u"a\u{e9}\u{1F600}" u3)"#,
+            Ok(Some(
+                Value::some(
+                    Value::string_utf8_from_string_utf8_literal("a\\u{e9}\\u{1F600}".to_owned())
+                        .unwrap(),
+                )
+                .unwrap(),
+            )),
+        );
+        crosscheck(
+            r#"(as-max-len? u"a\u{e9}\u{1F600}" u2)"#,
+            Ok(Some(Value::none())),
+        );
+    }
+
+    #[test]
+    fn as_max_len_buff_boundary() {
+        crosscheck(
+            "(as-max-len? 0x010203 u3)",
+            Ok(Some(
+                Value::some(Value::buff_from(vec![1, 2, 3]).unwrap()).unwrap(),
+            )),
+        );
+        crosscheck("(as-max-len? 0x010203 u2)", Ok(Some(Value::none())));
+    }
+
+    #[test]
+    fn as_max_len_above_u64_max() {
+        crosscheck(
+            "(as-max-len? 0x0102 u18446744073709551616)",
+            Ok(Some(
+                Value::some(Value::buff_from(vec![1, 2]).unwrap()).unwrap(),
+            )),
+        );
+    }
+
+    #[test]
+    fn fold_runtime_length() {
+        // The list is shorter at runtime than its maximum length.
+        crosscheck(
+            "(fold + (unwrap-panic (slice? (list 1 2 3 4 5 6) u1 u4)) 100)",
+            Ok(Some(Value::Int(109))),
+        );
+        crosscheck(
+            "(fold + (unwrap-panic (slice? (list 1 2 3 4 5 6) u2 u2)) 100)",
+            Ok(Some(Value::Int(100))),
+        );
+    }
+
+    #[test]
+    fn fold_empty_runtime_buffer() {
+        crosscheck(
+            "
+(define-private (add-byte (byte (buff 1)) (acc uint))
+    (+ acc (buff-to-uint-be byte)))
+(define-private (sum-bytes (b (buff 8)))
+    (fold add-byte b u42))
+(sum-bytes 0x)
+",
+            evaluate("u42"),
+        );
+    }
+
+    #[test]
+    fn fold_empty_runtime_string() {
+        crosscheck(
+            r#"
+(define-private (prepend (char (string-ascii 1)) (acc (string-ascii 10)))
+    (unwrap-panic (as-max-len? (concat char acc) u10)))
+(fold prepend (unwrap-panic (slice? "hello" u3 u3)) "init")
+"#,
+            evaluate(r#""init""#),
+        );
+    }
+
+    #[test]
+    fn fold_filter_emptied_list() {
+        crosscheck(
+            "
+(define-private (is-negative (x int))
+    (< x 0))
+(fold + (filter is-negative (list 1 2 3)) 7)
+",
+            evaluate("7"),
+        );
+    }
+
+    #[test]
+    fn slice_utf8_multi_byte() {
+        crosscheck(
+            r#"(slice? u"h\u{e9}llo w\u{f6}rld \u{1f600}!" u1 u9)"#,
+            evaluate(r#"(some u"\u{e9}llo w\u{f6}r")"#),
+        );
+        crosscheck(
+            r#"(slice? u"\u{1f600}\u{1f601}\u{1f602}" u2 u3)"#,
+            evaluate(r#"(some u"\u{1f602}")"#),
+        );
+        crosscheck(
+            r#"(slice? u"\u{1f600}\u{1f601}\u{1f602}" u2 u4)"#,
+            evaluate("none"),
+        );
+    }
+
+    #[test]
+    fn slice_out_of_bounds() {
+        // The left bound is out of range, but the right one is not.
+        crosscheck_compare_only(r#"(slice? "abc" u5 u2)"#);
+        crosscheck_compare_only("(slice? (list 1 2 3) u4 u3)");
+        // The right bound is before the left one.
+        crosscheck_compare_only(r#"(slice? u"\u{e9}t\u{e9}" u2 u1)"#);
+        crosscheck_compare_only("(slice? 0x010203 u2 u1)");
+    }
+
+    #[test]
+    fn zero_max_length_list() {
+        let preamble = "
+(define-private (id (l (list 0 uint))) l)
+(define-private (add (a uint) (b uint)) (+ a b))
+(define-private (is-zero (a uint)) (is-eq a u0))
+";
+        crosscheck(
+            &format!("{preamble} (len (id (list)))"),
+            Ok(Some(Value::UInt(0))),
+        );
+        crosscheck(
+            &format!("{preamble} (fold add (id (list)) u7)"),
+            Ok(Some(Value::UInt(7))),
+        );
+        crosscheck(
+            &format!("{preamble} (element-at? (id (list)) u0)"),
+            Ok(Some(Value::none())),
+        );
+        crosscheck(
+            &format!("{preamble} (index-of? (id (list)) u0)"),
+            Ok(Some(Value::none())),
+        );
+        crosscheck(
+            &format!("{preamble} (is-eq (id (list)) (list))"),
+            Ok(Some(Value::Bool(true))),
+        );
+        crosscheck_compare_only(&format!("{preamble} (id (list))"));
+        crosscheck_compare_only(&format!("{preamble} (map add (id (list)) (id (list)))"));
+        crosscheck_compare_only(&format!("{preamble} (filter is-zero (id (list)))"));
+        crosscheck_compare_only(&format!("{preamble} (concat (id (list)) (id (list)))"));
+        crosscheck_compare_only(&format!("{preamble} (append (id (list)) u1)"));
+    }
+
+    #[test]
+    fn map_stops_at_shortest_sequence() {
+        crosscheck(
+            "(map + (list 1 2 3) (unwrap-panic (slice? (list 10 20 30) u0 u2)) (list 100 200 300))",
+            evaluate("(list 111 222)"),
+        );
+        crosscheck(
+            "(map - (list 1 2 3) (unwrap-panic (slice? (list 10 20 30) u1 u1)))",
+            evaluate("(list)"),
+        );
+    }
+
+    #[test]
+    fn higher_order_functions_share_loop_bounds() {
+        let preamble = r#"
+(define-private (is-vowel (char (string-utf8 1)))
+  (is-some (index-of? u"aeiou" char)))
+(define-private (count-vowel (char (string-utf8 1)) (count uint))
+  (if (is-vowel char) (+ count u1) count))
+(define-constant word (unwrap-panic (slice? u"education" u1 u6)))
+"#;
+        crosscheck(
+            &format!("{preamble} (fold count-vowel word u0)"),
+            evaluate("u2"),
+        );
+        crosscheck(
+            &format!("{preamble} (map is-vowel word)"),
+            evaluate("(list false true false true false)"),
+        );
+        crosscheck(
+            &format!("{preamble} (filter is-vowel word)"),
+            evaluate(r#"u"ua""#),
+        );
+    }
+
     #[test]
     fn as_max_len_list_0() {
         crosscheck(
@@ -1938,4 +2365,804 @@ mod tests {
     fn map_unary() {
         crosscheck("(map - (list 10 20 30))", evaluate("(list -10 -20 -30)"));
     }
+
+    #[test]
+    fn concat_literal_with_runtime_argument() {
+        const PREFIX: &str = r#"
+(define-read-only (prefix (s (string-ascii 10))) (concat "x" s))
+(define-read-only (suffix (b (buff 10))) (concat b 0xff))
+"#;
+        crosscheck(
+            &format!(
+                r#"{PREFIX}
+(list (len (prefix "")) (len (prefix "ab")) (len (prefix "abcdefghij"))
+      (len (suffix 0x)) (len (suffix 0x010203)))"#
+            ),
+            evaluate("(list u1 u3 u11 u1 u4)"),
+        );
+        crosscheck(
+            &format!(r#"{PREFIX} {{ a: (prefix "ab"), b: (suffix 0x0102) }}"#),
+            evaluate(r#"{ a: "xab", b: 0x0102ff }"#),
+        );
+    }
+
+    #[test]
+    fn concat_lists_with_unified_element_types() {
+        for (snippet, expected) in [
+            (
+                "(concat (list (ok u1)) (list (err u2)))",
+                "(list (ok u1) (err u2))",
+            ),
+            (
+                "(concat (list none) (list (some 1) none))",
+                "(list none (some 1) none)",
+            ),
+            (
+                "(concat (list (some (list 1))) (list (some (list 2 3)) none))",
+                "(list (some (list 1)) (some (list 2 3)) none)",
+            ),
+            (
+                r#"(concat (concat (list (ok u1)) (list (err "e"))) (list (ok u3)))"#,
+                r#"(list (ok u1) (err "e") (ok u3))"#,
+            ),
+            (
+                r#"(concat (list { a: (some 0x01), b: (err u1) }) (list { a: none, b: (ok "x") }))"#,
+                r#"(list { a: (some 0x01), b: (err u1) } { a: none, b: (ok "x") })"#,
+            ),
+        ] {
+            crosscheck(snippet, evaluate(expected));
+        }
+    }
+
+    #[test]
+    fn concat_runtime_list_with_narrower_literal() {
+        crosscheck(
+            "
+(define-data-var results (list 2 (response uint int)) (list (ok u5)))
+(concat (concat (var-get results) (list (err -6))) (list (ok u7)))",
+            evaluate("(list (ok u5) (err -6) (ok u7))"),
+        );
+    }
+
+    #[test]
+    fn concat_chain_allocates_single_buffer() {
+        let snippet = r#"(concat (concat (concat "ab" "c") "def") "g")"#;
+        let module = compile_snippet(snippet).module;
+        assert_eq!(count_top_level_allocations(&module), 1);
+
+        crosscheck(
+            snippet,
+            Ok(Some(
+                Value::string_ascii_from_bytes("abcdefg".to_string().into_bytes()).unwrap(),
+            )),
+        );
+    }
+
+    #[test]
+    fn concat_chain_flattening_disabled() {
+        let snippet = r#"(concat (concat (concat "ab" "c") "def") "g")"#;
+        let config = WasmGeneratorConfig {
+            flatten_sequence_chains: false,
+            ..Default::default()
+        };
+        let module = compile_snippet_with_config(snippet, config).module;
+        assert_eq!(count_top_level_allocations(&module), 3);
+    }
+
+    #[test]
+    fn concat_chain_of_lists() {
+        crosscheck(
+            "(concat (list 1 2) (concat (list 3) (concat (list 4) (list 5))))",
+            evaluate("(list 1 2 3 4 5)"),
+        );
+    }
+
+    #[test]
+    fn fold_callback_with_early_return() {
+        let snippet = |list: &str| {
+            format!(
+                "
+(define-private (check (x int) (acc (response int int)))
+  (let ((sum (try! acc)))
+    (asserts! (> x 0) (err x))
+    (ok (+ sum x))))
+(fold check {list} (ok 0))
+"
+            )
+        };
+
+        crosscheck(
+            &snippet("(list 1 2 3 4)"),
+            Ok(Some(Value::okay(Value::Int(10)).unwrap())),
+        );
+        crosscheck(
+            &snippet("(list 1 2 -3 4 -5)"),
+            Ok(Some(Value::error(Value::Int(-3)).unwrap())),
+        );
+    }
+
+    #[test]
+    fn map_callback_with_early_return() {
+        crosscheck(
+            "
+(define-private (positive (x int))
+  (begin
+    (asserts! (> x 0) (err x))
+    (ok x)))
+(map positive (list 1 -2 3))
+",
+            Ok(Some(
+                Value::cons_list_unsanitized(vec![
+                    Value::okay(Value::Int(1)).unwrap(),
+                    Value::error(Value::Int(-2)).unwrap(),
+                    Value::okay(Value::Int(3)).unwrap(),
+                ])
+                .unwrap(),
+            )),
+        );
+    }
+
+    #[test]
+    fn element_at_list_of_tuples_get_field() {
+        crosscheck(
+            r#"
+(define-constant items (list {b: u1, a: "first"} {b: u2, a: "second"}))
+(get a (unwrap-panic (element-at? items u1)))
+"#,
+            evaluate(r#""second""#),
+        );
+    }
+
+    #[test]
+    fn element_at_list_of_tuples_get_optional_field() {
+        crosscheck(
+            r#"
+(define-constant items (list {b: u1, a: "first"} {b: u2, a: "second"}))
+(list (get b (element-at? items u1)) (get b (element-at? items u2)))
+"#,
+            evaluate("(list (some u2) none)"),
+        );
+    }
+
+    #[test]
+    fn concat_len_is_sum_of_runtime_lengths() {
+        crosscheck(
+            r#"
+(define-private (joined-len (a (string-ascii 10)) (b (string-ascii 10)))
+  (len (concat a b)))
+(joined-len "abc" "de")
+"#,
+            Ok(Some(Value::UInt(5))),
+        );
+    }
+
+    #[test]
+    fn concat_len_of_lists_under_max_length() {
+        crosscheck(
+            "
+(define-private (joined-len (a (list 10 int)) (b (list 10 int)))
+  (len (concat (concat a b) a)))
+(joined-len (list 1 2) (list 3))
+",
+            Ok(Some(Value::UInt(5))),
+        );
+    }
+
+    /// Compile the snippet, replace the callback of its last expression,
+    /// which must be a higher-order function call, by `callback`, and
+    /// return the error of generating the module.
+    fn callback_error(snippet: &str, callback: &str) -> GeneratorError {
+        generation_error(snippet, |analysis| {
+            match analysis.expressions.last_mut().map(|expr| &mut expr.expr) {
+                Some(SymbolicExpressionType::List(call)) => {
+                    call[1].expr = SymbolicExpressionType::Atom(callback.into())
+                }
+                _ => panic!("expected a call"),
+            }
+        })
+    }
+
+    #[test]
+    fn fold_callback_arity_mismatch() {
+        let err = callback_error(
+            "
+(define-private (add (a int) (b int)) (+ a b))
+(define-private (negate (a int)) (- 0 a))
+(fold add (list 1 2 3) 0)
+",
+            "negate",
+        );
+        assert!(
+            matches!(err, GeneratorError::TypeError(msg) if msg.contains("fold callback 'negate'"))
+        );
+    }
+
+    #[test]
+    fn map_callback_arity_mismatch() {
+        let err = callback_error(
+            "
+(define-private (add (a int) (b int)) (+ a b))
+(define-private (negate (a int)) (- 0 a))
+(map negate (list 1 2 3))
+",
+            "add",
+        );
+        assert!(
+            matches!(err, GeneratorError::TypeError(msg) if msg.contains("map callback 'add'"))
+        );
+    }
+
+    #[test]
+    fn fold_callback_type_mismatch() {
+        let snippet = "
+(define-private (add (a int) (b int)) (+ a b))
+(define-private (greet (a (string-ascii 5)) (b int)) (+ b 1))
+(define-private (count (a int) (b uint)) (+ b u1))
+(fold add (list 1 2 3) 0)
+";
+        for (callback, argument) in [("greet", "argument 1"), ("count", "argument 2")] {
+            let err = callback_error(snippet, callback);
+            assert!(
+                matches!(&err, GeneratorError::TypeError(msg) if msg.contains(argument)),
+                "{callback}: {err:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn map_callback_type_mismatch() {
+        let err = callback_error(
+            "
+(define-private (negate (a int)) (- 0 a))
+(define-private (flip (a bool)) (not a))
+(map negate (list 1 2 3))
+",
+            "flip",
+        );
+        assert!(
+            matches!(err, GeneratorError::TypeError(msg) if msg.contains("map callback 'flip'"))
+        );
+    }
+
+    #[test]
+    fn map_callback_admits_narrower_elements() {
+        crosscheck(
+            r#"
+(define-private (size (s (string-ascii 10))) (len s))
+(map size (list "a" "bcd"))
+"#,
+            evaluate("(list u1 u3)"),
+        );
+    }
+
+    /// Visit the instructions of the `.top-level` function generated for
+    /// `snippet` with `config`.
+    fn visit_top_level(
+        snippet: &str,
+        config: WasmGeneratorConfig,
+        visitor: &mut impl for<'instr> Visitor<'instr>,
+    ) {
+        let module = compile_snippet_with_config(snippet, config).module;
+        visit_top_level_of(&module, visitor);
+    }
+
+    fn visit_top_level_of(module: &Module, visitor: &mut impl for<'instr> Visitor<'instr>) {
+        let func = top_level_function(module);
+        dfs_in_order(visitor, func, func.entry_block());
+    }
+
+    fn top_level_function(module: &Module) -> &LocalFunction {
+        let top_level = module
+            .exports
+            .iter()
+            .find_map(|export| match export.item {
+                ExportItem::Function(func) if export.name == ".top-level" => Some(func),
+                _ => None,
+            })
+            .expect(".top-level export not found");
+        let FunctionKind::Local(func) = &module.funcs.get(top_level).kind else {
+            panic!(".top-level should be a local function");
+        };
+        func
+    }
+
+    /// Count the `loop` instructions in the `.top-level` function generated
+    /// for `snippet` with `config`.
+    fn count_top_level_loops(snippet: &str, config: WasmGeneratorConfig) -> usize {
+        struct LoopCounter(usize);
+        impl<'instr> Visitor<'instr> for LoopCounter {
+            fn visit_loop(&mut self, _: &Loop) {
+                self.0 += 1;
+            }
+        }
+
+        let mut counter = LoopCounter(0);
+        visit_top_level(snippet, config, &mut counter);
+        counter.0
+    }
+
+    /// The instructions directly in each loop of the `.top-level` function
+    /// generated for `snippet`.
+    fn top_level_loop_bodies(snippet: &str) -> Vec<Vec<Instr>> {
+        struct LoopCollector(Vec<InstrSeqId>);
+        impl<'instr> Visitor<'instr> for LoopCollector {
+            fn visit_loop(&mut self, instr: &Loop) {
+                self.0.push(instr.seq);
+            }
+        }
+
+        let module = compile_snippet(snippet).module;
+        let func = top_level_function(&module);
+        let mut collector = LoopCollector(vec![]);
+        dfs_in_order(&mut collector, func, func.entry_block());
+        collector
+            .0
+            .iter()
+            .map(|seq| {
+                func.block(*seq)
+                    .instrs
+                    .iter()
+                    .map(|(instr, _)| instr.clone())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sequence_loop_branches_once_per_iteration() {
+        const SNIPPET: &str = "
+(define-data-var numbers (list 10 int) (list 1 2 3 4 5))
+(fold + (var-get numbers) 0)";
+
+        let bodies = top_level_loop_bodies(SNIPPET);
+        assert_eq!(bodies.len(), 1);
+        let count = |pred: fn(&Instr) -> bool| bodies[0].iter().filter(|instr| pred(instr)).count();
+        assert_eq!(count(|instr| matches!(instr, Instr::Br(_))), 0);
+        assert_eq!(count(|instr| matches!(instr, Instr::BrIf(_))), 1);
+        // The element size is only used to move to the next element.
+        assert_eq!(
+            count(|instr| matches!(
+                instr,
+                Instr::Const(Const {
+                    value: WasmValue::I32(16)
+                })
+            )),
+            1
+        );
+
+        crosscheck(SNIPPET, Ok(Some(Value::Int(15))));
+    }
+
+    #[test]
+    fn sequence_loop_stops_at_shortest_sequence() {
+        crosscheck(
+            "
+(define-data-var short (list 10 int) (list 1 2))
+(define-data-var long (list 10 int) (list 10 20 30 40))
+(define-data-var empty (list 10 int) (list))
+{
+  two: (map + (var-get short) (var-get long)),
+  three: (map + (var-get long) (var-get short) (var-get long)),
+  empty: (map + (var-get long) (var-get empty)),
+  sum: (fold + (var-get long) 0)
+}",
+            evaluate("{ two: (list 11 22), three: (list 21 42), empty: (list), sum: 100 }"),
+        );
+    }
+
+    const FOLD_SMALL_LIST: &str = "
+(define-private (sub (a int) (b int)) (- a b))
+(fold sub (list 1 2 3) 0)
+";
+
+    #[test]
+    fn fold_small_list_is_unrolled() {
+        assert_eq!(
+            count_top_level_loops(FOLD_SMALL_LIST, WasmGeneratorConfig::default()),
+            0
+        );
+        crosscheck(FOLD_SMALL_LIST, Ok(Some(Value::Int(2))));
+    }
+
+    #[test]
+    fn fold_unrolling_disabled() {
+        let config = WasmGeneratorConfig {
+            fold_unroll_threshold: 0,
+            ..Default::default()
+        };
+        assert_ne!(count_top_level_loops(FOLD_SMALL_LIST, config), 0);
+    }
+
+    #[test]
+    fn fold_large_list_is_not_unrolled() {
+        let snippet = "
+(define-private (sub (a int) (b int)) (- a b))
+(fold sub (list 1 2 3 4 5) 0)
+";
+        assert_ne!(
+            count_top_level_loops(snippet, WasmGeneratorConfig::default()),
+            0
+        );
+        crosscheck(snippet, Ok(Some(Value::Int(3))));
+    }
+
+    #[test]
+    fn fold_unrolled_builtin() {
+        crosscheck("(fold + (list 1 2 3 4) 10)", Ok(Some(Value::Int(20))));
+    }
+
+    /// Count the `memory.copy` instructions in the `.top-level` function
+    /// generated for `snippet` with `config`.
+    fn count_top_level_memory_copies(snippet: &str, config: WasmGeneratorConfig) -> usize {
+        struct MemoryCopyCounter(usize);
+        impl<'instr> Visitor<'instr> for MemoryCopyCounter {
+            fn visit_memory_copy(&mut self, _: &MemoryCopy) {
+                self.0 += 1;
+            }
+        }
+
+        let mut counter = MemoryCopyCounter(0);
+        visit_top_level(snippet, config, &mut counter);
+        counter.0
+    }
+
+    const APPEND_CHAIN: &str = "(append (append (append (list 1 2) 3) 4) 5)";
+
+    #[test]
+    fn append_chain_copies_base_list_once() {
+        assert_eq!(
+            count_top_level_memory_copies(APPEND_CHAIN, WasmGeneratorConfig::default()),
+            1
+        );
+        crosscheck(APPEND_CHAIN, evaluate("(list 1 2 3 4 5)"));
+    }
+
+    #[test]
+    fn append_chain_flattening_disabled() {
+        let config = WasmGeneratorConfig {
+            flatten_sequence_chains: false,
+            ..Default::default()
+        };
+        assert_eq!(count_top_level_memory_copies(APPEND_CHAIN, config), 3);
+    }
+
+    #[test]
+    fn append_chain_to_shorter_list() {
+        crosscheck(
+            "
+(define-data-var lst (list 5 int) (list 1))
+(append (append (var-get lst) 2) 3)
+",
+            evaluate("(list 1 2 3)"),
+        );
+    }
+
+    #[test]
+    fn append_chain_with_inner_call() {
+        crosscheck(
+            "(append (concat (append (list 0x01) 0x02) (list 0x03)) 0x04)",
+            evaluate("(list 0x01 0x02 0x03 0x04)"),
+        );
+    }
+
+    /// Collect the `(op_id, n)` arguments of the calls to the `charge_cost`
+    /// host import in the `.top-level` function generated for `snippet`.
+    fn top_level_cost_charges(snippet: &str, config: WasmGeneratorConfig) -> Vec<(i32, i64)> {
+        struct CostCollector {
+            charge_cost: Option<FunctionId>,
+            consts: Vec<WasmValue>,
+            charges: Vec<(i32, i64)>,
+        }
+        impl<'instr> Visitor<'instr> for CostCollector {
+            fn visit_const(&mut self, instr: &Const) {
+                self.consts.push(instr.value);
+            }
+
+            fn visit_call(&mut self, instr: &Call) {
+                if Some(instr.func) == self.charge_cost {
+                    match self.consts[..] {
+                        [.., WasmValue::I32(op), WasmValue::I64(n)] => self.charges.push((op, n)),
+                        _ => panic!("charge_cost should be called with constants"),
+                    }
+                }
+            }
+        }
+
+        let module = compile_snippet_with_config(snippet, config).module;
+        let mut collector = CostCollector {
+            charge_cost: module.funcs.by_name("stdlib.charge_cost"),
+            consts: vec![],
+            charges: vec![],
+        };
+        visit_top_level_of(&module, &mut collector);
+        collector.charges
+    }
+
+    fn with_cost_hooks() -> WasmGeneratorConfig {
+        WasmGeneratorConfig {
+            emit_cost_hooks: true,
+            ..Default::default()
+        }
+    }
+
+    /// Run the `.top-level` function generated for `snippet` with cost hooks
+    /// enabled, and return the `(op_id, n)` charges made to the host. The
+    /// other host functions trap, except `define_function`.
+    fn runtime_cost_charges(snippet: &str) -> Vec<(i32, i64)> {
+        let mut module = compile_snippet_with_config(snippet, with_cost_hooks()).module;
+        run_top_level(&mut module, Vec::new(), |linker| {
+            linker
+                .func_wrap(
+                    "clarity",
+                    "charge_cost",
+                    |mut caller: wasmtime::Caller<'_, Vec<(i32, i64)>>, op: i32, n: i64| {
+                        caller.data_mut().push((op, n))
+                    },
+                )
+                .unwrap()
+                .func_wrap("clarity", "define_function", |_: i32, _: i32, _: i32| {})
+                .unwrap();
+        })
+        .1
+    }
+
+    /// The amounts of the charges of `op` in `charges`, in order.
+    fn amounts_of(charges: &[(i32, i64)], op: CostOp) -> Vec<i64> {
+        charges
+            .iter()
+            .filter(|(op_id, _)| *op_id == op as i32)
+            .map(|(_, n)| *n)
+            .collect()
+    }
+
+    #[test]
+    fn concat_charges_the_copied_bytes() {
+        // The operands are shorter than the maximum size of their types.
+        let charges = runtime_cost_charges(
+            "
+(define-private (join (a (buff 10)) (b (buff 10))) (concat a b))
+(join 0x01 0x0203)
+",
+        );
+        // The result is then moved to the start of the frame of `join`, and
+        // copied to the frame of the caller.
+        assert_eq!(amounts_of(&charges, CostOp::MemoryCopy), vec![1, 2, 3, 3]);
+        // Defining `join` calls the host.
+        assert_eq!(amounts_of(&charges, CostOp::HostCall), vec![1]);
+    }
+
+    #[test]
+    fn append_charges_the_copied_bytes() {
+        // The list of two ints is copied, then the appended int is written.
+        let charges = runtime_cost_charges("(append (list 1 2) 3)");
+        assert_eq!(amounts_of(&charges, CostOp::MemoryCopy), vec![32, 16]);
+    }
+
+    #[test]
+    fn fold_charges_the_copies_of_a_sequence_accumulator() {
+        // Each result of `keep` is copied from its frame, then into the
+        // buffer of the accumulator.
+        let charges = runtime_cost_charges(
+            "
+(define-private (keep (x (buff 2)) (acc (buff 2))) x)
+(fold keep (list 0x01 0x0203) 0x)
+",
+        );
+        assert_eq!(amounts_of(&charges, CostOp::MemoryCopy), vec![1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn division_and_typed_arithmetic_charge_their_operands() {
+        // A variadic division is charged for each pair of operands.
+        let charges = runtime_cost_charges("(/ 12 2 3)");
+        assert_eq!(amounts_of(&charges, CostOp::Arithmetic), vec![2, 2]);
+
+        let charges = runtime_cost_charges("(sqrti u16)");
+        assert_eq!(amounts_of(&charges, CostOp::Arithmetic), vec![1]);
+    }
+
+    #[test]
+    fn hash_charges_the_input_bytes() {
+        let charges = runtime_cost_charges(
+            "
+(define-private (digest (a (buff 10))) (sha256 a))
+(digest 0x010203)
+",
+        );
+        assert_eq!(amounts_of(&charges, CostOp::Hash), vec![3]);
+    }
+
+    #[test]
+    fn map_and_filter_charge_each_iteration() {
+        let charges = runtime_cost_charges("(map + (list 1 2 3) (list 4 5 6))");
+        assert_eq!(amounts_of(&charges, CostOp::LoopIteration), vec![1; 3]);
+
+        let charges = runtime_cost_charges(
+            "
+(define-private (positive (x int)) (> x 0))
+(filter positive (list 1 -2 3 -4))
+",
+        );
+        assert_eq!(amounts_of(&charges, CostOp::LoopIteration), vec![1; 4]);
+    }
+
+    #[test]
+    fn cost_hooks_disabled_by_default() {
+        let module = compile_snippet("(concat 0x0102 0x030405)").module;
+        assert!(module.funcs.by_name("stdlib.charge_cost").is_none());
+        assert!(module
+            .imports
+            .iter()
+            .all(|import| import.name != "charge_cost"));
+    }
+
+    #[test]
+    fn fold_charges_each_iteration() {
+        let charges = top_level_cost_charges(FOLD_SMALL_LIST, with_cost_hooks());
+        let iterations = charges
+            .iter()
+            .filter(|(op, _)| *op == CostOp::LoopIteration as i32)
+            .count();
+        assert_eq!(iterations, 3);
+    }
+
+    fn buff(len: u32) -> TypeSignature {
+        TypeSignature::SequenceType(SequenceSubtype::BufferType(len.try_into().unwrap()))
+    }
+
+    #[test]
+    fn check_concat_size_accepts_large_enough_result() {
+        assert!(check_concat_size(&buff(7), &[buff(3), buff(4)]).is_ok());
+        assert!(check_concat_size(&buff(10), &[buff(3), buff(4)]).is_ok());
+    }
+
+    #[test]
+    fn check_concat_size_rejects_too_small_result() {
+        let err = check_concat_size(&buff(5), &[buff(3), buff(4)])
+            .expect_err("result is too small for the operands");
+        assert!(matches!(err, GeneratorError::InternalError(_)));
+    }
+
+    #[test]
+    fn check_concat_size_of_lists() {
+        let list_of = |len| TypeSignature::list_of(TypeSignature::IntType, len).unwrap();
+        assert!(check_concat_size(&list_of(5), &[list_of(2), list_of(3)]).is_ok());
+        assert!(check_concat_size(&list_of(4), &[list_of(2), list_of(3)]).is_err());
+    }
+
+    /// The instructions of the body of the `.top-level` function generated
+    /// for `snippet`.
+    fn top_level_instrs(snippet: &str) -> Vec<Instr> {
+        let module = compile_snippet(snippet).module;
+        let func = top_level_function(&module);
+        func.block(func.entry_block())
+            .instrs
+            .iter()
+            .map(|(instr, _)| instr.clone())
+            .collect()
+    }
+
+    #[test]
+    fn len_of_literal_is_a_constant() {
+        for (snippet, length) in [
+            (r#"(len "hello")"#, 5),
+            ("(len 0x010203)", 3),
+            (r#"(len u"ab\u{1F98A}")"#, 3),
+            ("(len (list 1 2 3 4))", 4),
+        ] {
+            assert!(
+                matches!(
+                    top_level_instrs(snippet).as_slice(),
+                    [
+                        Instr::Const(Const { value: WasmValue::I64(lo) }),
+                        Instr::Const(Const { value: WasmValue::I64(0) }),
+                    ] if *lo == length
+                ),
+                "{snippet} should be a constant"
+            );
+            crosscheck(snippet, Ok(Some(Value::UInt(length as u128))));
+        }
+    }
+
+    #[test]
+    fn len_of_runtime_sequence() {
+        let snippet = r#"
+(define-data-var s (string-ascii 10) "abc")
+(define-private (f (x int)) (+ x 1))
+{ var: (len (var-get s)), list: (len (list (f 1) 2)) }"#;
+        assert!(top_level_instrs(snippet)
+            .iter()
+            .any(|instr| matches!(instr, Instr::Call(_))));
+        crosscheck(snippet, evaluate("{ var: u3, list: u2 }"));
+    }
+
+    #[test]
+    fn callback_scratch_is_reclaimed_per_iteration() {
+        // Each call of `step` concatenates two 1000-byte buffers in its frame.
+        // The list has 1000 elements, so leaking the callback frames would
+        // need 2MB of call stack.
+        let buffer = "0x".to_owned() + &"ab".repeat(1000);
+        let numbers = format!("(list {})", ["u1"; 1000].join(" "));
+        let snippet = format!(
+            "
+(define-private (step (x uint)) (+ x (len (concat {buffer} {buffer}))))
+(define-private (add-step (x uint) (acc uint)) (+ acc (step x)))
+(define-private (keep (x uint)) (> (step x) u0))
+(define-read-only (run-map) (fold + (map step {numbers}) u0))
+(define-read-only (run-fold) (fold add-step {numbers} u0))
+(define-read-only (run-filter) (len (filter keep {numbers})))
+"
+        );
+
+        let (mut store, instance) = instantiate(&mut compile_snippet(&snippet).module, (), |_| {});
+
+        let memory = instance.get_memory(&mut store, "memory").unwrap();
+        assert!(memory.data_size(&store) < 2000 * 1000);
+        let stack_pointer = instance.get_global(&mut store, "stack-pointer").unwrap();
+        let entry_stack_pointer = stack_pointer.get(&mut store).unwrap_i32();
+
+        for (name, expected) in [
+            ("run-map", 2001000),
+            ("run-fold", 2001000),
+            ("run-filter", 1000),
+        ] {
+            let func = instance.get_func(&mut store, name).unwrap();
+            let mut results = [wasmtime::Val::I64(0), wasmtime::Val::I64(0)];
+            func.call(&mut store, &[], &mut results)
+                .unwrap_or_else(|err| panic!("{name} failed: {err}"));
+            assert_eq!(results[0].unwrap_i64(), expected, "{name}");
+            assert_eq!(
+                stack_pointer.get(&mut store).unwrap_i32(),
+                entry_stack_pointer,
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn composite_callback_results_are_moved_out_of_the_frame() {
+        // Each callback concatenates two 1000-byte buffers in its frame and
+        // returns a slice of it inside an optional, or inside a tuple with a
+        // list in a response. The slices are moved to the start of the frame
+        // on return, so the rest of the frame is released.
+        let buffer = "0x".to_owned() + &"ab".repeat(1000);
+        let numbers = format!("(list {})", ["u1"; 1000].join(" "));
+        let snippet = format!(
+            "
+(define-private (label (x uint))
+  (slice? (concat {buffer} {buffer}) x (+ x u2)))
+(define-private (entry (x uint))
+  (let ((part (unwrap-panic (label x))))
+    (if (> x u0) (ok {{tag: part, parts: (list part part)}}) (err x))))
+(define-private (check-label (item (optional (buff 2000))) (acc bool))
+  (and acc (is-eq item (some 0xabab))))
+(define-private (check-entry
+    (item (response {{tag: (buff 2000), parts: (list 2 (buff 2000))}} uint))
+    (acc bool))
+  (and acc (is-eq (unwrap-panic item) {{tag: 0xabab, parts: (list 0xabab 0xabab)}})))
+(define-read-only (run-labels) (fold check-label (map label {numbers}) true))
+(define-read-only (run-entries) (fold check-entry (map entry {numbers}) true))
+"
+        );
+
+        let (mut store, instance) = instantiate(&mut compile_snippet(&snippet).module, (), |_| {});
+
+        let memory = instance.get_memory(&mut store, "memory").unwrap();
+        assert!(memory.data_size(&store) < 2000 * 1000);
+        let stack_pointer = instance.get_global(&mut store, "stack-pointer").unwrap();
+        let entry_stack_pointer = stack_pointer.get(&mut store).unwrap_i32();
+
+        for name in ["run-labels", "run-entries"] {
+            let func = instance.get_func(&mut store, name).unwrap();
+            let mut results = [wasmtime::Val::I32(0)];
+            func.call(&mut store, &[], &mut results)
+                .unwrap_or_else(|err| panic!("{name} failed: {err}"));
+            assert_eq!(results[0].unwrap_i32(), 1, "{name}");
+            assert_eq!(
+                stack_pointer.get(&mut store).unwrap_i32(),
+                entry_stack_pointer,
+                "{name}"
+            );
+        }
+    }
 }
@@ -1,5 +1,7 @@
 use clarity::vm::types::TypeSignature;
 use clarity::vm::{ClarityName, SymbolicExpression};
+use walrus::ir::{BinaryOp, InstrSeqType, UnaryOp};
+use walrus::ValType;
 
 use super::{ComplexWord, SimpleWord};
 use crate::wasm_generator::{ArgumentsExt, GeneratorError, WasmGenerator};
@@ -72,7 +74,7 @@ impl ComplexWord for StxTransfer {
 
         // placeholder for memo
         builder.i32_const(0).i32_const(0);
-        builder.call(generator.func_by_name("stdlib.stx_transfer"));
+        call_stx_transfer(generator, builder);
         Ok(())
     }
 }
@@ -102,11 +104,59 @@ impl ComplexWord for StxTransferMemo {
         generator.traverse_expr(builder, recipient)?;
         generator.traverse_expr(builder, memo)?;
 
-        builder.call(generator.func_by_name("stdlib.stx_transfer"));
+        call_stx_transfer(generator, builder);
         Ok(())
     }
 }
 
+/// Call the `stx_transfer` host function with the amount, sender, recipient
+/// and memo on the stack. A zero amount is rejected with `(err u3)` without
+/// calling the host, as the interpreter checks it before anything else.
+fn call_stx_transfer(generator: &mut WasmGenerator, builder: &mut walrus::InstrSeqBuilder) {
+    let arg_types = [
+        ValType::I64,
+        ValType::I64,
+        ValType::I32,
+        ValType::I32,
+        ValType::I32,
+        ValType::I32,
+        ValType::I32,
+        ValType::I32,
+    ];
+    let args: Vec<_> = arg_types
+        .iter()
+        .map(|ty| generator.module.locals.add(*ty))
+        .collect();
+    for arg in args.iter().rev() {
+        builder.local_set(*arg);
+    }
+
+    let result_ty = InstrSeqType::new(
+        &mut generator.module.types,
+        &[],
+        &[ValType::I32, ValType::I32, ValType::I64, ValType::I64],
+    );
+    let stx_transfer = generator.func_by_name("stdlib.stx_transfer");
+    builder
+        .local_get(args[0])
+        .local_get(args[1])
+        .binop(BinaryOp::I64Or)
+        .unop(UnaryOp::I64Eqz)
+        .if_else(
+            result_ty,
+            |zero| {
+                // (err u3)
+                zero.i32_const(0).i32_const(0).i64_const(3).i64_const(0);
+            },
+            |non_zero| {
+                for arg in &args {
+                    non_zero.local_get(*arg);
+                }
+                non_zero.call(stx_transfer);
+            },
+        );
+}
+
 #[derive(Debug)]
 pub struct StxGetAccount;
 
@@ -129,9 +179,12 @@ impl SimpleWord for StxGetAccount {
 
 #[cfg(test)]
 mod tests {
+    use clarity::vm::events::{STXEventType, StacksTransactionEvent};
     use clarity::vm::Value;
 
-    use crate::tools::{crosscheck, crosscheck_validate, evaluate};
+    use crate::tools::{
+        crosscheck, crosscheck_compare_only, crosscheck_validate, evaluate, TestEnvironment,
+    };
 
     #[test]
     fn stx_get_balance() {
@@ -168,6 +221,26 @@ mod tests {
         )
     }
 
+    #[test]
+    fn stx_account_get_fields() {
+        crosscheck(
+            "
+(define-read-only (account-fields (who principal))
+  (let ((account (stx-account who)))
+    (list (get locked account) (get unlock-height account) (get unlocked account))))
+
+(account-fields 'ST1PQHQKV0RJXZFY1DGX8MNSNYVE3VGZJSRTPGZGM)
+",
+            evaluate("(list u0 u0 u0)"),
+        )
+    }
+
+    #[test]
+    fn stx_account_funded_sender() {
+        // The sender is funded by the test environment.
+        crosscheck_compare_only("(get unlocked (stx-account tx-sender))")
+    }
+
     #[test]
     fn stx_test_burn_ok() {
         crosscheck(
@@ -245,6 +318,23 @@ mod tests {
         )
     }
 
+    #[test]
+    fn stx_transfer_runtime_amount() {
+        // A zero amount is checked before the sender and recipient.
+        crosscheck(
+            "
+(define-private (send (amount uint))
+  (list
+    (stx-transfer? amount tx-sender 'ST1PQHQKV0RJXZFY1DGX8MNSNYVE3VGZJSRTPGZGM)
+    (stx-transfer? amount tx-sender tx-sender)
+    (stx-transfer-memo? amount tx-sender 'ST1PQHQKV0RJXZFY1DGX8MNSNYVE3VGZJSRTPGZGM 0x01)))
+{ zero: (send u0), positive: (send u100) }",
+            evaluate(
+                "{ zero: (list (err u3) (err u3) (err u3)), positive: (list (ok true) (err u2) (ok true)) }",
+            ),
+        )
+    }
+
     #[test]
     fn stx_transfer_err_4() {
         // sender is not tx-sender
@@ -253,4 +343,37 @@ mod tests {
             evaluate("(err u4)"),
         )
     }
+
+    #[test]
+    fn stx_transfer_sender_in_as_contract() {
+        // The contract is funded by tx-sender, then sends from its own
+        // balance inside `as-contract`.
+        const SNIPPET: &str = "
+(list
+  (stx-transfer? u100 tx-sender (as-contract tx-sender))
+  (as-contract (stx-transfer? u40 tx-sender 'ST1PQHQKV0RJXZFY1DGX8MNSNYVE3VGZJSRTPGZGM)))
+";
+        crosscheck(SNIPPET, evaluate("(list (ok true) (ok true))"));
+
+        let mut env = TestEnvironment::default();
+        env.init_contract_with_snippet("snippet", SNIPPET)
+            .expect("Failed to init contract.");
+        let senders: Vec<_> = env
+            .get_events()
+            .iter()
+            .filter_map(|event| match event {
+                StacksTransactionEvent::STXEvent(STXEventType::STXTransferEvent(data)) => {
+                    Some(data.sender.to_string())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            senders,
+            vec![
+                "S1G2081040G2081040G2081040G208105NK8PE5".to_owned(),
+                "S1G2081040G2081040G2081040G208105NK8PE5.snippet".to_owned(),
+            ]
+        );
+    }
 }
@@ -479,7 +479,7 @@ impl ComplexWord for GetOwnerOfNonFungibleToken {
 
 #[cfg(test)]
 mod tests {
-    use crate::tools::crosscheck;
+    use crate::tools::{crosscheck, evaluate};
 
     #[test]
     fn bar_mint_too_many() {
@@ -500,4 +500,37 @@ mod tests {
             Err(()),
         );
     }
+
+    #[test]
+    fn ft_transfer_error_codes() {
+        crosscheck(
+            "
+(define-fungible-token gold)
+(ft-mint? gold u100 tx-sender)
+(list
+  (ft-transfer? gold u1000 tx-sender 'ST1PQHQKV0RJXZFY1DGX8MNSNYVE3VGZJSRTPGZGM)
+  (ft-transfer? gold u10 tx-sender tx-sender)
+  (ft-transfer? gold u0 tx-sender 'ST1PQHQKV0RJXZFY1DGX8MNSNYVE3VGZJSRTPGZGM)
+  (ft-transfer? gold u10 tx-sender 'ST1PQHQKV0RJXZFY1DGX8MNSNYVE3VGZJSRTPGZGM))
+",
+            evaluate("(list (err u1) (err u2) (err u3) (ok true))"),
+        );
+    }
+
+    #[test]
+    fn nft_transfer_error_codes() {
+        crosscheck(
+            "
+(define-non-fungible-token badge uint)
+(nft-mint? badge u1 tx-sender)
+(nft-mint? badge u2 'ST1PQHQKV0RJXZFY1DGX8MNSNYVE3VGZJSRTPGZGM)
+(list
+  (nft-transfer? badge u2 tx-sender 'ST1PQHQKV0RJXZFY1DGX8MNSNYVE3VGZJSRTPGZGM)
+  (nft-transfer? badge u1 tx-sender tx-sender)
+  (nft-transfer? badge u3 tx-sender 'ST1PQHQKV0RJXZFY1DGX8MNSNYVE3VGZJSRTPGZGM)
+  (nft-transfer? badge u1 tx-sender 'ST1PQHQKV0RJXZFY1DGX8MNSNYVE3VGZJSRTPGZGM))
+",
+            evaluate("(list (err u1) (err u2) (err u3) (ok true))"),
+        );
+    }
 }
@@ -110,16 +110,18 @@ impl ComplexWord for TupleGet {
             })?
             .clone();
 
+        // Look up the target field in the tuple layout before emitting any
+        // code. The type-checker should have rejected a missing field.
+        let field_types = tuple_ty.get_type_map();
+        let target_field_ty = field_types.get(target_field_name).ok_or_else(|| {
+            GeneratorError::InternalError(format!("missing field '{target_field_name}' in tuple"))
+        })?;
+
         // Traverse the tuple argument, leaving it on top of the stack.
         generator.traverse_expr(builder, &args[1])?;
 
-        // Determine the wasm types for each field of the tuple
-        let field_types = tuple_ty.get_type_map();
-
         // Create locals for the target field
-        let wasm_types = clar2wasm_ty(field_types.get(target_field_name).ok_or_else(|| {
-            GeneratorError::InternalError(format!("missing field '{target_field_name}' in tuple"))
-        })?);
+        let wasm_types = clar2wasm_ty(target_field_ty);
         let mut val_locals = Vec::with_capacity(wasm_types.len());
         for local_ty in wasm_types.iter().rev() {
             let local = generator.module.locals.add(*local_ty);
@@ -241,9 +243,10 @@ impl ComplexWord for TupleMerge {
 
 #[cfg(test)]
 mod test {
-    use clarity::vm::Value;
+    use clarity::vm::{SymbolicExpressionType, Value};
 
-    use crate::tools::crosscheck;
+    use crate::tools::{crosscheck, for_each_expr_mut, generation_error};
+    use crate::wasm_generator::GeneratorError;
 
     #[test]
     fn test_get_optional() {
@@ -261,4 +264,25 @@ mod test {
             Ok(Some(Value::some(Value::Int(3)).unwrap())),
         );
     }
+
+    #[test]
+    fn get_missing_field_is_an_error() {
+        let err = generation_error("(get a {a: 1})", |analysis| {
+            // Ask for a field that the tuple does not have.
+            for_each_expr_mut(&mut analysis.expressions, &mut |expr| {
+                if let SymbolicExpressionType::List(call) = &mut expr.expr {
+                    if call[0]
+                        .match_atom()
+                        .map_or(false, |name| name.as_str() == "get")
+                    {
+                        call[1].expr = SymbolicExpressionType::Atom("b".into());
+                    }
+                }
+            })
+        });
+        assert!(
+            matches!(&err, GeneratorError::InternalError(msg) if msg == "missing field 'b' in tuple"),
+            "unexpected error: {err:?}"
+        );
+    }
 }
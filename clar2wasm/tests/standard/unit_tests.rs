@@ -1692,7 +1692,7 @@ fn test_ge_int() {
     assert_eq!(result[0].i32(), Some(1));
 }
 
-fn test_cmp_buff(func_name: &str, reference_func: impl Fn(&[u8], &[u8]) -> bool) {
+fn test_cmp_buff<R: Into<i32>>(func_name: &str, reference_func: impl Fn(&[u8], &[u8]) -> R) {
     let (instance, mut store) = load_stdlib().unwrap();
     let memory = instance
         .get_memory(&mut store, "memory")
@@ -1721,11 +1721,11 @@ fn test_cmp_buff(func_name: &str, reference_func: impl Fn(&[u8], &[u8]) -> bool)
             ],
             &mut result,
         )
-        .expect("call to lt-buff failed");
+        .unwrap_or_else(|_| panic!("call to {func_name} failed"));
 
         assert_eq!(
             result[0].unwrap_i32(),
-            reference_func(buff_a, buff_b) as i32
+            reference_func(buff_a, buff_b).into()
         )
     };
 
@@ -1742,6 +1742,16 @@ fn test_cmp_buff(func_name: &str, reference_func: impl Fn(&[u8], &[u8]) -> bool)
     // test different end
     test_cmp(&[1, 2, 3], &[1, 2, 4]);
     test_cmp(&[1, 2, 3], &[1, 2, 2]);
+
+    // test different bytes before the end of the shorter buffer
+    test_cmp(&[2], &[1, 5]);
+    test_cmp(&[1, 5], &[2]);
+    test_cmp(&[0xff, 0], &[0x01, 0xff, 0xff]);
+}
+
+#[test]
+fn test_cmp_buff_ordering() {
+    test_cmp_buff("stdlib.cmp-buff", |a, b| a.cmp(b) as i32)
 }
 
 #[test]
@@ -4006,3 +4016,26 @@ fn sha512_int() {
         Vec::from_hex("83b7d9d929320aa6a6898e4ce1dc11db78a8e4f01e47c379b49b18e3c0c8bfb98af99a758f44d4f4ee845205a4c90d6016e01d470ff95a19f1f1b37284c5afa6").unwrap();
     assert_eq!(&buffer, &expected_result);
 }
+
+#[test]
+fn test_self_test() {
+    let (instance, mut store) = load_stdlib().unwrap();
+    let self_test = instance.get_func(&mut store, ".self-test").unwrap();
+    let mut result = [Val::I32(-1)];
+
+    // The self-test uses the memory above the stack pointer, which must not
+    // overlap the data of the standard library.
+    let stack_pointer = instance.get_global(&mut store, "stack-pointer").unwrap();
+    stack_pointer
+        .set(&mut store, Val::I32(END_OF_STANDARD_DATA as i32))
+        .unwrap();
+
+    self_test
+        .call(&mut store, &[], &mut result)
+        .expect("call to .self-test failed");
+    assert_eq!(result[0].unwrap_i32(), 0, "a standard library check failed");
+    assert_eq!(
+        stack_pointer.get(&mut store).unwrap_i32(),
+        END_OF_STANDARD_DATA as i32
+    );
+}
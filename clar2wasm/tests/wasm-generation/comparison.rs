@@ -1,5 +1,7 @@
 use clar2wasm::tools::crosscheck_compare_only;
 use clarity::vm::types::{SequenceSubtype, StringSubtype, TypeSignature};
+use proptest::collection::vec;
+use proptest::prelude::any;
 use proptest::strategy::{Just, Strategy};
 use proptest::{prop_oneof, proptest};
 
@@ -20,6 +22,13 @@ fn strategies_for_comparison() -> impl Strategy<Value = TypeSignature> {
     ]
 }
 
+fn buff_literal(bytes: &[u8]) -> String {
+    format!(
+        "0x{}",
+        bytes.iter().map(|b| format!("{b:02x}")).collect::<String>()
+    )
+}
+
 proptest! {
     #![proptest_config(super::runtime_config())]
 
@@ -34,4 +43,28 @@ proptest! {
             )
         }
     }
+
+    #[test]
+    fn crossprop_comparison_buff_prefix(
+        prefix in vec(any::<u8>(), 0..64),
+        suffix in vec(any::<u8>(), 1..64))
+    {
+        let short = buff_literal(&prefix);
+        let long = buff_literal(&[prefix, suffix].concat());
+        for func in &COMPARISONS_FUNC {
+            crosscheck_compare_only(&format!("({func} {short} {long})"));
+            crosscheck_compare_only(&format!("({func} {long} {short})"));
+        }
+    }
+
+    #[test]
+    fn crossprop_comparison_string_prefix(
+        prefix in "[a-zA-Z0-9 ]{0,32}",
+        suffix in "[a-zA-Z0-9 ]{1,32}")
+    {
+        for func in &COMPARISONS_FUNC {
+            crosscheck_compare_only(&format!("({func} \"{prefix}\" \"{prefix}{suffix}\")"));
+            crosscheck_compare_only(&format!("({func} \"{prefix}{suffix}\" \"{prefix}\")"));
+        }
+    }
 }
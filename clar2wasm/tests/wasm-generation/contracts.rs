@@ -0,0 +1,88 @@
+use clar2wasm::compile;
+use clarity::types::StacksEpochId;
+use clarity::vm::costs::LimitedCostTracker;
+use clarity::vm::database::MemoryBackingStore;
+use clarity::vm::types::{QualifiedContractIdentifier, TypeSignature};
+use clarity::vm::ClarityVersion;
+use proptest::prelude::*;
+
+use crate::{prop_signature, type_string, PropValue};
+
+/// Contracts which must compile, or fail to compile, without panicking.
+const CORPUS: &[&str] = &[
+    "",
+    "(define-constant c0 (list))",
+    "(define-data-var v0 (optional (list 2 int)) none) (var-set v0 (some (list 1)))",
+    "(define-map m0 {a: int} (response uint (buff 2))) (map-insert m0 {a: 1} (err 0x01))",
+    "(define-private (echo0 (x (string-utf8 3))) x) (echo0 u\"a\")",
+    "(print (list (ok 1) (err u2)))",
+    "(define-private (label0 (n uint)) (some (concat \"n\" \"m\"))) (map label0 (list u1 u2))",
+    "(define-private (inner0 (x int)) (if true (ok (list x)) (err u1))) (define-public (outer0) (inner0 1)) (outer0)",
+];
+
+/// Compile `contract`. The compiler may reject the contract, but it must
+/// not panic.
+fn compile_contract(contract: &str) {
+    let mut datastore = MemoryBackingStore::new();
+    let _ = compile(
+        contract,
+        &QualifiedContractIdentifier::transient(),
+        LimitedCostTracker::new_free(),
+        ClarityVersion::latest(),
+        StacksEpochId::latest(),
+        &mut datastore.as_analysis_db(),
+    );
+}
+
+/// A top-level definition, with the expressions using it, as a kind of
+/// definition, a type and three values of this type.
+fn definition() -> impl Strategy<Value = (u8, TypeSignature, Vec<PropValue>)> {
+    prop_signature()
+        .prop_flat_map(|ty| (0u8..6, Just(ty.clone()), PropValue::many_from_type(ty, 3)))
+}
+
+/// Render the definition number `index` of a contract.
+fn render_definition(index: usize, kind: u8, ty: &TypeSignature, values: &[PropValue]) -> String {
+    let ty = type_string(ty);
+    let (a, b, c) = (&values[0], &values[1], &values[2]);
+    match kind {
+        0 => format!("(define-constant c{index} {a}) (is-eq c{index} {b})"),
+        1 => format!(
+            "(define-data-var v{index} {ty} {a}) (var-set v{index} {b}) \
+             (define-read-only (get-v{index}) (var-get v{index}))"
+        ),
+        2 => format!(
+            "(define-map m{index} {ty} {ty}) (map-insert m{index} {a} {b}) (map-get? m{index} {c})"
+        ),
+        3 => format!("(define-private (echo{index} (x {ty})) x) (echo{index} {a})"),
+        4 => format!(
+            "(define-public (pair{index} (x {ty})) (if true (ok (list x {b})) (err u1))) \
+             (pair{index} {a})"
+        ),
+        _ => format!("(print (list {a} {b} {c}))"),
+    }
+}
+
+#[test]
+fn corpus_compiles_without_panicking() {
+    for contract in CORPUS {
+        compile_contract(contract);
+    }
+}
+
+proptest! {
+    #![proptest_config(super::runtime_config())]
+
+    #[test]
+    fn random_contract_compiles_without_panicking(
+        definitions in prop::collection::vec(definition(), 1..6)
+    ) {
+        let contract = definitions
+            .iter()
+            .enumerate()
+            .map(|(index, (kind, ty, values))| render_definition(index, *kind, ty, values))
+            .collect::<Vec<_>>()
+            .join("\n");
+        compile_contract(&contract);
+    }
+}
@@ -1,7 +1,7 @@
-use clar2wasm::tools::crosscheck;
-use proptest::proptest;
+use clar2wasm::tools::{crosscheck, crosscheck_compare_only};
+use proptest::prelude::*;
 
-use crate::PropValue;
+use crate::{prop_signature, PropValue};
 
 proptest! {
     #![proptest_config(super::runtime_config())]
@@ -38,3 +38,30 @@ proptest! {
         );
     }
 }
+
+proptest! {
+    #![proptest_config(super::runtime_config())]
+
+    #[test]
+    fn is_eq_many_values_of_a_type(
+        values in prop_signature().prop_flat_map(|ty| {
+            (3usize..=5).prop_flat_map(move |n| PropValue::many_from_type(ty.clone(), n))
+        })
+    ) {
+        let operands = values.iter().map(|val| val.to_string()).collect::<Vec<_>>().join(" ");
+        crosscheck_compare_only(&format!("(is-eq {operands})"));
+    }
+}
+
+proptest! {
+    #![proptest_config(super::runtime_config())]
+
+    #[test]
+    fn is_eq_value_with_itself_many_times(val in PropValue::any(), count in 3usize..=5) {
+        let operands = vec![val.to_string(); count].join(" ");
+        crosscheck(
+            &format!("(is-eq {operands})"),
+            Ok(Some(clarity::vm::Value::Bool(true)))
+        );
+    }
+}
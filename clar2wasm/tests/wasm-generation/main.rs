@@ -5,6 +5,7 @@ pub mod bitwise;
 pub mod blockinfo;
 pub mod comparison;
 pub mod conditionals;
+pub mod contracts;
 pub mod default_to;
 pub mod equal;
 pub mod optional;
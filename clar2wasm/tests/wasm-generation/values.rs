@@ -1,4 +1,4 @@
-use clar2wasm::tools::{crosscheck, TestEnvironment};
+use clar2wasm::tools::{crosscheck, crosscheck_compare_only, TestEnvironment};
 use clarity::vm::Value;
 use proptest::prelude::*;
 
@@ -64,3 +64,25 @@ proptest! {
         )
     }
 }
+
+proptest! {
+    #![proptest_config(super::runtime_config())]
+
+    // Map values and list elements are written to memory with the size the
+    // host expects for their type.
+    #[test]
+    fn values_written_to_memory_round_trip(
+        (ty, values) in prop_signature()
+            .prop_flat_map(|ty| (Just(ty.clone()), PropValue::many_from_type(ty, 3)))
+    ) {
+        let (a, b, c) = (&values[0], &values[1], &values[2]);
+        crosscheck(
+            &format!(
+                "(define-map m uint {}) (map-insert m u0 {a}) (map-get? m u0)",
+                type_string(&ty)
+            ),
+            Ok(Some(Value::some(a.clone().into()).unwrap()))
+        );
+        crosscheck_compare_only(&format!("(list {a} {b} {c})"));
+    }
+}